@@ -0,0 +1,397 @@
+//! The marginal effect of each feature flag, and of release mode, on the benchmarks
+//!
+//! Every variant with a flag on is paired with the variant which only differs by having it off.
+//! The effect of the flag on a measure is the mean difference across those pairs, along with the
+//! mean, smallest and largest difference as a percentage of the variant without it.
+
+use anyhow::{anyhow, Result};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use std::io::{self, Write};
+
+/// The measures the chart shows by default, with the labels they're shown under
+pub const MEASURES: &[(&str, &str)] = &[
+    ("code_size", "Size diff (KiB)"),
+    ("compute_time", "Time diff (ms)"),
+    ("data_sent", "Data sent diff (bytes)"),
+];
+
+const TITLE: &str = "Effect of features on code size, compute time and data sent";
+
+/// matplotlib's first few colours, which the chart used to be drawn with
+const COLOURS: &[RGBColor] = &[
+    RGBColor(0x1f, 0x77, 0xb4),
+    RGBColor(0xff, 0x7f, 0x0e),
+    RGBColor(0x2c, 0xa0, 0x2c),
+    RGBColor(0xd6, 0x27, 0x28),
+    RGBColor(0x94, 0x67, 0xbd),
+];
+
+const BACKGROUND: RGBColor = RGBColor(0xf3, 0xf3, 0xf3);
+
+/// One build of the server and what it measured
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// Whether each of the dataset's flags was on
+    pub flags: Vec<bool>,
+    /// Each of the dataset's measures, if it was taken for this variant
+    pub values: Vec<Option<f64>>,
+}
+
+/// The rows of `benchmark_data.csv`
+///
+/// Columns holding nothing but `True` and `False` are flags, the rest are measures and may be
+/// left empty for variants which weren't measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dataset {
+    pub flags: Vec<String>,
+    pub measures: Vec<String>,
+    pub variants: Vec<Variant>,
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "True" | "true" => Some(true),
+        "False" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+impl Dataset {
+    pub fn read(reader: impl io::Read) -> Result<Dataset> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+
+        let is_flag: Vec<bool> = (0..headers.len())
+            .map(|column| {
+                records
+                    .iter()
+                    .all(|record| parse_flag(&record[column]).is_some())
+                    && !records.is_empty()
+            })
+            .collect();
+        let names = |flag: bool| {
+            headers
+                .iter()
+                .zip(&is_flag)
+                .filter(|(_, &is)| is == flag)
+                .map(|(name, _)| name.to_string())
+                .collect()
+        };
+
+        let mut variants = Vec::with_capacity(records.len());
+        for (line, record) in records.iter().enumerate() {
+            let mut variant = Variant {
+                flags: Vec::new(),
+                values: Vec::new(),
+            };
+            for (column, value) in record.iter().enumerate() {
+                if is_flag[column] {
+                    variant.flags.push(parse_flag(value).unwrap());
+                } else if value.is_empty() {
+                    variant.values.push(None);
+                } else {
+                    let value = value.parse().map_err(|_| {
+                        anyhow!(
+                            "Row {} has {value:?} for {}, which should be a number",
+                            line + 1,
+                            &headers[column]
+                        )
+                    })?;
+                    variant.values.push(Some(value));
+                }
+            }
+            variants.push(variant);
+        }
+
+        Ok(Dataset {
+            flags: names(true),
+            measures: names(false),
+            variants,
+        })
+    }
+
+    fn flag(&self, name: &str) -> Result<usize> {
+        self.flags
+            .iter()
+            .position(|flag| flag == name)
+            .ok_or_else(|| anyhow!("There is no {name} flag in the benchmark data"))
+    }
+
+    fn measure(&self, name: &str) -> Result<usize> {
+        self.measures
+            .iter()
+            .position(|measure| measure == name)
+            .ok_or_else(|| anyhow!("There is no {name} measure in the benchmark data"))
+    }
+
+    /// The effect of turning `flag` on on `measure`, `None` if no pair of variants measured it
+    pub fn effect(&self, flag: &str, measure: &str) -> Result<Option<Effect>> {
+        let flag = self.flag(flag)?;
+        let measure = self.measure(measure)?;
+
+        let mut diffs = Vec::new();
+        let mut percentages = Vec::new();
+        for on in self.variants.iter().filter(|variant| variant.flags[flag]) {
+            let mut flags = on.flags.clone();
+            flags[flag] = false;
+            let Some(off) = self.variants.iter().find(|variant| variant.flags == flags) else {
+                continue;
+            };
+            let (Some(with), Some(without)) = (on.values[measure], off.values[measure]) else {
+                continue;
+            };
+            diffs.push(with - without);
+            if without != 0.0 {
+                percentages.push(100.0 * (with - without) / without);
+            }
+        }
+
+        if diffs.is_empty() {
+            return Ok(None);
+        }
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+        let percent = (!percentages.is_empty()).then(|| Percent {
+            mean: mean(&percentages),
+            min: percentages.iter().copied().fold(f64::INFINITY, f64::min),
+            max: percentages
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max),
+        });
+        Ok(Some(Effect {
+            mean: mean(&diffs),
+            percent,
+            pairs: diffs.len(),
+        }))
+    }
+}
+
+/// What turning a flag on does to a measure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Effect {
+    /// Mean difference, in the measure's own units
+    pub mean: f64,
+    /// The differences relative to the variants without the flag, unless those measured zero
+    pub percent: Option<Percent>,
+    /// Pairs of variants the effect was worked out from
+    pub pairs: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percent {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The effect of every flag on each of a set of measures
+pub struct Report {
+    pub flags: Vec<String>,
+    /// The measures' columns and the labels they're shown under
+    pub measures: Vec<(String, String)>,
+    /// For each flag, the effect on each measure
+    pub effects: Vec<Vec<Option<Effect>>>,
+}
+
+impl Report {
+    pub fn new(dataset: &Dataset, measures: &[(String, String)]) -> Result<Report> {
+        let effects = dataset
+            .flags
+            .iter()
+            .map(|flag| {
+                measures
+                    .iter()
+                    .map(|(measure, _)| dataset.effect(flag, measure))
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+        Ok(Report {
+            flags: dataset.flags.clone(),
+            measures: measures.to_vec(),
+            effects,
+        })
+    }
+
+    /// Write the mean differences, then the percentages, as Markdown tables
+    pub fn write_markdown(&self, mut out: impl Write) -> io::Result<()> {
+        let header = |out: &mut dyn Write| -> io::Result<()> {
+            write!(out, "| feature |")?;
+            for (_, label) in &self.measures {
+                write!(out, " {label} |")?;
+            }
+            writeln!(out)?;
+            writeln!(out, "| :-- |{}", " --: |".repeat(self.measures.len()))
+        };
+
+        writeln!(out, "Mean difference with the feature on\n")?;
+        header(&mut out)?;
+        for (flag, effects) in self.flags.iter().zip(&self.effects) {
+            write!(out, "| {} |", flag_label(flag))?;
+            for effect in effects {
+                match effect {
+                    Some(effect) => write!(out, " {} |", number(effect.mean))?,
+                    None => write!(out, " - |")?,
+                }
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(
+            out,
+            "\nMean percentage difference, with the range across variants\n"
+        )?;
+        header(&mut out)?;
+        for (flag, effects) in self.flags.iter().zip(&self.effects) {
+            write!(out, "| {} |", flag_label(flag))?;
+            for percent in effects.iter().map(|effect| effect.and_then(|e| e.percent)) {
+                match percent {
+                    Some(p) => {
+                        write!(out, " {:+.2}% ({:+.2}% to {:+.2}%) |", p.mean, p.min, p.max)?
+                    }
+                    None => write!(out, " - |")?,
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Write a row for every flag and measure
+    pub fn write_csv(&self, out: impl Write) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record([
+            "feature",
+            "measure",
+            "mean",
+            "mean_percent",
+            "min_percent",
+            "max_percent",
+            "pairs",
+        ])?;
+        for (flag, effects) in self.flags.iter().zip(&self.effects) {
+            for ((measure, _), effect) in self.measures.iter().zip(effects) {
+                let Some(effect) = effect else {
+                    continue;
+                };
+                let percent = |f: fn(&Percent) -> f64| {
+                    effect
+                        .percent
+                        .as_ref()
+                        .map(|p| f(p).to_string())
+                        .unwrap_or_default()
+                };
+                writer.write_record([
+                    flag.clone(),
+                    measure.clone(),
+                    effect.mean.to_string(),
+                    percent(|p| p.mean),
+                    percent(|p| p.min),
+                    percent(|p| p.max),
+                    effect.pairs.to_string(),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Draw the mean differences as a bar for each measure, grouped by flag
+    pub fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+    ) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+        let means = || self.effects.iter().flatten().flatten().map(|e| e.mean);
+        let top = means().fold(0.0, f64::max);
+        let bottom = means().fold(0.0, f64::min);
+        // leave room for the labels above and below the bars
+        let pad = (top - bottom).max(1.0) * 0.1;
+
+        let groups = self.flags.len();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption(TITLE, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(-0.5..groups as f64 - 0.5, (bottom - pad)..(top + pad))?;
+        chart.plotting_area().fill(&BACKGROUND)?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            // the groups are centred on whole numbers, only those get a label
+            .x_labels(groups)
+            .x_label_formatter(&|x| match self.flags.get(x.round() as usize) {
+                Some(flag) if (x - x.round()).abs() < 1e-9 => flag_label(flag),
+                _ => String::new(),
+            })
+            .y_desc("Mean difference")
+            .draw()?;
+
+        let width = 0.8 / self.measures.len() as f64;
+        for (m, (_, label)) in self.measures.iter().enumerate() {
+            let colour = COLOURS[m % COLOURS.len()];
+            let bars: Vec<(f64, f64)> = self
+                .effects
+                .iter()
+                .enumerate()
+                .filter_map(|(g, effects)| {
+                    let left = g as f64 - 0.4 + m as f64 * width;
+                    effects[m].map(|effect| (left, effect.mean))
+                })
+                .collect();
+
+            chart
+                .draw_series(bars.iter().map(|&(left, mean)| {
+                    Rectangle::new([(left, 0.0), (left + width, mean)], colour.filled())
+                }))?
+                .label(label)
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 10, y + 5)], colour.filled())
+                });
+
+            chart.draw_series(bars.iter().map(|&(left, mean)| {
+                let (vertical, offset) = if mean < 0.0 {
+                    (VPos::Top, -pad / 5.0)
+                } else {
+                    (VPos::Bottom, pad / 5.0)
+                };
+                let style = ("sans-serif", 12)
+                    .into_font()
+                    .color(&BLACK)
+                    .pos(Pos::new(HPos::Center, vertical));
+                Text::new(number(mean), (left + width / 2.0, mean + offset), style)
+            }))?;
+        }
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK.mix(0.3))
+            .draw()?;
+        root.present()
+    }
+}
+
+/// How a flag is named in the tables and on the chart
+pub fn flag_label(flag: &str) -> String {
+    match flag {
+        "release" => "release mode".into(),
+        _ => flag.into(),
+    }
+}
+
+/// At most three decimal places, without trailing zeros
+fn number(value: f64) -> String {
+    let formatted = format!("{value:.3}");
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    match trimmed {
+        "-0" => "0".into(),
+        _ => trimmed.into(),
+    }
+}
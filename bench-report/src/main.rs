@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Context, Result};
+use bench_report::{Dataset, Report, MEASURES};
+use clap::{Parser, ValueEnum};
+use plotters::prelude::*;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The benchmark results, one row per variant of the server
+    #[arg(long, default_value = "benchmark_data.csv")]
+    data: PathBuf,
+
+    /// Directory to write feature_effects.png and feature_effects.svg to
+    #[arg(long, default_value = "assets")]
+    out: PathBuf,
+
+    /// Columns to report on rather than code size, compute time and data sent
+    #[arg(long, value_delimiter = ',')]
+    measures: Vec<String>,
+
+    /// How to print the tables
+    #[arg(long, value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+
+    /// Only print the tables, without drawing the chart
+    #[arg(long)]
+    no_chart: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Markdown,
+}
+
+const SIZE: (u32, u32) = (800, 600);
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let data = File::open(&args.data)
+        .with_context(|| format!("Failed to open {}", args.data.display()))?;
+    let dataset = Dataset::read(data)?;
+
+    let measures: Vec<(String, String)> = if args.measures.is_empty() {
+        MEASURES
+            .iter()
+            .map(|(column, label)| (column.to_string(), label.to_string()))
+            .collect()
+    } else {
+        args.measures
+            .iter()
+            .map(|column| (column.clone(), column.clone()))
+            .collect()
+    };
+    let report = Report::new(&dataset, &measures)?;
+
+    match args.format {
+        Format::Csv => report.write_csv(io::stdout().lock())?,
+        Format::Markdown => report.write_markdown(io::stdout().lock())?,
+    }
+
+    if !args.no_chart {
+        fs::create_dir_all(&args.out)?;
+        let png = args.out.join("feature_effects.png");
+        report
+            .draw(&BitMapBackend::new(&png, SIZE).into_drawing_area())
+            .map_err(|e| anyhow!("Failed to draw {} - {e}", png.display()))?;
+        let svg = args.out.join("feature_effects.svg");
+        report
+            .draw(&SVGBackend::new(&svg, SIZE).into_drawing_area())
+            .map_err(|e| anyhow!("Failed to draw {} - {e}", svg.display()))?;
+        eprintln!("Drew {} and {}", png.display(), svg.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use bench_report::{Dataset, Report, MEASURES};
+use plotters::prelude::*;
+use std::fs::File;
+
+const DATA: &str = "\
+release,fast,code_size,compute_time
+False,False,10,100
+False,True,12,50
+True,False,20,80
+True,True,,40
+";
+
+fn dataset() -> Dataset {
+    Dataset::read(DATA.as_bytes()).unwrap()
+}
+
+/// The repository's own benchmark results
+fn benchmarks() -> Dataset {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../benchmark_data.csv");
+    Dataset::read(File::open(path).unwrap()).unwrap()
+}
+
+fn measures() -> Vec<(String, String)> {
+    MEASURES
+        .iter()
+        .map(|(column, label)| (column.to_string(), label.to_string()))
+        .collect()
+}
+
+#[test]
+fn flags_and_measures_are_told_apart() {
+    let dataset = dataset();
+    assert_eq!(dataset.flags, ["release", "fast"]);
+    assert_eq!(dataset.measures, ["code_size", "compute_time"]);
+    assert_eq!(dataset.variants[3].values, [None, Some(40.0)]);
+}
+
+#[test]
+fn variants_are_compared_with_their_pair() {
+    let dataset = dataset();
+    let time = dataset.effect("fast", "compute_time").unwrap().unwrap();
+    assert_eq!(time.mean, -45.0);
+    assert_eq!(time.pairs, 2);
+    let percent = time.percent.unwrap();
+    assert_eq!((percent.min, percent.max), (-50.0, -50.0));
+
+    // one of the pairs is missing its code size
+    let size = dataset.effect("fast", "code_size").unwrap().unwrap();
+    assert_eq!((size.mean, size.pairs), (2.0, 1));
+}
+
+#[test]
+fn unknown_columns_are_an_error() {
+    assert!(dataset().effect("slow", "compute_time").is_err());
+    assert!(dataset().effect("fast", "stack_peak").is_err());
+}
+
+#[test]
+fn measures_nobody_took_have_no_effect() {
+    let data = "release,code_size,stack_peak\nFalse,10,\nTrue,20,\n";
+    let dataset = Dataset::read(data.as_bytes()).unwrap();
+    assert_eq!(dataset.measures, ["code_size", "stack_peak"]);
+    assert_eq!(dataset.effect("release", "stack_peak").unwrap(), None);
+}
+
+#[test]
+fn the_benchmarks_match_the_notebook() {
+    let dataset = benchmarks();
+    let effect = |flag, measure| dataset.effect(flag, measure).unwrap().unwrap().mean;
+    assert_eq!(effect("release", "code_size"), 52.5);
+    assert_eq!(effect("release", "compute_time"), -18.8125);
+    assert_eq!(effect("implicit", "data_sent"), -136.0);
+    assert_eq!(effect("static_ssid", "code_size"), -0.875);
+}
+
+#[test]
+fn tables_have_a_row_per_flag() {
+    let report = Report::new(&benchmarks(), &measures()).unwrap();
+    let mut out = Vec::new();
+    report.write_markdown(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("| release mode | 52.5 | -18.812 | 0 |"));
+
+    let mut out = Vec::new();
+    report.write_csv(&mut out).unwrap();
+    // a header and a row for each of the five flags and three measures
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1 + 5 * 3);
+}
+
+#[test]
+fn the_chart_labels_every_flag() {
+    let report = Report::new(&benchmarks(), &measures()).unwrap();
+    let mut svg = String::new();
+    report
+        .draw(&SVGBackend::with_string(&mut svg, (800, 600)).into_drawing_area())
+        .unwrap();
+    for flag in [
+        "release mode",
+        "partial",
+        "implicit",
+        "strong",
+        "static_ssid",
+    ] {
+        assert!(svg.contains(flag), "{flag} isn't on the chart");
+    }
+}
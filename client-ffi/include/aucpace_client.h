@@ -0,0 +1,219 @@
+#ifndef AUCPACE_CLIENT_H
+#define AUCPACE_CLIENT_H
+
+/* Generated by cbindgen from client-ffi/src/lib.rs, regenerate it rather than editing it */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Length of each of the session's keys
+ */
+#define AUCPACE_KEY_LEN 32
+
+/**
+ * What a call into the library came to
+ */
+typedef enum AucpaceStatus {
+  AUCPACE_STATUS_OK = 0,
+  /**
+   * A pointer was null, a string wasn't UTF-8 or the scrypt parameters were unusable
+   */
+  AUCPACE_STATUS_INVALID_ARGUMENT,
+  /**
+   * A callback failed, or the serial port couldn't be opened, read or written
+   */
+  AUCPACE_STATUS_IO,
+  /**
+   * Nothing arrived from the server for too long
+   */
+  AUCPACE_STATUS_TIMED_OUT,
+  /**
+   * The server was built with a different protocol version or different features
+   */
+  AUCPACE_STATUS_PREAMBLE_MISMATCH,
+  /**
+   * The server's answer to the preamble didn't parse, it is probably running an older protocol
+   */
+  AUCPACE_STATUS_UNKNOWN_SERVER,
+  /**
+   * The server sent a message which doesn't belong at this point of the exchange
+   */
+  AUCPACE_STATUS_UNEXPECTED_MESSAGE,
+  /**
+   * AuCPace rejected what the server sent, or couldn't hash the password
+   */
+  AUCPACE_STATUS_PROTOCOL,
+  /**
+   * The server asked for password hashing parameters which can't be used
+   */
+  AUCPACE_STATUS_PARAMS,
+  /**
+   * The server turned the client away after too many failed attempts
+   */
+  AUCPACE_STATUS_THROTTLED,
+  /**
+   * One side failed to prove it knows the password
+   */
+  AUCPACE_STATUS_AUTH_FAILED,
+  /**
+   * Something the server sent under the session's keys failed its integrity check
+   */
+  AUCPACE_STATUS_INTEGRITY,
+  /**
+   * A message couldn't be encoded to send
+   */
+  AUCPACE_STATUS_ENCODE,
+  /**
+   * The library panicked, which is a bug
+   */
+  AUCPACE_STATUS_INTERNAL,
+} AucpaceStatus;
+
+/**
+ * Reads up to `len` bytes into `buf`
+ *
+ * Returns the number of bytes read, 0 if nothing arrived before the transport's timeout, or a
+ * negative number if reading failed.
+ */
+typedef ptrdiff_t (*AucpaceReadFn)(void *ctx, uint8_t *buf, size_t len);
+
+/**
+ * Writes up to `len` bytes from `buf`
+ *
+ * Returns the number of bytes written, or a negative number if writing failed.
+ */
+typedef ptrdiff_t (*AucpaceWriteFn)(void *ctx, const uint8_t *buf, size_t len);
+
+/**
+ * A link to the server made of the caller's callbacks, which are passed `ctx` on every call
+ */
+typedef struct AucpaceTransport {
+  void *ctx;
+  AucpaceReadFn read;
+  AucpaceWriteFn write;
+} AucpaceTransport;
+
+/**
+ * Parameters to hash the password with when registering, the server hands them back later
+ */
+typedef struct AucpaceScryptParams {
+  uint8_t log_n;
+  uint32_t r;
+  uint32_t p;
+} AucpaceScryptParams;
+
+/**
+ * A session the client and server both saw through to the end
+ */
+typedef struct AucpaceSession {
+  /**
+   * Key for messages to the server, derived from the exchange
+   */
+  uint8_t client_to_server[AUCPACE_KEY_LEN];
+  /**
+   * Key for messages from the server
+   */
+  uint8_t server_to_client[AUCPACE_KEY_LEN];
+  /**
+   * Six digit code for an operator to compare against the server's
+   */
+  uint32_t sas;
+  /**
+   * Whether the session was resumed with a ticket rather than a full AuCPace exchange
+   */
+  bool resumed;
+  /**
+   * Bytes sent to the server after the preamble
+   */
+  size_t bytes_sent;
+  /**
+   * Time spent computing rather than waiting for the server
+   */
+  uint64_t compute_micros;
+  /**
+   * How long the server asked to be left alone for, only set along with `Status::Throttled`
+   */
+  uint32_t retry_after_ms;
+} AucpaceSession;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Register `user` with the server over the caller's callbacks
+ *
+ * `params` may be null to use scrypt's recommended parameters.
+ *
+ * # Safety
+ *
+ * `transport` must point to a `Transport` whose callbacks can be called with its `ctx`, `user`
+ * and `password` must be NUL terminated strings and `params` must be null or point to a
+ * `ScryptParams`.
+ */
+enum AucpaceStatus aucpace_register(const struct AucpaceTransport *transport,
+                                    const char *user,
+                                    const char *password,
+                                    const struct AucpaceScryptParams *params);
+
+/**
+ * Run a session as `user` over the caller's callbacks, filling in `session` if it succeeds
+ *
+ * # Safety
+ *
+ * `transport` must point to a `Transport` whose callbacks can be called with its `ctx`, `user`
+ * and `password` must be NUL terminated strings and `session` must point to a `Session`.
+ */
+enum AucpaceStatus aucpace_login(const struct AucpaceTransport *transport,
+                                 const char *user,
+                                 const char *password,
+                                 struct AucpaceSession *session);
+
+/**
+ * Register `user` with the server on the serial port at `path`
+ *
+ * The port is opened 8N1 at `baudrate`, or at the server's default rate if it is 0. `params` may
+ * be null to use scrypt's recommended parameters.
+ *
+ * # Safety
+ *
+ * `path`, `user` and `password` must be NUL terminated strings and `params` must be null or
+ * point to a `ScryptParams`.
+ */
+enum AucpaceStatus aucpace_register_serial(const char *path,
+                                           uint32_t baudrate,
+                                           const char *user,
+                                           const char *password,
+                                           const struct AucpaceScryptParams *params);
+
+/**
+ * Run a session as `user` with the server on the serial port at `path`, filling in `session` if
+ * it succeeds
+ *
+ * The port is opened 8N1 at `baudrate`, or at the server's default rate if it is 0.
+ *
+ * # Safety
+ *
+ * `path`, `user` and `password` must be NUL terminated strings and `session` must point to a
+ * `Session`.
+ */
+enum AucpaceStatus aucpace_login_serial(const char *path,
+                                        uint32_t baudrate,
+                                        const char *user,
+                                        const char *password,
+                                        struct AucpaceSession *session);
+
+/**
+ * A NUL terminated description of `status`, which lives as long as the program
+ */
+const char *aucpace_status_str(enum AucpaceStatus status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AUCPACE_CLIENT_H */
@@ -0,0 +1,397 @@
+//! C ABI for the client, so test rigs written in other languages can authenticate to a board
+//!
+//! Everything here is declared in `include/aucpace_client.h`, which is generated with
+//! `cbindgen --config cbindgen.toml --output include/aucpace_client.h` and has to be regenerated
+//! after changing the API. The library has to be built with the same features as the server.
+use client::{Error, Options};
+use protocol::key_schedule::SUBKEY_LEN;
+use protocol::link::LinkConfig;
+use protocol::sas::ShortAuthString;
+use scrypt::Params;
+use std::ffi::{c_char, c_void, CStr};
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Length of each of the session's keys
+pub const KEY_LEN: usize = 32;
+
+const _: () = assert!(KEY_LEN == SUBKEY_LEN);
+
+/// What a call into the library came to
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    /// A pointer was null, a string wasn't UTF-8 or the scrypt parameters were unusable
+    InvalidArgument,
+    /// A callback failed, or the serial port couldn't be opened, read or written
+    Io,
+    /// Nothing arrived from the server for too long
+    TimedOut,
+    /// The server was built with a different protocol version or different features
+    PreambleMismatch,
+    /// The server's answer to the preamble didn't parse, it is probably running an older protocol
+    UnknownServer,
+    /// The server sent a message which doesn't belong at this point of the exchange
+    UnexpectedMessage,
+    /// AuCPace rejected what the server sent, or couldn't hash the password
+    Protocol,
+    /// The server asked for password hashing parameters which can't be used
+    Params,
+    /// The server turned the client away after too many failed attempts
+    Throttled,
+    /// One side failed to prove it knows the password
+    AuthFailed,
+    /// Something the server sent under the session's keys failed its integrity check
+    Integrity,
+    /// A message couldn't be encoded to send
+    Encode,
+    /// The library panicked, which is a bug
+    Internal,
+}
+
+/// Reads up to `len` bytes into `buf`
+///
+/// Returns the number of bytes read, 0 if nothing arrived before the transport's timeout, or a
+/// negative number if reading failed.
+pub type ReadFn = Option<unsafe extern "C" fn(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize>;
+
+/// Writes up to `len` bytes from `buf`
+///
+/// Returns the number of bytes written, or a negative number if writing failed.
+pub type WriteFn =
+    Option<unsafe extern "C" fn(ctx: *mut c_void, buf: *const u8, len: usize) -> isize>;
+
+/// A link to the server made of the caller's callbacks, which are passed `ctx` on every call
+#[repr(C)]
+pub struct Transport {
+    pub ctx: *mut c_void,
+    pub read: ReadFn,
+    pub write: WriteFn,
+}
+
+/// Parameters to hash the password with when registering, the server hands them back later
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+/// A session the client and server both saw through to the end
+#[repr(C)]
+pub struct Session {
+    /// Key for messages to the server, derived from the exchange
+    pub client_to_server: [u8; KEY_LEN],
+    /// Key for messages from the server
+    pub server_to_client: [u8; KEY_LEN],
+    /// Six digit code for an operator to compare against the server's
+    pub sas: u32,
+    /// Whether the session was resumed with a ticket rather than a full AuCPace exchange
+    pub resumed: bool,
+    /// Bytes sent to the server after the preamble
+    pub bytes_sent: usize,
+    /// Time spent computing rather than waiting for the server
+    pub compute_micros: u64,
+    /// How long the server asked to be left alone for, only set along with `Status::Throttled`
+    pub retry_after_ms: u32,
+}
+
+/// Register `user` with the server over the caller's callbacks
+///
+/// `params` may be null to use scrypt's recommended parameters.
+///
+/// # Safety
+///
+/// `transport` must point to a `Transport` whose callbacks can be called with its `ctx`, `user`
+/// and `password` must be NUL terminated strings and `params` must be null or point to a
+/// `ScryptParams`.
+#[no_mangle]
+pub unsafe extern "C" fn aucpace_register(
+    transport: *const Transport,
+    user: *const c_char,
+    password: *const c_char,
+    params: *const ScryptParams,
+) -> Status {
+    guard(|| {
+        let (Some(transport), Some(user), Some(password), Some(params)) = (
+            callbacks(transport),
+            string(user),
+            string(password),
+            params_or_default(params),
+        ) else {
+            return Status::InvalidArgument;
+        };
+        let options = options(LinkConfig::DEFAULT, params);
+        client::register(transport, user, password, &options).into()
+    })
+}
+
+/// Run a session as `user` over the caller's callbacks, filling in `session` if it succeeds
+///
+/// # Safety
+///
+/// `transport` must point to a `Transport` whose callbacks can be called with its `ctx`, `user`
+/// and `password` must be NUL terminated strings and `session` must point to a `Session`.
+#[no_mangle]
+pub unsafe extern "C" fn aucpace_login(
+    transport: *const Transport,
+    user: *const c_char,
+    password: *const c_char,
+    session: *mut Session,
+) -> Status {
+    guard(|| {
+        let (Some(transport), Some(user), Some(password), Some(session)) = (
+            callbacks(transport),
+            string(user),
+            string(password),
+            session.as_mut(),
+        ) else {
+            return Status::InvalidArgument;
+        };
+        let options = options(LinkConfig::DEFAULT, Params::recommended());
+        let result = client::authenticate(transport, user, password, &options);
+        fill(session, result)
+    })
+}
+
+/// Register `user` with the server on the serial port at `path`
+///
+/// The port is opened 8N1 at `baudrate`, or at the server's default rate if it is 0. `params` may
+/// be null to use scrypt's recommended parameters.
+///
+/// # Safety
+///
+/// `path`, `user` and `password` must be NUL terminated strings and `params` must be null or
+/// point to a `ScryptParams`.
+#[no_mangle]
+pub unsafe extern "C" fn aucpace_register_serial(
+    path: *const c_char,
+    baudrate: u32,
+    user: *const c_char,
+    password: *const c_char,
+    params: *const ScryptParams,
+) -> Status {
+    guard(|| {
+        let (Some(path), Some(user), Some(password), Some(params)) = (
+            string(path),
+            string(user),
+            string(password),
+            params_or_default(params),
+        ) else {
+            return Status::InvalidArgument;
+        };
+        let link = serial_link(baudrate);
+        let Ok(port) = client::open_serial(path, &link) else {
+            return Status::Io;
+        };
+        client::register(port, user, password, &options(link, params)).into()
+    })
+}
+
+/// Run a session as `user` with the server on the serial port at `path`, filling in `session` if
+/// it succeeds
+///
+/// The port is opened 8N1 at `baudrate`, or at the server's default rate if it is 0.
+///
+/// # Safety
+///
+/// `path`, `user` and `password` must be NUL terminated strings and `session` must point to a
+/// `Session`.
+#[no_mangle]
+pub unsafe extern "C" fn aucpace_login_serial(
+    path: *const c_char,
+    baudrate: u32,
+    user: *const c_char,
+    password: *const c_char,
+    session: *mut Session,
+) -> Status {
+    guard(|| {
+        let (Some(path), Some(user), Some(password), Some(session)) = (
+            string(path),
+            string(user),
+            string(password),
+            session.as_mut(),
+        ) else {
+            return Status::InvalidArgument;
+        };
+        let link = serial_link(baudrate);
+        let Ok(port) = client::open_serial(path, &link) else {
+            return Status::Io;
+        };
+        let options = options(link, Params::recommended());
+        fill(
+            session,
+            client::authenticate(port, user, password, &options),
+        )
+    })
+}
+
+/// A NUL terminated description of `status`, which lives as long as the program
+#[no_mangle]
+pub extern "C" fn aucpace_status_str(status: Status) -> *const c_char {
+    let description = match status {
+        Status::Ok => c"success",
+        Status::InvalidArgument => c"invalid argument",
+        Status::Io => c"failed to talk to the server",
+        Status::TimedOut => c"the server stopped answering",
+        Status::PreambleMismatch => c"the server runs a different protocol version or features",
+        Status::UnknownServer => c"the server sent an unrecognised answer to the preamble",
+        Status::UnexpectedMessage => c"the server sent an unexpected message",
+        Status::Protocol => c"AuCPace failed",
+        Status::Params => c"the server sent unusable password hashing parameters",
+        Status::Throttled => c"too many failed attempts, the server asked to retry later",
+        Status::AuthFailed => c"mutual authentication failed",
+        Status::Integrity => c"a message from the server failed its integrity check",
+        Status::Encode => c"failed to encode a message",
+        Status::Internal => c"internal error",
+    };
+    description.as_ptr()
+}
+
+impl From<&Error> for Status {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::Io(e) if e.kind() == io::ErrorKind::TimedOut => Status::TimedOut,
+            Error::Io(_) => Status::Io,
+            Error::Preamble(_) => Status::PreambleMismatch,
+            Error::UnknownServer => Status::UnknownServer,
+            Error::UnexpectedMessage => Status::UnexpectedMessage,
+            Error::Protocol(_) => Status::Protocol,
+            Error::Params => Status::Params,
+            Error::Throttled { .. } => Status::Throttled,
+            Error::AuthFailed => Status::AuthFailed,
+            Error::Integrity => Status::Integrity,
+            Error::Encode(_) => Status::Encode,
+            _ => Status::Internal,
+        }
+    }
+}
+
+impl From<Result<(), Error>> for Status {
+    fn from(result: Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => Status::Ok,
+            Err(e) => (&e).into(),
+        }
+    }
+}
+
+/// The caller's callbacks as a transport the client can use
+struct Callbacks {
+    ctx: *mut c_void,
+    read: unsafe extern "C" fn(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize,
+    write: unsafe extern "C" fn(ctx: *mut c_void, buf: *const u8, len: usize) -> isize,
+}
+
+impl Read for Callbacks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: the caller promised the callback can be called with `ctx`
+        let count = unsafe { (self.read)(self.ctx, buf.as_mut_ptr(), buf.len()) };
+        match usize::try_from(count) {
+            Ok(0) => Err(io::ErrorKind::TimedOut.into()),
+            Ok(count) if count <= buf.len() => Ok(count),
+            _ => Err(io::Error::other("read callback failed")),
+        }
+    }
+}
+
+impl Write for Callbacks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // SAFETY: the caller promised the callback can be called with `ctx`
+        let count = unsafe { (self.write)(self.ctx, buf.as_ptr(), buf.len()) };
+        match usize::try_from(count) {
+            Ok(count) if count <= buf.len() => Ok(count),
+            _ => Err(io::Error::other("write callback failed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Callbacks have no settings to change, the session stays at the rate the link already runs at
+#[cfg(feature = "baud_negotiation")]
+impl client::Port for Callbacks {
+    fn reconfigure(&mut self, _link: &LinkConfig) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Run `f`, reporting a panic as `Status::Internal` rather than unwinding into the caller
+fn guard(f: impl FnOnce() -> Status) -> Status {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(Status::Internal)
+}
+
+/// The callbacks `transport` points to, `None` if it or either of them is null
+unsafe fn callbacks(transport: *const Transport) -> Option<Callbacks> {
+    let transport = transport.as_ref()?;
+    Some(Callbacks {
+        ctx: transport.ctx,
+        read: transport.read?,
+        write: transport.write?,
+    })
+}
+
+/// The string `ptr` points to, `None` if it is null or not UTF-8
+unsafe fn string<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().ok()
+}
+
+/// The parameters `ptr` points to, the recommended ones if it is null and `None` if they're unusable
+unsafe fn params_or_default(ptr: *const ScryptParams) -> Option<Params> {
+    match ptr.as_ref() {
+        None => Some(Params::recommended()),
+        Some(params) => Params::new(params.log_n, params.r, params.p, Params::RECOMMENDED_LEN).ok(),
+    }
+}
+
+fn serial_link(baudrate: u32) -> LinkConfig {
+    match baudrate {
+        0 => LinkConfig::DEFAULT,
+        baudrate => LinkConfig::DEFAULT.with_baudrate(baudrate),
+    }
+}
+
+/// Options for a link opened with the settings in `link`
+// which fields there are to update depends on the features
+#[cfg_attr(
+    not(feature = "baud_negotiation"),
+    allow(unused_variables, clippy::needless_update)
+)]
+fn options(link: LinkConfig, params: Params) -> Options {
+    Options {
+        params,
+        #[cfg(feature = "baud_negotiation")]
+        link,
+        ..Options::default()
+    }
+}
+
+/// Copy what the caller needs out of a session, or how long to wait after being throttled
+fn fill(out: &mut Session, result: Result<client::Session, Error>) -> Status {
+    match result {
+        Ok(session) => {
+            let schedule = &session.schedule;
+            out.client_to_server = *schedule.client_to_server();
+            out.server_to_client = *schedule.server_to_client();
+            out.sas = ShortAuthString::new(schedule).code();
+            out.resumed = session.resumed;
+            out.bytes_sent = session.bytes_sent;
+            out.compute_micros = session.timings.total();
+            out.retry_after_ms = 0;
+            Status::Ok
+        }
+        Err(e) => {
+            if let Error::Throttled { retry_after } = e {
+                out.retry_after_ms = retry_after.as_millis().try_into().unwrap_or(u32::MAX);
+            }
+            (&e).into()
+        }
+    }
+}
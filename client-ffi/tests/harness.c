@@ -0,0 +1,127 @@
+/*
+ * Registers a user and logs in through the library, the way a test station would
+ *
+ *     harness tcp PORT USER PASSWORD [LOGIN_PASSWORD]
+ *     harness serial PATH USER PASSWORD [LOGIN_PASSWORD]
+ *
+ * tcp connects to the server on 127.0.0.1:PORT and talks to it through callbacks, serial opens
+ * the serial port at PATH. It prints the status of each call and the session's keys, and exits
+ * with the status of the first call which failed, or with 64 or 70 if the harness itself failed.
+ */
+#include <errno.h>
+#include <netinet/in.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <unistd.h>
+
+#include "aucpace_client.h"
+
+/* exit codes which can't be confused with a status */
+#define USAGE 64
+#define HARNESS_FAILED 70
+
+/* cheap parameters so the tests don't spend their time hashing passwords */
+static const AucpaceScryptParams PARAMS = {.log_n = 4, .r = 8, .p = 1};
+
+static ptrdiff_t socket_read(void *ctx, uint8_t *buf, size_t len) {
+  ssize_t count = recv(*(int *)ctx, buf, len, 0);
+  if (count < 0 && (errno == EAGAIN || errno == EWOULDBLOCK)) {
+    return 0;
+  }
+  /* the server hanging up is an error rather than a timeout */
+  return count > 0 ? count : -1;
+}
+
+static ptrdiff_t socket_write(void *ctx, const uint8_t *buf, size_t len) {
+  return send(*(int *)ctx, buf, len, MSG_NOSIGNAL);
+}
+
+/* connect to 127.0.0.1:port with reads timing out like the serial port's */
+static int connect_to(int port) {
+  int fd = socket(AF_INET, SOCK_STREAM, 0);
+  if (fd < 0) {
+    return -1;
+  }
+  struct timeval timeout = {.tv_sec = 0, .tv_usec = 500000};
+  setsockopt(fd, SOL_SOCKET, SO_RCVTIMEO, &timeout, sizeof(timeout));
+
+  struct sockaddr_in addr = {
+      .sin_family = AF_INET,
+      .sin_port = htons(port),
+      .sin_addr.s_addr = htonl(INADDR_LOOPBACK),
+  };
+  if (connect(fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) {
+    close(fd);
+    return -1;
+  }
+  return fd;
+}
+
+static void print_key(const char *name, const uint8_t key[AUCPACE_KEY_LEN]) {
+  printf("%s: ", name);
+  for (size_t i = 0; i < AUCPACE_KEY_LEN; i++) {
+    printf("%02x", key[i]);
+  }
+  printf("\n");
+}
+
+static void print_status(const char *call, AucpaceStatus status) {
+  printf("%s: %d %s\n", call, (int)status, aucpace_status_str(status));
+}
+
+int main(int argc, char **argv) {
+  if (argc < 5) {
+    fprintf(stderr, "usage: %s tcp|serial PORT|PATH USER PASSWORD [LOGIN_PASSWORD]\n", argv[0]);
+    return USAGE;
+  }
+  const char *mode = argv[1];
+  const char *user = argv[3];
+  const char *password = argv[4];
+  const char *login_password = argc > 5 ? argv[5] : password;
+
+  /* bad arguments are turned away before anything is sent */
+  AucpaceSession session;
+  if (aucpace_login(NULL, user, password, &session) != AUCPACE_STATUS_INVALID_ARGUMENT) {
+    fprintf(stderr, "a null transport was accepted\n");
+    return HARNESS_FAILED;
+  }
+
+  AucpaceStatus status;
+  if (strcmp(mode, "tcp") == 0) {
+    int fd = connect_to(atoi(argv[2]));
+    if (fd < 0) {
+      perror("connect");
+      return HARNESS_FAILED;
+    }
+    AucpaceTransport transport = {.ctx = &fd, .read = socket_read, .write = socket_write};
+
+    status = aucpace_register(&transport, user, password, &PARAMS);
+    print_status("register", status);
+    if (status == AUCPACE_STATUS_OK) {
+      status = aucpace_login(&transport, user, login_password, &session);
+      print_status("login", status);
+    }
+    close(fd);
+  } else if (strcmp(mode, "serial") == 0) {
+    const char *path = argv[2];
+    status = aucpace_register_serial(path, 0, user, password, &PARAMS);
+    print_status("register", status);
+    if (status == AUCPACE_STATUS_OK) {
+      status = aucpace_login_serial(path, 0, user, login_password, &session);
+      print_status("login", status);
+    }
+  } else {
+    fprintf(stderr, "unknown mode %s\n", mode);
+    return USAGE;
+  }
+
+  if (status == AUCPACE_STATUS_OK) {
+    print_key("client_to_server", session.client_to_server);
+    print_key("server_to_client", session.server_to_client);
+    printf("sas: %06u\n", session.sas);
+  }
+  return (int)status;
+}
@@ -0,0 +1,233 @@
+//! Runs the C harness in tests/harness.c against the server over an in-memory link
+//!
+//! The harness is compiled with the system's C compiler against `include/aucpace_client.h` and
+//! linked with the library's cdylib. It reaches the server through a TCP connection or a pty,
+//! which are bridged to the in-memory link.
+
+use aucpace_client::Status;
+use integration::{ClientPort, Server};
+use nix::pty;
+use nix::sys::termios::{self, SetArg};
+use nix::unistd;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+
+const USER: &str = "alice";
+const PASSWORD: &str = "correct horse battery staple";
+
+/// A command to run the harness, which is compiled the first time a test needs it
+fn harness() -> Command {
+    static HARNESS: OnceLock<PathBuf> = OnceLock::new();
+    let path = HARNESS.get_or_init(|| {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+        // cargo builds the cdylib next to the tests when it builds them
+        let exe = std::env::current_exe().unwrap();
+        let lib_dir = exe.parent().unwrap();
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("harness");
+
+        let status = Command::new("cc")
+            .arg(manifest.join("tests/harness.c"))
+            .arg("-I")
+            .arg(manifest.join("include"))
+            .arg("-L")
+            .arg(lib_dir)
+            .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+            .arg("-laucpace_client")
+            .arg("-o")
+            .arg(&out)
+            .status()
+            .expect("a C compiler is installed as cc");
+        assert!(status.success(), "failed to compile the harness");
+        out
+    });
+
+    let mut command = Command::new(path);
+    // cargo points this at target/<profile>, whose copy of the library can be out of date
+    command.env_remove("LD_LIBRARY_PATH");
+    command
+}
+
+/// Copies bytes between the harness's end of a link and the server's in-memory one
+struct Bridge {
+    threads: [JoinHandle<()>; 2],
+    done: Arc<AtomicBool>,
+}
+
+impl Bridge {
+    /// Start copying until reading from the harness's end fails or reaches its end
+    fn new(
+        mut from_harness: impl Read + Send + 'static,
+        mut to_harness: impl Write + Send + 'static,
+        port: ClientPort,
+    ) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let mut to_server = port.clone();
+        let finished = done.clone();
+        let forward = thread::spawn(move || {
+            let _ = io::copy(&mut from_harness, &mut to_server);
+            finished.store(true, Ordering::Relaxed);
+        });
+
+        let mut from_server = port;
+        let finished = done.clone();
+        let back = thread::spawn(move || {
+            let mut buf = [0; 256];
+            while !finished.load(Ordering::Relaxed) {
+                match from_server.read(&mut buf) {
+                    Ok(count) => {
+                        if to_harness.write_all(&buf[..count]).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Self {
+            threads: [forward, back],
+            done,
+        }
+    }
+
+    fn join(self) {
+        let [forward, back] = self.threads;
+        forward.join().unwrap();
+        self.done.store(true, Ordering::Relaxed);
+        back.join().unwrap();
+    }
+}
+
+/// What the harness printed, by the name before each colon
+struct Report {
+    status: Option<i32>,
+    lines: HashMap<String, String>,
+}
+
+impl Report {
+    fn new(output: Output) -> Self {
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines = stdout
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Self {
+            status: output.status.code(),
+            lines,
+        }
+    }
+
+    fn get(&self, name: &str) -> &str {
+        &self.lines[name]
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Run the harness over a TCP connection to the server, logging in with `password`
+fn over_tcp(port: ClientPort, password: &str) -> Report {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let accept = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        Bridge::new(stream.try_clone().unwrap(), stream, port)
+    });
+
+    let output = harness()
+        .args(["tcp", &address.port().to_string(), USER, PASSWORD, password])
+        .output()
+        .unwrap();
+    accept.join().unwrap().join();
+    Report::new(output)
+}
+
+/// Run the harness over a pty standing in for the board's serial port
+fn over_pty(port: ClientPort) -> Report {
+    let pty = pty::openpty(None, None).unwrap();
+    // the client's end has to pass bytes through untouched, like a serial port
+    let mut raw = termios::tcgetattr(pty.slave).unwrap();
+    termios::cfmakeraw(&mut raw);
+    termios::tcsetattr(pty.slave, SetArg::TCSANOW, &raw).unwrap();
+    let path = unistd::ttyname(pty.slave).unwrap();
+
+    // SAFETY: the pty's master is open and nothing else owns it
+    let master = unsafe { File::from_raw_fd(pty.master) };
+    let bridge = Bridge::new(master.try_clone().unwrap(), master, port);
+
+    let output = harness()
+        .arg("serial")
+        .arg(&path)
+        .args([USER, PASSWORD])
+        .output()
+        .unwrap();
+    // reading the master fails once nothing holds the other end open
+    unistd::close(pty.slave).unwrap();
+    bridge.join();
+    Report::new(output)
+}
+
+#[test]
+fn the_harness_logs_in_through_callbacks() {
+    let (server, port) = Server::spawn(1);
+    let report = over_tcp(port, PASSWORD);
+    let server = server.join().remove(0).unwrap();
+
+    assert_eq!(report.status, Some(Status::Ok as i32));
+    assert_eq!(
+        report.get("client_to_server"),
+        hex(server.schedule.client_to_server().as_slice())
+    );
+    assert_eq!(
+        report.get("server_to_client"),
+        hex(server.schedule.server_to_client().as_slice())
+    );
+}
+
+#[test]
+fn the_harness_logs_in_over_a_serial_port() {
+    let (server, port) = Server::spawn(1);
+    let report = over_pty(port);
+    let server = server.join().remove(0).unwrap();
+
+    assert_eq!(report.status, Some(Status::Ok as i32));
+    assert_eq!(
+        report.get("client_to_server"),
+        hex(server.schedule.client_to_server().as_slice())
+    );
+}
+
+/// In implicit mode neither side can tell, see the integration tests
+#[cfg(not(feature = "implicit"))]
+#[test]
+fn wrong_passwords_come_back_as_auth_failed() {
+    let (server, port) = Server::spawn(1);
+    let report = over_tcp(port, "hunter2");
+    server.join();
+
+    assert_eq!(report.status, Some(Status::AuthFailed as i32));
+    assert!(report
+        .get("login")
+        .ends_with("mutual authentication failed"));
+}
+
+#[test]
+fn missing_serial_ports_come_back_as_io_errors() {
+    let output = harness()
+        .args(["serial", "/dev/does-not-exist", USER, PASSWORD])
+        .output()
+        .unwrap();
+
+    assert_eq!(Report::new(output).status, Some(Status::Io as i32));
+}
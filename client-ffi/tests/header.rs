@@ -0,0 +1,24 @@
+//! Checks `include/aucpace_client.h` is what cbindgen generates from the library as it stands
+
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn header_is_up_to_date() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(manifest.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(manifest.join("src/lib.rs"))
+        .generate()
+        .expect("cbindgen failed to generate the header")
+        .write(&mut generated);
+
+    let checked_in = fs::read(manifest.join("include/aucpace_client.h")).unwrap();
+    assert!(
+        generated == checked_in,
+        "include/aucpace_client.h is out of date, regenerate it from client-ffi with \
+         `cbindgen --config cbindgen.toml --output include/aucpace_client.h`"
+    );
+}
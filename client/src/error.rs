@@ -0,0 +1,78 @@
+//! Why registering with or authenticating to the server failed
+
+use protocol::fragment::FragmentError;
+use protocol::preamble::Mismatch;
+use std::time::Duration;
+use std::{fmt, io};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading from or writing to the transport failed, a timeout means the server stopped answering
+    Io(io::Error),
+    /// The server was built with a different protocol version or different features
+    Preamble(Mismatch),
+    /// The server's answer to our preamble didn't parse, it is probably running an older protocol
+    UnknownServer,
+    /// The server sent a message which doesn't belong at this point of the exchange
+    UnexpectedMessage,
+    /// AuCPace rejected what the server sent, or couldn't hash the password
+    Protocol(aucpace::Error),
+    /// The server asked for password hashing parameters we can't use
+    Params,
+    /// The server turned us away after too many failed attempts
+    Throttled { retry_after: Duration },
+    /// One side failed to prove it knows the password, or holds the resumption ticket
+    ///
+    /// The server stops answering when our proof is wrong, so timing out while waiting for its
+    /// authenticator is reported as this too.
+    AuthFailed,
+    /// Something the server sent under the session's keys failed its integrity check
+    Integrity,
+    /// A message couldn't be split into fragments to send
+    Encode(FragmentError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "failed to talk to the server - {e}"),
+            Error::Preamble(mismatch) => write!(f, "server rejected our preamble - {mismatch}"),
+            Error::UnknownServer => f.write_str(
+                "server sent an unrecognised answer to our preamble, it is probably running an older protocol",
+            ),
+            Error::UnexpectedMessage => f.write_str("server sent an unexpected message"),
+            Error::Protocol(e) => write!(f, "AuCPace failed - {e}"),
+            Error::Params => f.write_str("server sent unusable password hashing parameters"),
+            Error::Throttled { retry_after } => write!(
+                f,
+                "too many failed attempts, the server asked to retry after {:.1}s",
+                retry_after.as_secs_f32()
+            ),
+            Error::AuthFailed => f.write_str("mutual authentication failed"),
+            Error::Integrity => f.write_str("a message from the server failed its integrity check"),
+            Error::Encode(e) => write!(f, "failed to send message - {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<aucpace::Error> for Error {
+    fn from(e: aucpace::Error) -> Self {
+        Error::Protocol(e)
+    }
+}
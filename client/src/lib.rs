@@ -0,0 +1,1511 @@
+//! The client's side of the exchange, over a serial port or anything else which reads and writes
+//!
+//! [`register`] stores a user's verifier on the server, [`authenticate`] runs a session as them.
+use aucpace::{Client, ServerMessage};
+use protocol::admission::Admission;
+use protocol::confirmation::KeyConfirmation;
+use protocol::fragment::{Fragment, Fragmenter, Reassembler};
+use protocol::framing::{self, Checksum, FrameError, FrameReceiver, FrameStats};
+use protocol::key_schedule::{KeySchedule, Transcript};
+use protocol::link::{LinkConfig, Parity, StopBits};
+use protocol::phase::{Phase, PhaseTimings};
+use protocol::preamble::{self, Preamble, PreambleResponse};
+use scrypt::password_hash::ParamsString;
+use scrypt::{Params, Scrypt};
+use serde::{Deserialize, Serialize};
+use serialport::SerialPort;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{io, thread};
+use zeroize::{Zeroize, Zeroizing};
+
+#[allow(unused)]
+use tracing::{debug, error, info, trace, warn};
+
+#[cfg(feature = "resumption")]
+use {
+    protocol::resumption::{
+        self, NewTicket, ResumeFinish, ResumeResponse, ResumeTranscript, SessionStart,
+    },
+    std::fs,
+    std::path::{Path, PathBuf},
+    std::time::SystemTime,
+    zeroize::ZeroizeOnDrop,
+};
+
+#[cfg(feature = "baud_negotiation")]
+use protocol::link::{BaudRequest, BaudResponse, LinkCheck, CHECK_TIMEOUT_MS, SWITCH_DELAY_MS};
+
+#[cfg(any(feature = "resumption", feature = "baud_negotiation"))]
+use rand_core::RngCore;
+
+#[cfg(feature = "reliable")]
+use {
+    protocol::fragment::fragment_count,
+    protocol::reliable::{self, GaveUp, Received, RecvStats, RecvWindow, SendStats, SendWindow},
+    serde::de::IgnoredAny,
+    std::sync::OnceLock,
+};
+
+#[cfg(feature = "event_log")]
+use protocol::events::{self, Event, LogRequest, LogResponse};
+
+mod error;
+pub use error::Error;
+
+#[cfg(all(feature = "resumption", feature = "implicit"))]
+compile_error!("Resumption tickets are only issued after explicit mutual authentication");
+
+#[cfg(all(feature = "crc16", feature = "crc32"))]
+compile_error!("Only one of the crc16 and crc32 features can be enabled");
+
+#[cfg(all(feature = "reliable", not(any(feature = "crc16", feature = "crc32"))))]
+compile_error!("Reliable delivery needs the crc16 or crc32 feature to spot corrupted frames");
+
+const RECV_BUF_LEN: usize = 1024;
+
+/// The server can't receive frames any longer than this
+const SEND_BUF_LEN: usize = 1024;
+
+/// The server can't reassemble messages any longer than this
+const SEND_MSG_LEN: usize = 4096;
+
+/// The server never sends messages any longer than this
+const RECV_MSG_LEN: usize = 1024;
+
+/// Features which change the wire format, the server has to be built with the same
+const FEATURES: u32 = {
+    let mut features = 0;
+    if cfg!(feature = "strong") {
+        features |= preamble::STRONG;
+    }
+    if cfg!(feature = "implicit") {
+        features |= preamble::IMPLICIT;
+    }
+    if cfg!(feature = "static_ssid") {
+        features |= preamble::STATIC_SSID;
+    }
+    if cfg!(feature = "resumption") {
+        features |= preamble::RESUMPTION;
+    }
+    if cfg!(feature = "baud_negotiation") {
+        features |= preamble::BAUD_NEGOTIATION;
+    }
+    if cfg!(feature = "crc16") {
+        features |= preamble::CRC16;
+    }
+    if cfg!(feature = "crc32") {
+        features |= preamble::CRC32;
+    }
+    if cfg!(feature = "reliable") {
+        features |= preamble::RELIABLE;
+    }
+    if cfg!(feature = "event_log") {
+        features |= preamble::EVENT_LOG;
+    }
+    if cfg!(feature = "timings") {
+        features |= preamble::TIMINGS;
+    }
+    features
+};
+
+/// Checksum on every frame after the preamble, the server has to use the same
+const CHECKSUM: Option<Checksum> = if cfg!(feature = "crc16") {
+    Some(Checksum::Crc16)
+} else if cfg!(feature = "crc32") {
+    Some(Checksum::Crc32)
+} else {
+    None
+};
+
+/// Checksum on every frame of the reliable delivery layer
+#[cfg(feature = "reliable")]
+const RELIABLE_CHECKSUM: Checksum = match CHECKSUM {
+    Some(checksum) => checksum,
+    None => panic!("reliable delivery needs a checksum"),
+};
+
+/// Number of frames which can be waiting for the server to acknowledge them
+///
+/// The protocol never sends more than a couple of messages without hearing back from the server.
+#[cfg(feature = "reliable")]
+const WINDOW: usize = 2 * fragment_count(SEND_MSG_LEN);
+
+const K1: usize = 16;
+
+#[cfg(feature = "static_ssid")]
+const SSID: [u8; 32] = [
+    60, 173, 56, 252, 74, 141, 171, 146, 102, 169, 149, 169, 158, 106, 87, 232, 220, 141, 251, 73,
+    39, 130, 105, 184, 93, 87, 195, 23, 246, 158, 85, 226,
+];
+
+/// function like macro to wrap receiving data over the serial port, defaults to receiving a `ServerMessage`
+///
+/// The sender answers the server's resend requests and asks for corrupted frames again.
+/// Failing to read from or write to the port returns the error from the enclosing function.
+#[cfg(not(feature = "reliable"))]
+macro_rules! recv {
+    ($recvr:ident, $sendr:ident) => {
+        recv!($recvr, $sendr, ServerMessage<K1>)
+    };
+    ($recvr:ident, $sendr:ident, $msg_ty:ty) => {
+        loop {
+            let parsed = $recvr.recv_msg::<$msg_ty>()?;
+            match parsed {
+                Ok(msg) => {
+                    debug!("Parsed message - {msg:?}");
+                    break msg;
+                }
+                Err(FrameError::ResendRequest) => {
+                    warn!("Server asked for the last frame again");
+                    $sendr.resend_last()?;
+                }
+                Err(FrameError::Checksum) => {
+                    warn!("Received a corrupted frame - asking for it again");
+                    $sendr.request_resend()?;
+                }
+                Err(e) => {
+                    error!("Failed to parse message - {e:?}");
+                }
+            };
+        }
+    };
+}
+
+/// function like macro to wrap receiving data over the serial port, defaults to receiving a `ServerMessage`
+///
+/// The sender acknowledges every message, answers the server's ACKs and NAKs,
+/// and sends frames again while it waits.
+/// Failing to read from or write to the port returns the error from the enclosing function.
+#[cfg(feature = "reliable")]
+macro_rules! recv {
+    ($recvr:ident, $sendr:ident) => {
+        recv!($recvr, $sendr, ServerMessage<K1>)
+    };
+    ($recvr:ident, $sendr:ident, $msg_ty:ty) => {
+        loop {
+            let parsed = $recvr.recv_msg::<$msg_ty>($sendr.retransmit_deadline())?;
+            match parsed {
+                None => $sendr.retransmit()?,
+                Some(Ok(Received::Message {
+                    msg: Some(msg),
+                    ack,
+                })) => {
+                    $sendr.ack(ack)?;
+                    debug!("Parsed message - {msg:?}");
+                    break msg;
+                }
+                // more of the message is still to come
+                Some(Ok(Received::Message { msg: None, ack })) => $sendr.ack(ack)?,
+                Some(Ok(Received::Duplicate { ack })) => {
+                    warn!("Received a message twice - acknowledging it again");
+                    $sendr.ack(ack)?;
+                }
+                Some(Ok(Received::Gap { expected })) => {
+                    warn!("Missed message {expected} - asking for it again");
+                    $sendr.nak(expected)?;
+                }
+                Some(Ok(Received::Ack(seq))) => $sendr.acked(seq),
+                Some(Ok(Received::Nak(seq))) => {
+                    warn!("Server asked for every frame from {seq} again");
+                    $sendr.nakked(seq)?;
+                }
+                Some(Err(FrameError::Checksum | FrameError::Malformed)) => {
+                    warn!("Received a corrupted frame - asking for it again");
+                    $sendr.nak($recvr.expected())?;
+                }
+                Some(Err(e)) => {
+                    // the message still arrived, so it mustn't be sent again
+                    error!("Failed to parse message - {e:?}");
+                    $sendr.ack($recvr.expected().wrapping_sub(1))?;
+                }
+            };
+        }
+    };
+}
+
+/// Anything the client can talk to the server over, usually a serial port
+///
+/// Any `Read + Write` transport will do, but with `baud_negotiation` it also has to be able to
+/// change its settings. Reads should give up with `io::ErrorKind::TimedOut` if nothing arrives for
+/// a while.
+pub trait Port: Read + Write {
+    /// Switch to the settings in `link`, dropping anything sent or received under the old ones
+    #[cfg(feature = "baud_negotiation")]
+    fn reconfigure(&mut self, link: &LinkConfig) -> io::Result<()>;
+}
+
+#[cfg(not(feature = "baud_negotiation"))]
+impl<T: Read + Write + ?Sized> Port for T {}
+
+#[cfg(feature = "baud_negotiation")]
+impl<P: Port + ?Sized> Port for &mut P {
+    fn reconfigure(&mut self, link: &LinkConfig) -> io::Result<()> {
+        (**self).reconfigure(link)
+    }
+}
+
+#[cfg(feature = "baud_negotiation")]
+impl Port for Box<dyn SerialPort> {
+    fn reconfigure(&mut self, link: &LinkConfig) -> io::Result<()> {
+        self.set_baud_rate(link.baudrate)?;
+        self.set_parity(serial_parity(link.parity))?;
+        self.set_stop_bits(serial_stop_bits(link.stop_bits))?;
+        self.clear(serialport::ClearBuffer::All)?;
+        Ok(())
+    }
+}
+
+/// Open the serial port called `name` with the settings in `link`
+pub fn open_serial(name: &str, link: &LinkConfig) -> serialport::Result<Box<dyn SerialPort>> {
+    serialport::new(name, link.baudrate)
+        .parity(serial_parity(link.parity))
+        .stop_bits(serial_stop_bits(link.stop_bits))
+        .timeout(Duration::from_millis(500))
+        .open()
+}
+
+/// How the client registers and authenticates, the defaults suit a server on the serial port
+pub struct Options {
+    /// Parameters to hash the password with when registering, the server hands them back later
+    pub params: Params,
+    /// Settings the port was opened with, which both sides fall back to
+    #[cfg(feature = "baud_negotiation")]
+    pub link: LinkConfig,
+    /// Baud rate to ask the server to switch to for the session
+    #[cfg(feature = "baud_negotiation")]
+    pub negotiate_baud: Option<u32>,
+    /// File to load a resumption ticket from and store new tickets in
+    #[cfg(feature = "resumption")]
+    pub ticket: Option<PathBuf>,
+    /// Read back the server's event log once the session's keys are derived
+    #[cfg(feature = "event_log")]
+    pub download_events: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            params: Params::recommended(),
+            #[cfg(feature = "baud_negotiation")]
+            link: LinkConfig::DEFAULT,
+            #[cfg(feature = "baud_negotiation")]
+            negotiate_baud: None,
+            #[cfg(feature = "resumption")]
+            ticket: None,
+            #[cfg(feature = "event_log")]
+            download_events: false,
+        }
+    }
+}
+
+/// A session the client and server both saw through to the end
+pub struct Session {
+    /// The subkeys derived from the exchange, the raw AuCPace key is never used directly
+    pub schedule: KeySchedule,
+    /// Whether the session was resumed with a ticket rather than a full AuCPace exchange
+    pub resumed: bool,
+    /// Bytes sent to the server after the preamble
+    pub bytes_sent: usize,
+    /// Time spent computing in each phase rather than waiting for the server
+    pub timings: PhaseTimings,
+    /// The server's own timings for the session, to put next to the client's
+    #[cfg(feature = "timings")]
+    pub server_timings: PhaseTimings,
+    /// The server's event log, if it was asked for
+    #[cfg(feature = "event_log")]
+    pub events: Option<DeviceLog>,
+}
+
+/// The events the server still held at the end of the session, oldest first
+#[cfg(feature = "event_log")]
+pub struct DeviceLog {
+    /// Events recorded since the server booted, including the ones which have been overwritten
+    pub recorded: u32,
+    pub events: Vec<Event>,
+}
+
+/// A resumption ticket saved between runs of the client
+#[cfg(feature = "resumption")]
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct StoredTicket {
+    ticket: Vec<u8>,
+    secret: [u8; resumption::SECRET_LEN],
+    /// seconds since the unix epoch after which the server will reject the ticket
+    expires: u64,
+}
+
+/// Register `user` with the server on the other end of `transport`
+///
+/// The server only stores the verifier derived from the password, hashed with `options.params`.
+pub fn register<P: Port>(
+    transport: P,
+    user: &str,
+    password: &str,
+    options: &Options,
+) -> Result<(), Error> {
+    let serial = Mutex::new(transport);
+    let mut sender = MsgSender::new(&serial);
+    let mut receiver = MsgReceiver::new(&serial);
+    exchange_preamble(&mut sender, &mut receiver)?;
+
+    let mut client = Client::new(rand_core::OsRng);
+    #[cfg(not(feature = "strong"))]
+    let message = client.register_alloc(user.as_bytes(), password, options.params, Scrypt)?;
+
+    #[cfg(feature = "strong")]
+    let message =
+        client.register_alloc_strong(user.as_bytes(), password, options.params, Scrypt)?;
+
+    sender.send_msg(&message)?;
+    // the server has to have the registration before the session's preamble
+    #[cfg(feature = "reliable")]
+    flush(&mut sender, &mut receiver)?;
+    info!(
+        "Registered as {user} for {}",
+        if cfg!(feature = "strong") {
+            "Strong AuCPace"
+        } else {
+            "AuCPace"
+        }
+    );
+
+    Ok(())
+}
+
+/// Run a session as `user` with the server on the other end of `transport`
+///
+/// Resumes the previous session if `options.ticket` holds a ticket the server accepts, and runs
+/// a full AuCPace exchange otherwise.
+pub fn authenticate<P: Port>(
+    transport: P,
+    user: &str,
+    password: &str,
+    #[cfg_attr(
+        not(any(
+            feature = "baud_negotiation",
+            feature = "resumption",
+            feature = "event_log"
+        )),
+        allow(unused_variables)
+    )]
+    options: &Options,
+) -> Result<Session, Error> {
+    let serial = Mutex::new(transport);
+    let mut sender = MsgSender::new(&serial);
+    let mut receiver = MsgReceiver::new(&serial);
+    let mut bytes_sent = 0;
+
+    // ===== Preamble =====
+    exchange_preamble(&mut sender, &mut receiver)?;
+
+    // ===== Baud Rate Negotiation =====
+    // this happens outside the transcript as frames can be lost while switching
+    #[cfg(feature = "baud_negotiation")]
+    {
+        let target = options.negotiate_baud.unwrap_or(options.link.baudrate);
+        negotiate_baud(&serial, &mut sender, &mut receiver, &options.link, target)?;
+    }
+
+    // none of the above is part of the session so start the transcripts afresh
+    sender.reset_transcript();
+    receiver.reset_transcript();
+
+    // frames lost while switching rates don't carry over into the session
+    #[cfg(feature = "reliable")]
+    {
+        sender.reset_window();
+        receiver.reset_window();
+    }
+
+    // ===== Session Resumption =====
+    #[cfg(feature = "resumption")]
+    if let Some(stored) = options.ticket.as_deref().and_then(take_ticket) {
+        info!("Attempting to resume the previous session");
+        let mut timings = PhaseTimings::default();
+        let mut client_nonce = [0u8; resumption::NONCE_LEN];
+        timed(&mut timings, Phase::Resumption, || {
+            rand_core::OsRng.fill_bytes(&mut client_nonce)
+        });
+        let message = SessionStart::Resume {
+            ticket: &stored.ticket,
+            nonce: client_nonce,
+        };
+        bytes_sent += sender.send_msg(&message)?;
+
+        let response = recv!(receiver, sender, ResumeResponse);
+        if let ResumeResponse::Accepted {
+            nonce: server_nonce,
+            mac,
+        } = response
+        {
+            let (verified, message) = timed(&mut timings, Phase::Resumption, || {
+                let transcript = ResumeTranscript::new(&stored.ticket, client_nonce, server_nonce);
+                let verified = transcript.verify_server_mac(&stored.secret, &mac);
+                let message = ResumeFinish {
+                    mac: transcript.client_mac(&stored.secret),
+                };
+                (verified.then_some(transcript), message)
+            });
+            let Some(transcript) = verified else {
+                error!("Server failed the resumption authentication check");
+                return Err(Error::AuthFailed);
+            };
+            bytes_sent += sender.send_msg(&message)?;
+            let schedule = timed(&mut timings, Phase::Resumption, || {
+                let key = transcript.session_key(&stored.secret);
+                KeySchedule::new(key.as_ref(), sender.transcript(), receiver.transcript())
+            });
+
+            let new_ticket = recv!(receiver, sender, NewTicket);
+            store_ticket(
+                options.ticket.as_deref(),
+                &new_ticket,
+                &schedule.resumption(),
+            )?;
+
+            // the rest of the session still has to be read if the log is refused
+            #[cfg(feature = "event_log")]
+            let events = exchange_event_log(
+                &mut sender,
+                &mut receiver,
+                &schedule,
+                options.download_events,
+            );
+            #[cfg(feature = "timings")]
+            let server_timings = recv!(receiver, sender, PhaseTimings);
+
+            #[cfg(feature = "reliable")]
+            flush(&mut sender, &mut receiver)?;
+            #[cfg(feature = "event_log")]
+            let events = events?;
+
+            info!("Resumed session");
+            log_keys(&schedule);
+            info!("Total bytes sent: {}", bytes_sent);
+            log_stats(receiver.stats());
+            #[cfg(feature = "reliable")]
+            log_reliable_stats(sender.reliable_stats(), receiver.reliable_stats());
+            log_timings(&timings);
+
+            return Ok(Session {
+                schedule,
+                resumed: true,
+                bytes_sent,
+                timings,
+                #[cfg(feature = "timings")]
+                server_timings,
+                #[cfg(feature = "event_log")]
+                events,
+            });
+        }
+
+        warn!("Server rejected the resumption ticket, falling back to AuCPace");
+    }
+
+    #[cfg(feature = "resumption")]
+    {
+        let message = SessionStart::Full;
+        bytes_sent += sender.send_msg(&message)?;
+    }
+
+    info!("Starting AuCPace");
+    let mut base_client = Client::new(rand_core::OsRng);
+    let mut timings = PhaseTimings::default();
+    // ===== SSID Establishment =====
+    #[cfg(feature = "static_ssid")]
+    let client = {
+        let client = timed(&mut timings, Phase::Ssid, || {
+            base_client.begin_prestablished_ssid(SSID)
+        })?;
+        info!("Began from static SSID={:02X?}", SSID);
+        client
+    };
+
+    #[cfg(not(feature = "static_ssid"))]
+    let client = {
+        let (client, message) = timed(&mut timings, Phase::Ssid, || base_client.begin());
+        bytes_sent += sender.send_msg(&message)?;
+
+        let server_message = recv!(receiver, sender);
+        let ServerMessage::Nonce(server_nonce) = server_message else {
+            return Err(unexpected(&server_message));
+        };
+        let client = timed(&mut timings, Phase::Ssid, || {
+            client.agree_ssid(server_nonce)
+        });
+        info!("Agreed on SSID");
+        client
+    };
+
+    // ===== Augmentation Layer =====
+    #[cfg(not(feature = "strong"))]
+    let (client, message) = {
+        info!("Sending message: Username");
+        timed(&mut timings, Phase::Augmentation, || {
+            client.start_augmentation(user.as_bytes(), password.as_bytes())
+        })
+    };
+    #[cfg(feature = "strong")]
+    let (client, message) = {
+        info!("Sending message: Strong Username");
+        timed(&mut timings, Phase::Augmentation, || {
+            client.start_augmentation_strong(
+                user.as_bytes(),
+                password.as_bytes(),
+                &mut rand_core::OsRng,
+            )
+        })
+    };
+    bytes_sent += sender.send_msg(&message)?;
+
+    // the server may refuse to continue if there have been too many failed attempts
+    if let Admission::RetryAfter { millis } = recv!(receiver, sender, Admission) {
+        return Err(Error::Throttled {
+            retry_after: Duration::from_millis(millis.into()),
+        });
+    }
+
+    let server_message = recv!(receiver, sender);
+    #[cfg(not(feature = "strong"))]
+    let client = if let ServerMessage::AugmentationInfo {
+        x_pub,
+        salt,
+        pbkdf_params,
+        ..
+    } = server_message
+    {
+        info!("Received Augmentation info");
+        let params = parse_params(pbkdf_params)?;
+        timed(&mut timings, Phase::Pbkdf, || {
+            client.generate_cpace_alloc(x_pub, &salt, params, Scrypt)
+        })?
+    } else {
+        return Err(unexpected(&server_message));
+    };
+
+    #[cfg(feature = "strong")]
+    let client = if let ServerMessage::StrongAugmentationInfo {
+        x_pub,
+        blinded_salt,
+        pbkdf_params,
+        ..
+    } = server_message
+    {
+        info!("Received Strong Augmentation info");
+        let params = parse_params(pbkdf_params)?;
+        timed(&mut timings, Phase::Pbkdf, || {
+            client.generate_cpace_alloc(x_pub, blinded_salt, params, Scrypt)
+        })?
+    } else {
+        return Err(unexpected(&server_message));
+    };
+
+    // ===== CPace substep =====
+    let (client, message) = timed(&mut timings, Phase::CPace, || {
+        client.generate_public_key(preamble::CHANNEL_ID, &mut rand_core::OsRng)
+    });
+    bytes_sent += sender.send_msg(&message)?;
+    info!("Sent PublicKey");
+
+    let server_message = recv!(receiver, sender);
+    let ServerMessage::PublicKey(server_pubkey) = server_message else {
+        return Err(unexpected(&server_message));
+    };
+    let mut key = if cfg!(feature = "implicit") {
+        timed(&mut timings, Phase::CPace, || {
+            client.implicit_auth(server_pubkey)
+        })?
+    } else {
+        let (client, message) = timed(&mut timings, Phase::CPace, || {
+            client.receive_server_pubkey(server_pubkey)
+        })?;
+
+        // ===== Explicit Mutual Auth =====
+        bytes_sent += sender.send_msg(&message)?;
+        info!("Sent Authenticator");
+
+        let server_authenticator = recv_authenticator(&mut sender, &mut receiver)?;
+        timed(&mut timings, Phase::Authenticator, || {
+            client.receive_server_authenticator(server_authenticator)
+        })
+        .map_err(|e| {
+            error!("Server failed explicit mutual authentication - {e}");
+            Error::AuthFailed
+        })?
+    };
+    // the keys come out of the last phase of the exchange
+    let key_phase = if cfg!(feature = "implicit") {
+        Phase::CPace
+    } else {
+        Phase::Authenticator
+    };
+    let schedule = timed(&mut timings, key_phase, || {
+        let schedule = KeySchedule::new(key.as_slice(), sender.transcript(), receiver.transcript());
+        // only the subkeys are used from here on
+        key.as_mut_slice().zeroize();
+        schedule
+    });
+
+    // without the authenticators this is how the server learns whether the password was right
+    if cfg!(feature = "implicit") {
+        let message = KeyConfirmation::new(&schedule.client_to_server());
+        bytes_sent += sender.send_msg(&message)?;
+        info!("Sent KeyConfirmation");
+    }
+
+    #[cfg(feature = "resumption")]
+    {
+        let new_ticket = recv!(receiver, sender, NewTicket);
+        store_ticket(
+            options.ticket.as_deref(),
+            &new_ticket,
+            &schedule.resumption(),
+        )?;
+    }
+
+    // reading the log back isn't part of the exchange so it doesn't count towards `bytes_sent`,
+    // and a refused log still leaves the rest of the session to read before giving up
+    #[cfg(feature = "event_log")]
+    let events = exchange_event_log(
+        &mut sender,
+        &mut receiver,
+        &schedule,
+        options.download_events,
+    );
+    #[cfg(feature = "timings")]
+    let server_timings = recv!(receiver, sender, PhaseTimings);
+
+    // the server mustn't be left waiting for a frame after the client exits
+    #[cfg(feature = "reliable")]
+    flush(&mut sender, &mut receiver)?;
+    #[cfg(feature = "event_log")]
+    let events = events?;
+
+    log_keys(&schedule);
+    info!("Total bytes sent: {}", bytes_sent);
+    log_stats(receiver.stats());
+    #[cfg(feature = "reliable")]
+    log_reliable_stats(sender.reliable_stats(), receiver.reliable_stats());
+    log_timings(&timings);
+
+    Ok(Session {
+        schedule,
+        resumed: false,
+        bytes_sent,
+        timings,
+        #[cfg(feature = "timings")]
+        server_timings,
+        #[cfg(feature = "event_log")]
+        events,
+    })
+}
+
+/// Ask for the server's event log if `download` is set, or tell the server we don't want it
+///
+/// The server only hands the log to a client holding the session's keys, in implicit mode this is
+/// where a wrong password shows up.
+#[cfg(feature = "event_log")]
+fn exchange_event_log<P: Port>(
+    sender: &mut MsgSender<P>,
+    receiver: &mut MsgReceiver<P>,
+    schedule: &KeySchedule,
+    download: bool,
+) -> Result<Option<DeviceLog>, Error> {
+    if !download {
+        sender.send_msg(&LogRequest::Skip)?;
+        return Ok(None);
+    }
+
+    let message = LogRequest::Download {
+        mac: events::request_mac(&schedule.client_to_server()),
+    };
+    sender.send_msg(&message)?;
+    match recv!(receiver, sender, LogResponse) {
+        LogResponse::Events {
+            recorded,
+            events,
+            mac,
+        } => {
+            if !events::verify_response(&schedule.server_to_client(), recorded, events, &mac) {
+                error!("The server's event log failed its integrity check");
+                return Err(Error::Integrity);
+            }
+            let events = events::decode(events)
+                .collect::<Result<_, _>>()
+                .map_err(|e| {
+                    error!("Failed to decode the server's event log - {e}");
+                    Error::UnexpectedMessage
+                })?;
+            Ok(Some(DeviceLog { recorded, events }))
+        }
+        LogResponse::Refused => {
+            error!("Server refused the event log, it didn't derive the same keys");
+            Err(Error::AuthFailed)
+        }
+    }
+}
+
+/// Run `f`, adding the time it took to `phase`
+fn timed<T>(timings: &mut PhaseTimings, phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    timings.add(phase, start.elapsed().as_micros() as u64);
+    result
+}
+
+/// Log the time spent computing in each phase, named the same as in the server's logs
+fn log_timings(timings: &PhaseTimings) {
+    for (phase, micros) in timings.iter().filter(|(_, micros)| *micros > 0) {
+        info!("{phase} computation time: {micros}us");
+    }
+    let total = timings.total();
+    info!("Total computation time: {}ms - {}us", total / 1000, total);
+}
+
+/// Log the subkeys the application would use, the raw AuCPace key is never used directly
+fn log_keys(schedule: &KeySchedule) {
+    info!(
+        "Derived client_to_server key: {:02X?}",
+        schedule.client_to_server().as_slice()
+    );
+    info!(
+        "Derived server_to_client key: {:02X?}",
+        schedule.server_to_client().as_slice()
+    );
+}
+
+/// Log a message the server shouldn't have sent at this point of the exchange
+fn unexpected(msg: &impl Debug) -> Error {
+    error!("Received invalid server message {msg:?}");
+    Error::UnexpectedMessage
+}
+
+/// Wait for the server's authenticator
+///
+/// The server stops answering rather than send one when our authenticator is wrong, so the port
+/// timing out means authentication failed.
+fn recv_authenticator<P: Port>(
+    sender: &mut MsgSender<P>,
+    receiver: &mut MsgReceiver<P>,
+) -> Result<[u8; 64], Error> {
+    let mut recv = || -> io::Result<_> {
+        Ok(match recv!(receiver, sender) {
+            ServerMessage::Authenticator(authenticator) => Ok(authenticator),
+            server_message => Err(unexpected(&server_message)),
+        })
+    };
+    match recv() {
+        Ok(authenticator) => authenticator,
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            error!("Server didn't send its authenticator - it rejected ours");
+            Err(Error::AuthFailed)
+        }
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// Check the server speaks the same protocol before starting an exchange with it
+fn exchange_preamble<P: Port>(
+    sender: &mut MsgSender<P>,
+    receiver: &mut MsgReceiver<P>,
+) -> Result<(), Error> {
+    let ours = Preamble::new(FEATURES);
+    sender.send_plain(&ours)?;
+
+    match receiver.recv_plain::<PreambleResponse>()? {
+        Ok(PreambleResponse::Accepted) => {
+            // both ends number their frames from zero again
+            #[cfg(feature = "reliable")]
+            {
+                sender.reset_window();
+                receiver.reset_window();
+            }
+            Ok(())
+        }
+        Ok(PreambleResponse::Mismatch(theirs)) => match ours.check(&theirs) {
+            Err(mismatch) => Err(Error::Preamble(mismatch)),
+            Ok(()) => {
+                error!("Server rejected a preamble matching its own");
+                Err(Error::UnexpectedMessage)
+            }
+        },
+        Err(e) => {
+            error!("Failed to parse the server's answer to our preamble - {e:?}");
+            Err(Error::UnknownServer)
+        }
+    }
+}
+
+fn serial_parity(parity: Parity) -> serialport::Parity {
+    match parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Even => serialport::Parity::Even,
+        Parity::Odd => serialport::Parity::Odd,
+    }
+}
+
+fn serial_stop_bits(stop_bits: StopBits) -> serialport::StopBits {
+    match stop_bits {
+        StopBits::One => serialport::StopBits::One,
+        StopBits::Two => serialport::StopBits::Two,
+    }
+}
+
+/// Reconfigure the serial port, dropping anything sent or received under the old settings
+#[cfg(feature = "baud_negotiation")]
+fn switch_link<P: Port>(
+    serial: &Mutex<P>,
+    receiver: &mut MsgReceiver<P>,
+    link: &LinkConfig,
+) -> io::Result<()> {
+    serial
+        .lock()
+        .expect("Failed to acquire lock for serial port.")
+        .reconfigure(link)?;
+    receiver.clear();
+    Ok(())
+}
+
+/// Ask the server to switch to `target` baud, returns the settings the link ends up with
+///
+/// If the link doesn't work at the new rate both sides fall back to `link`.
+#[cfg(feature = "baud_negotiation")]
+fn negotiate_baud<P: Port>(
+    serial: &Mutex<P>,
+    sender: &mut MsgSender<P>,
+    receiver: &mut MsgReceiver<P>,
+    link: &LinkConfig,
+    target: u32,
+) -> Result<LinkConfig, Error> {
+    sender.send_msg(&BaudRequest { baudrate: target })?;
+    let BaudResponse { baudrate } = recv!(receiver, sender, BaudResponse);
+    if baudrate == link.baudrate {
+        return Ok(*link);
+    }
+
+    thread::sleep(Duration::from_millis(SWITCH_DELAY_MS));
+    let negotiated = link.with_baudrate(baudrate);
+    switch_link(serial, receiver, &negotiated)?;
+
+    let check = LinkCheck {
+        nonce: rand_core::OsRng.next_u32(),
+    };
+    sender.send_msg(&check)?;
+    let deadline = Instant::now() + Duration::from_millis(CHECK_TIMEOUT_MS);
+    #[cfg(not(feature = "reliable"))]
+    let echo = receiver
+        .recv_msg_before::<LinkCheck>(deadline)?
+        .and_then(Result::ok);
+    #[cfg(feature = "reliable")]
+    let echo = recv_echo(sender, receiver, deadline)?;
+    if echo == Some(check) {
+        info!("Switched to {baudrate} baud");
+        return Ok(negotiated);
+    }
+
+    warn!(
+        "Link check at {baudrate} baud failed - falling back to {} baud",
+        link.baudrate
+    );
+    switch_link(serial, receiver, link)?;
+    // give the server time to give up on the new rate as well
+    thread::sleep(Duration::from_millis(CHECK_TIMEOUT_MS));
+    Ok(*link)
+}
+
+/// Wait for the server to echo the link check, acknowledging the echo
+///
+/// Nothing is sent again while switching rates, so anything but the echo or an ACK fails the check.
+#[cfg(all(feature = "baud_negotiation", feature = "reliable"))]
+fn recv_echo<P: Port>(
+    sender: &mut MsgSender<P>,
+    receiver: &mut MsgReceiver<P>,
+    deadline: Instant,
+) -> io::Result<Option<LinkCheck>> {
+    loop {
+        match receiver.recv_msg::<LinkCheck>(Some(deadline))? {
+            Some(Ok(Received::Message {
+                msg: Some(msg),
+                ack,
+            })) => {
+                sender.ack(ack)?;
+                return Ok(Some(msg));
+            }
+            Some(Ok(Received::Message { msg: None, ack })) => sender.ack(ack)?,
+            Some(Ok(Received::Ack(seq))) => sender.acked(seq),
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Wait for the server to acknowledge every frame sent to it, sending them again as needed
+///
+/// Messages from the server are acknowledged but thrown away, there shouldn't be any.
+#[cfg(feature = "reliable")]
+fn flush<P: Port>(sender: &mut MsgSender<P>, receiver: &mut MsgReceiver<P>) -> io::Result<()> {
+    while !sender.is_flushed() {
+        match receiver.recv_msg::<IgnoredAny>(sender.retransmit_deadline())? {
+            None => sender.retransmit()?,
+            Some(Ok(Received::Message { ack, .. } | Received::Duplicate { ack })) => {
+                sender.ack(ack)?
+            }
+            Some(Ok(Received::Gap { expected })) => sender.nak(expected)?,
+            Some(Ok(Received::Ack(seq))) => sender.acked(seq),
+            Some(Ok(Received::Nak(seq))) => sender.nakked(seq)?,
+            Some(Err(_)) => sender.nak(receiver.expected())?,
+        }
+    }
+    Ok(())
+}
+
+/// Log how often the reliable delivery layer had to step in
+#[cfg(feature = "reliable")]
+fn log_reliable_stats(sent: SendStats, received: RecvStats) {
+    info!(
+        "Frames sent again: {} - gave up: {} - duplicate messages: {} - out of order messages: {}",
+        sent.retransmits, sent.give_ups, received.duplicates, received.out_of_order
+    );
+}
+
+/// Milliseconds since the first call, which the reliable delivery layer measures time in
+#[cfg(feature = "reliable")]
+fn now_ms() -> u64 {
+    epoch().elapsed().as_millis() as u64
+}
+
+#[cfg(feature = "reliable")]
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Log the health of the link, anything but zero oversized and corrupt frames points at a bad connection
+fn log_stats(stats: FrameStats) {
+    info!(
+        "Frames received: {} - oversized: {} - corrupt: {} - bad checksum: {} - resend requests: {} - discarded bytes: {}",
+        stats.frames,
+        stats.oversized,
+        stats.corrupt,
+        stats.bad_checksum,
+        stats.resend_requests,
+        stats.discarded_bytes
+    );
+}
+
+/// Encode `msg` in a frame of its own
+fn encode_frame<T: Serialize + ?Sized>(msg: &T, checksum: Option<Checksum>) -> Zeroizing<Vec<u8>> {
+    let mut scratch = Zeroizing::new(vec![0u8; SEND_BUF_LEN]);
+    let mut serialised = Zeroizing::new(vec![0u8; SEND_BUF_LEN]);
+    let len = framing::encode(msg, checksum, &mut scratch, &mut serialised)
+        .expect("Failed to serialise message")
+        .len();
+    serialised.truncate(len);
+    serialised
+}
+
+struct MsgSender<'mtx, P> {
+    mtx: &'mtx Mutex<P>,
+    transcript: Transcript,
+    // the last frame sent, kept in case the server asks for it again
+    last: Zeroizing<Vec<u8>>,
+    fragmenter: Box<Fragmenter<SEND_MSG_LEN>>,
+    #[cfg(feature = "reliable")]
+    window: SendWindow<WINDOW, SEND_BUF_LEN>,
+}
+
+impl<'mtx, P: Port> MsgSender<'mtx, P> {
+    fn new(mtx: &'mtx Mutex<P>) -> Self {
+        Self {
+            mtx,
+            transcript: Transcript::default(),
+            last: Zeroizing::default(),
+            fragmenter: Box::default(),
+            #[cfg(feature = "reliable")]
+            window: SendWindow::new(reliable::Config::DEFAULT),
+        }
+    }
+
+    /// The transcript of every frame sent since the last reset
+    fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    fn reset_transcript(&mut self) {
+        self.transcript = Transcript::default();
+    }
+
+    /// Send a message over the serial port in as many fragments as it takes, returns the bytes sent
+    #[cfg(not(feature = "reliable"))]
+    fn send_msg<T: Serialize>(&mut self, msg: &T) -> Result<usize, Error> {
+        self.fragmenter.start(msg).map_err(Error::Encode)?;
+        let mut sent = 0;
+        while let Some(fragment) = self.fragmenter.next_fragment() {
+            let serialised = encode_frame(&fragment, CHECKSUM);
+            sent += self.send_frame(serialised, CHECKSUM)?;
+        }
+        Ok(sent)
+    }
+
+    /// Send a message over the serial port in as many fragments as it takes and hold on to each
+    /// until the server acknowledges it, returns the bytes sent
+    #[cfg(feature = "reliable")]
+    fn send_msg<T: Serialize>(&mut self, msg: &T) -> Result<usize, Error> {
+        self.fragmenter.start(msg).map_err(Error::Encode)?;
+        let mut sent = 0;
+        while let Some(fragment) = self.fragmenter.next_fragment() {
+            let seq = self
+                .window
+                .next_seq()
+                .expect("Too many frames waiting for an ACK");
+            let mut scratch = Zeroizing::new(vec![0u8; SEND_BUF_LEN]);
+            let mut serialised = Zeroizing::new(vec![0u8; SEND_BUF_LEN]);
+            let len = reliable::encode_data(
+                seq,
+                &fragment,
+                RELIABLE_CHECKSUM,
+                &mut scratch,
+                &mut serialised,
+            )
+            .expect("Failed to serialise message")
+            .len();
+            serialised.truncate(len);
+            trace!(
+                "Sending {} byte long message {seq} - {:02X?}",
+                serialised.len(),
+                serialised.as_slice()
+            );
+            self.transcript.update(&serialised);
+            self.window
+                .push(&serialised, now_ms())
+                .expect("Frame fits in the send window");
+            self.write(&serialised)?;
+            sent += len;
+        }
+        Ok(sent)
+    }
+
+    /// Send a message without a checksum, for the preamble whose framing never changes
+    fn send_plain<T: Serialize>(&mut self, msg: &T) -> io::Result<usize> {
+        self.send_frame(encode_frame(msg, None), None)
+    }
+
+    fn send_frame(
+        &mut self,
+        serialised: Zeroizing<Vec<u8>>,
+        checksum: Option<Checksum>,
+    ) -> io::Result<usize> {
+        let len = serialised.len();
+        trace!(
+            "Sending {} byte long message - {:02X?}",
+            serialised.len(),
+            serialised.as_slice()
+        );
+        self.transcript.update(&serialised);
+        self.write(&serialised)?;
+
+        // only frames with a checksum can be asked for again
+        self.last = if checksum.is_some() {
+            serialised
+        } else {
+            Zeroizing::default()
+        };
+        Ok(len)
+    }
+
+    /// Send the last frame again, it is already in the transcript
+    #[cfg(not(feature = "reliable"))]
+    fn resend_last(&self) -> io::Result<()> {
+        if self.last.is_empty() {
+            warn!("There is no frame to send again");
+            return Ok(());
+        }
+        self.write(&self.last)
+    }
+
+    /// Ask the server to send its last frame again
+    #[cfg(not(feature = "reliable"))]
+    fn request_resend(&self) -> io::Result<()> {
+        let Some(checksum) = CHECKSUM else {
+            return Ok(());
+        };
+        let mut buf = [0u8; framing::RESEND_REQUEST_MAX_LEN];
+        self.write(framing::resend_request(checksum, &mut buf))
+    }
+
+    /// Acknowledge every message from the server up to and including `seq`
+    #[cfg(feature = "reliable")]
+    fn ack(&self, seq: u8) -> io::Result<()> {
+        let mut buf = [0u8; reliable::CONTROL_MAX_LEN];
+        self.write(reliable::encode_ack(seq, RELIABLE_CHECKSUM, &mut buf))
+    }
+
+    /// Ask the server for every message from `seq` onwards again
+    #[cfg(feature = "reliable")]
+    fn nak(&self, seq: u8) -> io::Result<()> {
+        let mut buf = [0u8; reliable::CONTROL_MAX_LEN];
+        self.write(reliable::encode_nak(seq, RELIABLE_CHECKSUM, &mut buf))
+    }
+
+    /// The server received every frame up to and including `seq`
+    #[cfg(feature = "reliable")]
+    fn acked(&mut self, seq: u8) {
+        self.window.ack(seq);
+    }
+
+    /// The server wants every frame from `seq` onwards again
+    #[cfg(feature = "reliable")]
+    fn nakked(&mut self, seq: u8) -> io::Result<()> {
+        self.window.nak(seq);
+        self.retransmit()
+    }
+
+    /// Send every frame whose retransmit timer has run out
+    #[cfg(feature = "reliable")]
+    fn retransmit(&mut self) -> io::Result<()> {
+        loop {
+            match self.window.due(now_ms()) {
+                Ok(Some(frame)) => {
+                    let frame = Zeroizing::new(frame.to_vec());
+                    self.write(&frame)?;
+                }
+                Ok(None) => break,
+                Err(GaveUp { seq }) => {
+                    error!("Gave up on frame {seq} - the server isn't responding");
+                    self.window.reset();
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// When the next frame is due to be sent again, `None` if nothing is waiting for an ACK
+    #[cfg(feature = "reliable")]
+    fn retransmit_deadline(&self) -> Option<Instant> {
+        let deadline = self.window.deadline()?;
+        Some(epoch() + Duration::from_millis(deadline))
+    }
+
+    /// Whether the server has acknowledged every frame sent to it
+    #[cfg(feature = "reliable")]
+    fn is_flushed(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    /// Forget every frame waiting for an ACK and number frames from zero again
+    #[cfg(feature = "reliable")]
+    fn reset_window(&mut self) {
+        self.window.reset();
+    }
+
+    /// Counters for every frame sent since the client started
+    #[cfg(feature = "reliable")]
+    fn reliable_stats(&self) -> SendStats {
+        self.window.stats()
+    }
+
+    fn write(&self, frame: &[u8]) -> io::Result<()> {
+        self.mtx
+            .lock()
+            .expect("Failed to acquire serial port mutex")
+            .write_all(frame)?;
+        thread::sleep(Duration::from_millis(10));
+        Ok(())
+    }
+}
+
+struct MsgReceiver<'mtx, P> {
+    frames: FrameReceiver<RECV_BUF_LEN>,
+    mtx: &'mtx Mutex<P>,
+    transcript: Transcript,
+    reassembler: Box<Reassembler<RECV_MSG_LEN>>,
+    #[cfg(feature = "reliable")]
+    window: RecvWindow,
+}
+
+impl<'mtx, P: Port> MsgReceiver<'mtx, P> {
+    fn new(mtx: &'mtx Mutex<P>) -> Self {
+        Self {
+            frames: FrameReceiver::new(),
+            mtx,
+            transcript: Transcript::default(),
+            reassembler: Box::default(),
+            #[cfg(feature = "reliable")]
+            window: RecvWindow::new(),
+        }
+    }
+
+    /// The transcript of every message received since the last reset
+    fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    fn reset_transcript(&mut self) {
+        self.transcript = Transcript::default();
+    }
+
+    /// Counters for every frame received since the client started
+    fn stats(&self) -> FrameStats {
+        self.frames.stats()
+    }
+
+    /// Counters for every message received since the client started
+    #[cfg(feature = "reliable")]
+    fn reliable_stats(&self) -> RecvStats {
+        self.window.stats()
+    }
+
+    /// The sequence number of the next message from the server
+    #[cfg(feature = "reliable")]
+    fn expected(&self) -> u8 {
+        self.window.expected()
+    }
+
+    /// Expect the server to number its frames from zero again
+    #[cfg(feature = "reliable")]
+    fn reset_window(&mut self) {
+        self.window.reset();
+    }
+
+    /// Throw away everything received but not yet handed out as a message
+    #[cfg(feature = "baud_negotiation")]
+    fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Read off the wire until there is a whole frame buffered, or until `deadline` passes
+    ///
+    /// Without a deadline the port timing out is an error, the server has stopped talking to us.
+    fn wait_for_frame(&mut self, deadline: Option<Instant>) -> io::Result<bool> {
+        // acquire a handle to the serial port
+        let mut serial = self
+            .mtx
+            .lock()
+            .expect("Failed to acquire lock for serial port.");
+
+        // more than one frame can be read at once so there may already be one buffered
+        while self.frames.next_frame_len().is_none() {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(false);
+            }
+
+            // read as much as we can off the wire
+            let spare = self.frames.spare();
+            let count = match serial.read(spare) {
+                Ok(count) => count,
+                Err(e) if deadline.is_some() && e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    error!("Failed to read from serial port - {e}");
+                    return Err(e);
+                }
+            };
+            if count == 0 {
+                continue;
+            }
+
+            // log that we managed to read some data
+            trace!("Read {} bytes - {:02X?}", count, &spare[..count]);
+
+            if !self.frames.fill(count) {
+                warn!("Receive buffer filled without a frame ending - discarding up to the next frame");
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Receive a message, or `None` if its last fragment doesn't arrive before `deadline`
+    #[cfg(all(feature = "baud_negotiation", not(feature = "reliable")))]
+    fn recv_msg_before<'a, T: Deserialize<'a>>(
+        &'a mut self,
+        deadline: Instant,
+    ) -> io::Result<Option<Result<T, FrameError>>> {
+        loop {
+            if !self.wait_for_frame(Some(deadline))? {
+                return Ok(None);
+            }
+            match self.push_fragment() {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => return Ok(Some(Err(e))),
+            }
+        }
+        Ok(Some(self.reassembler.decode().map_err(FrameError::Message)))
+    }
+
+    /// Receive a message in as many fragments as it takes, checking their checksums if there are any
+    #[cfg(not(feature = "reliable"))]
+    fn recv_msg<'a, T: Deserialize<'a>>(&'a mut self) -> io::Result<Result<T, FrameError>> {
+        loop {
+            self.wait_for_frame(None)?;
+            match self.push_fragment() {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => return Ok(Err(e)),
+            }
+        }
+        Ok(self.reassembler.decode().map_err(FrameError::Message))
+    }
+
+    /// Add the buffered frame to the message being reassembled, returns whether it is now whole
+    #[cfg(not(feature = "reliable"))]
+    fn push_fragment(&mut self) -> Result<bool, FrameError> {
+        let fragment: Fragment = Self::decode(&mut self.frames, &mut self.transcript, CHECKSUM)?;
+        self.reassembler
+            .push(fragment)
+            .map_err(FrameError::Fragment)
+    }
+
+    /// Receive the next frame of the reliable delivery layer, `None` if `deadline` passes first
+    ///
+    /// `Received::Message` only holds the message once its last fragment has arrived.
+    #[cfg(feature = "reliable")]
+    fn recv_msg<'a, T: Deserialize<'a>>(
+        &'a mut self,
+        deadline: Option<Instant>,
+    ) -> io::Result<Option<Result<Received<Option<T>>, FrameError>>> {
+        if !self.wait_for_frame(deadline)? {
+            return Ok(None);
+        }
+
+        let transcript = &mut self.transcript;
+        let window = &mut self.window;
+        let parsed = self.frames.decode_frame(|frame| {
+            // duplicates and control frames stay out of the transcript, the server has those already
+            let mut updated = transcript.clone();
+            updated.update(frame);
+
+            let parsed = window.decode::<Fragment>(frame, RELIABLE_CHECKSUM);
+            if let Ok(Received::Message { .. }) = parsed {
+                *transcript = updated;
+            }
+
+            parsed
+        });
+
+        Ok(Some(match parsed.expect("a complete frame is buffered") {
+            Ok(Received::Message { msg, ack }) => match self.reassembler.push(msg) {
+                Ok(true) => self
+                    .reassembler
+                    .decode()
+                    .map(|msg| Received::Message {
+                        msg: Some(msg),
+                        ack,
+                    })
+                    .map_err(FrameError::Message),
+                Ok(false) => Ok(Received::Message { msg: None, ack }),
+                Err(e) => Err(FrameError::Fragment(e)),
+            },
+            Ok(Received::Duplicate { ack }) => Ok(Received::Duplicate { ack }),
+            Ok(Received::Gap { expected }) => Ok(Received::Gap { expected }),
+            Ok(Received::Ack(seq)) => Ok(Received::Ack(seq)),
+            Ok(Received::Nak(seq)) => Ok(Received::Nak(seq)),
+            Err(e) => Err(e),
+        }))
+    }
+
+    /// Receive a message without a checksum, for the preamble whose framing never changes
+    fn recv_plain<'a, T: Deserialize<'a>>(&'a mut self) -> io::Result<Result<T, FrameError>> {
+        self.wait_for_frame(None)?;
+        Ok(Self::decode(&mut self.frames, &mut self.transcript, None))
+    }
+
+    /// Decode the buffered frame as a `T`, taking the fields so the reassembler can still be used
+    fn decode<'a, T: Deserialize<'a>>(
+        frames: &'a mut FrameReceiver<RECV_BUF_LEN>,
+        transcript: &mut Transcript,
+        checksum: Option<Checksum>,
+    ) -> Result<T, FrameError> {
+        let parsed = frames.decode_frame(|frame| {
+            // only frames which parse go in the transcript, the sender can't know about the others
+            let mut updated = transcript.clone();
+            updated.update(frame);
+
+            // parse the result
+            let parsed = framing::decode::<T>(frame, checksum);
+            if parsed.is_ok() {
+                *transcript = updated;
+            }
+
+            parsed
+        });
+
+        parsed.expect("a complete frame is buffered")
+    }
+}
+
+fn parse_params(ps: ParamsString) -> Result<Params, Error> {
+    fn get<T: FromStr>(ps: &ParamsString, name: &str) -> Option<T> {
+        ps.get_str(name)?.parse().ok()
+    }
+    let (Some(ln), Some(r), Some(p)) = (get(&ps, "ln"), get(&ps, "r"), get(&ps, "p")) else {
+        error!("Missing parameter in ParamsString {ps}");
+        return Err(Error::Params);
+    };
+    let len = Params::RECOMMENDED_LEN;
+
+    Params::new(ln, r, p, len).map_err(|e| {
+        error!("Unusable scrypt parameters - {e}");
+        Error::Params
+    })
+}
+
+/// Load the ticket stored at `path`, removing it as tickets can only be used once
+#[cfg(feature = "resumption")]
+fn take_ticket(path: &Path) -> Option<StoredTicket> {
+    let data = Zeroizing::new(fs::read(path).ok()?);
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Failed to remove used ticket {} - {e}", path.display());
+    }
+
+    let stored: StoredTicket = match postcard::from_bytes(&data) {
+        Ok(stored) => stored,
+        Err(e) => {
+            warn!("Ignoring unreadable ticket {} - {e}", path.display());
+            return None;
+        }
+    };
+
+    if unix_time() >= stored.expires {
+        info!("Stored ticket has expired");
+        return None;
+    }
+
+    Some(stored)
+}
+
+/// Store a ticket received from the server alongside its resumption secret
+#[cfg(feature = "resumption")]
+fn store_ticket(
+    path: Option<&Path>,
+    new_ticket: &NewTicket,
+    secret: &[u8; resumption::SECRET_LEN],
+) -> io::Result<()> {
+    let Some(path) = path else {
+        debug!("No ticket file given, discarding resumption ticket");
+        return Ok(());
+    };
+
+    let stored = StoredTicket {
+        ticket: new_ticket.ticket.to_vec(),
+        secret: *secret,
+        expires: unix_time() + u64::from(new_ticket.lifetime_secs),
+    };
+    let data = Zeroizing::new(postcard::to_stdvec(&stored).map_err(io::Error::other)?);
+    fs::write(path, data.as_slice())?;
+    info!("Stored resumption ticket in {}", path.display());
+
+    Ok(())
+}
+
+#[cfg(feature = "resumption")]
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
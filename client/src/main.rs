@@ -1,59 +1,17 @@
 use anyhow::{anyhow, Result};
-use aucpace::{Client, ServerMessage};
 use clap::Parser;
-use scrypt::password_hash::ParamsString;
-use scrypt::{Params, Scrypt};
-use serialport::{SerialPort, SerialPortType};
-use std::io::{Read, Write};
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
-use std::{io, thread};
+use client::{Options, Session};
+use protocol::link::{self, LinkConfig, Parity, StopBits};
+use protocol::sas::ShortAuthString;
+use serialport::SerialPortType;
+use std::{io, mem};
+use zeroize::Zeroizing;
 
 #[allow(unused)]
 use tracing::{debug, error, info, trace, warn};
 
-const USART_BAUD: u32 = 28800;
-const RECV_BUF_LEN: usize = 1024;
-const K1: usize = 16;
-
-#[cfg(feature = "static_ssid")]
-const SSID: [u8; 32] = [
-    60, 173, 56, 252, 74, 141, 171, 146, 102, 169, 149, 169, 158, 106, 87, 232, 220, 141, 251, 73,
-    39, 130, 105, 184, 93, 87, 195, 23, 246, 158, 85, 226,
-];
-
-/// function like macro to wrap sending data over the serial port, returns the number of bytes sent
-macro_rules! send {
-    ($serial_mtx:ident, $msg:ident) => {{
-        let serialised = postcard::to_stdvec_cobs(&$msg).expect("Failed to serialise message");
-        trace!("Sending {} byte long message - {serialised:02X?}", serialised.len());
-        $serial_mtx
-            .lock()
-            .expect("Failed to acquire serial port mutex")
-            .write_all(&serialised)
-            .expect("Failed to write message to serial");
-        thread::sleep(Duration::from_millis(10));
-        serialised.len()
-    }};
-}
-
-/// function like macro to wrap receiving data over the serial port
-macro_rules! recv {
-    ($recvr:ident) => {
-        loop {
-            let parsed = $recvr.recv_msg();
-            match parsed {
-                Ok(msg) => {
-                    debug!("Parsed message - {msg:?}");
-                    break msg;
-                }
-                Err(e) => {
-                    error!("Failed to parse message - {e:?}");
-                }
-            };
-        }
-    };
-}
+#[cfg(feature = "resumption")]
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -66,6 +24,23 @@ struct Args {
     #[arg(long)]
     list_ports: bool,
 
+    /// Baud rate to open the port at, the server has to be built with the same
+    #[arg(long, default_value_t = link::DEFAULT_BAUDRATE)]
+    baud: u32,
+
+    /// Parity of the serial link: none, even or odd
+    #[arg(long, default_value_t = Parity::None)]
+    parity: Parity,
+
+    /// Stop bits of the serial link: 1 or 2
+    #[arg(long, default_value_t = StopBits::One)]
+    stop_bits: StopBits,
+
+    /// Baud rate to ask the server to switch to for the session
+    #[cfg(feature = "baud_negotiation")]
+    #[arg(long)]
+    negotiate_baud: Option<u32>,
+
     /// The maximum log level
     #[arg(long, default_value_t = tracing::Level::INFO)]
     log_level: tracing::Level,
@@ -81,10 +56,26 @@ struct Args {
     /// The Password to perform the exchange with
     #[arg(long, short)]
     password: String,
+
+    /// File to load a resumption ticket from and store new tickets in
+    #[cfg(feature = "resumption")]
+    #[arg(long)]
+    ticket: Option<PathBuf>,
+
+    /// Download the server's event log at the end of the session and print it
+    #[cfg(feature = "event_log")]
+    #[arg(long)]
+    events: bool,
+
+    /// Print the time spent computing in each phase, next to the server's with the timings feature
+    #[arg(long)]
+    timings: bool,
 }
 
 fn main() -> Result<()> {
-    let args = Args::try_parse()?;
+    let mut args = Args::try_parse()?;
+    // keep the password out of the logs and wipe it once we're done with it
+    let password = Zeroizing::new(mem::take(&mut args.password));
 
     // setup the logger
     tracing_subscriber::fmt()
@@ -110,237 +101,79 @@ fn main() -> Result<()> {
     // open the serial port connection
     let port_name = args
         .port
+        .as_deref()
         .ok_or_else(|| anyhow!("Must supply a USB port."))?;
-    let serial = Mutex::new({
-        serialport::new(port_name, USART_BAUD)
-            .timeout(Duration::from_millis(500))
-            .open()?
-    });
-    let mut receiver = MsgReceiver::new(&serial);
-    info!("Opened serial port connection.");
-
-    // start the client
-    let mut base_client = Client::new(rand_core::OsRng);
-    let mut bytes_sent = 0;
-
-    let user = args.username.as_str();
-    let pass = args.password.as_str();
-    if !args.skip_register {
-        #[cfg(not(feature = "strong"))]
-        let message = base_client
-            .register_alloc(user.as_bytes(), pass, Params::recommended(), Scrypt)
-            .map_err(|e| anyhow!(e))?;
-
-        #[cfg(feature = "strong")]
-        let message = base_client
-            .register_alloc_strong(user.as_bytes(), pass, Params::recommended(), Scrypt)
-            .map_err(|e| anyhow!(e))?;
-
-        let _ = send!(serial, message);
-        info!(
-            "Registered as {user}:{pass} for {}",
-            if cfg!(feature = "strong") {
-                "Strong AuCPace"
-            } else {
-                "AuCPace"
-            }
-        );
-    }
-
-    info!("Starting AuCPace");
-    let start = Instant::now();
-    // ===== SSID Establishment =====
-    #[cfg(feature = "static_ssid")]
-    let client = {
-        let client = base_client.begin_prestablished_ssid(SSID).unwrap();
-        info!("Began from static SSID={:02X?}", SSID);
-        client
-    };
-
-    #[cfg(not(feature = "static_ssid"))]
-    let client = {
-        let (client, message) = base_client.begin();
-        bytes_sent += send!(serial, message);
-
-        let server_message = recv!(receiver);
-        let client = if let ServerMessage::Nonce(server_nonce) = server_message {
-            client.agree_ssid(server_nonce)
-        } else {
-            panic!("Received invalid server message {:?}", server_message);
-        };
-        info!("Agreed on SSID");
-        client
+    let link = LinkConfig {
+        baudrate: args.baud,
+        parity: args.parity,
+        stop_bits: args.stop_bits,
     };
-
-    // ===== Augmentation Layer =====
-    #[cfg(not(feature = "strong"))]
-    let (client, message) = {
-        info!("Sending message: Username");
-        client.start_augmentation(user.as_bytes(), pass.as_bytes())
-    };
-    #[cfg(feature = "strong")]
-    let (client, message) = {
-        info!("Sending message: Strong Username");
-        client.start_augmentation_strong(user.as_bytes(), pass.as_bytes(), &mut rand_core::OsRng)
-    };
-    bytes_sent += send!(serial, message);
-
-    let mut server_message = recv!(receiver);
-    #[cfg(not(feature = "strong"))]
-    let client = if let ServerMessage::AugmentationInfo {
-        x_pub,
-        salt,
-        pbkdf_params,
-        ..
-    } = server_message
-    {
-        info!("Received Augmentation info");
-        let params = parse_params(pbkdf_params)?;
-        client
-            .generate_cpace_alloc(x_pub, &salt, params, Scrypt)
-            .expect("Failed to generate CPace step data")
-    } else {
-        panic!("Received invalid server message {:?}", server_message);
-    };
-
-    #[cfg(feature = "strong")]
-    let client = if let ServerMessage::StrongAugmentationInfo {
-        x_pub,
-        blinded_salt,
-        pbkdf_params,
-        ..
-    } = server_message
-    {
-        info!("Received Strong Augmentation info");
-        let params = parse_params(pbkdf_params)?;
-        client
-            .generate_cpace_alloc(x_pub, blinded_salt, params, Scrypt)
-            .expect("Failed to generate CPace step data")
-    } else {
-        panic!("Received invalid server message {:?}", server_message);
-    };
-
-    // ===== CPace substep =====
-    let ci = "Server-USART2-Client-SerialPort";
-    let (client, message) = client.generate_public_key(ci, &mut rand_core::OsRng);
-    bytes_sent += send!(serial, message);
-    info!("Sent PublicKey");
-
-    server_message = recv!(receiver);
-    let ServerMessage::PublicKey(server_pubkey) = server_message else {
-        panic!("Received invalid server message {:?}", server_message);
-    };
-    let key = if cfg!(feature = "implicit") {
-        client.implicit_auth(server_pubkey)
-            .map_err(|e| anyhow!(e))?
-    } else {
-        let (client, message) = client.receive_server_pubkey(server_pubkey)
-            .map_err(|e| anyhow!(e))?;
-
-        // ===== Explicit Mutual Auth =====
-        bytes_sent += send!(serial, message);
-        info!("Sent Authenticator");
-
-        server_message = recv!(receiver);
-        if let ServerMessage::Authenticator(server_authenticator) = server_message {
-            client
-                .receive_server_authenticator(server_authenticator)
-                .expect("Failed Explicit Mutual Authentication")
-        } else {
-            panic!("Received invalid server message {:?}", server_message);
-        }
+    let mut serial = client::open_serial(port_name, &link)?;
+    info!("Opened serial port connection at {} baud.", link.baudrate);
+
+    let options = Options {
+        #[cfg(feature = "baud_negotiation")]
+        link,
+        #[cfg(feature = "baud_negotiation")]
+        negotiate_baud: args.negotiate_baud,
+        #[cfg(feature = "resumption")]
+        ticket: args.ticket.take(),
+        #[cfg(feature = "event_log")]
+        download_events: args.events,
+        ..Options::default()
     };
+    if !args.skip_register {
+        client::register(&mut serial, &args.username, &password, &options)?;
+    }
+    let session = client::authenticate(&mut serial, &args.username, &password, &options)?;
 
-    info!("Derived final key: {:02X?}", key.as_slice());
-    info!("Total bytes sent: {}", bytes_sent);
-    info!(
-        "Derived final key in {}ms",
-        Instant::now().duration_since(start).as_millis()
+    // for the operator to compare against the server
+    println!(
+        "Short authentication string: {}",
+        ShortAuthString::new(&session.schedule)
     );
+    if args.timings {
+        print_timings(&session);
+    }
+    #[cfg(feature = "event_log")]
+    if let Some(log) = &session.events {
+        print_events(log);
+    }
 
     Ok(())
 }
 
-struct MsgReceiver<'mtx> {
-    buf: [u8; RECV_BUF_LEN],
-    idx: usize,
-    mtx: &'mtx Mutex<Box<dyn SerialPort>>,
-    reset_pos: Option<usize>,
-}
+/// Print the time each side spent computing in each phase, in milliseconds
+fn print_timings(session: &Session) {
+    #[cfg(feature = "timings")]
+    let server = Some(session.server_timings);
+    #[cfg(not(feature = "timings"))]
+    let server: Option<protocol::phase::PhaseTimings> = None;
 
-impl<'mtx> MsgReceiver<'mtx> {
-    fn new(mtx: &'mtx Mutex<Box<dyn SerialPort>>) -> Self {
-        Self {
-            buf: [0u8; 1024],
-            idx: 0,
-            mtx,
-            reset_pos: None,
-        }
-    }
-
-    fn recv_msg(&mut self) -> postcard::Result<ServerMessage<'_, K1>> {
-        // reset the state
-        // copy all the data we read after the 0 byte to the start of the self.buffer
-        if let Some(zi) = self.reset_pos {
-            self.buf.copy_within(zi + 1..self.idx, 0);
-            self.idx = self.idx.saturating_sub(zi + 1);
-            self.reset_pos = None;
-        }
-
-        // acquire a handle to the serial port
-        let mut serial = self
-            .mtx
-            .lock()
-            .expect("Failed to acquire lock for serial port.");
-
-        loop {
-            // read as much as we can off the wire
-            let count = serial
-                .read(&mut self.buf[self.idx..])
-                .expect("Failed to read from serial port.");
-            let zero_idx = if count == 0 {
-                continue;
-            } else {
-                // log that we managed to read some data
-                trace!(
-                    "Read {} bytes - {:02X?}",
-                    count,
-                    &self.buf[self.idx..self.idx + count]
-                );
-
-                // update state
-                self.idx += count;
-
-                // calculate the index of zero in the self.buffer
-                // it is tempting to optimise this to just what is read but more than one packet can
-                // be read at once so the whole buffer needs to be searched to allow for this behaviour
-                let zero_idx = self.buf[..self.idx].iter().position(|x| *x == 0);
-
-                zero_idx
-            };
-
-            let Some(zi) = zero_idx else {
-                if self.idx == RECV_BUF_LEN {
-                    self.idx = 0;
-                    warn!("Weird state encountered - filled entire self.buffer without finding message.");
-                }
-
-                continue;
-            };
+    let ms = |micros: Option<u64>| match micros {
+        Some(micros) => format!("{:.3}", micros as f64 / 1000.0),
+        None => String::from("-"),
+    };
+    let row = |name: &str, client: u64, server: Option<u64>| {
+        println!("{name:<20} {:>12} {:>12}", ms(Some(client)), ms(server));
+    };
 
-            self.reset_pos = Some(zi);
-            // parse the result
-            break postcard::from_bytes_cobs::<ServerMessage<K1>>(&mut self.buf[..=zi]);
-        }
+    println!("{:<20} {:>12} {:>12}", "phase", "client ms", "server ms");
+    for (phase, micros) in session.timings.iter() {
+        row(&phase.to_string(), micros, server.map(|t| t.get(phase)));
     }
+    row("total", session.timings.total(), server.map(|t| t.total()));
 }
 
-fn parse_params(ps: ParamsString) -> Result<Params> {
-    const MSG: &str = "Missing parameter in ParamsString";
-    let ln = ps.get_str("ln").ok_or_else(|| anyhow!(MSG))?.parse()?;
-    let r = ps.get_str("r").ok_or_else(|| anyhow!(MSG))?.parse()?;
-    let p = ps.get_str("p").ok_or_else(|| anyhow!(MSG))?.parse()?;
-    let len = Params::RECOMMENDED_LEN;
-
-    Ok(Params::new(ln, r, p, len)?)
+/// Print the server's event log, noting how many events it had to overwrite
+#[cfg(feature = "event_log")]
+fn print_events(log: &client::DeviceLog) {
+    let dropped = (log.recorded as usize).saturating_sub(log.events.len());
+    println!(
+        "Server event log - {} events since boot, {dropped} overwritten",
+        log.recorded
+    );
+    for event in &log.events {
+        println!("{event}");
+    }
 }
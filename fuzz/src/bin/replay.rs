@@ -0,0 +1,59 @@
+//! Run a fuzz target's harness over saved inputs without libFuzzer, so crashes found by
+//! `cargo fuzz` can be reproduced and debugged on stable Rust
+//!
+//! Usage: `cargo run --bin replay -- <target> <file or directory>...`
+
+use std::path::Path;
+use std::{env, fs, process};
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let Some(name) = args.next() else {
+        usage();
+    };
+    let Some(&(_, harness)) = fuzz::TARGETS.iter().find(|(target, _)| *target == name) else {
+        eprintln!("Unknown target {name}");
+        usage();
+    };
+
+    let mut count = 0;
+    for arg in args {
+        let path = Path::new(&arg);
+        if path.is_dir() {
+            let entries = fs::read_dir(path).unwrap_or_else(|e| fail(path, e));
+            for entry in entries {
+                let entry = entry.unwrap_or_else(|e| fail(path, e));
+                count += replay(&entry.path(), harness);
+            }
+        } else {
+            count += replay(path, harness);
+        }
+    }
+
+    println!("Replayed {count} inputs through {name}");
+}
+
+fn replay(path: &Path, harness: fuzz::Harness) -> usize {
+    if path.is_dir() {
+        return 0;
+    }
+
+    let data = fs::read(path).unwrap_or_else(|e| fail(path, e));
+    println!("Running {}", path.display());
+    harness(&data);
+    1
+}
+
+fn fail(path: &Path, e: std::io::Error) -> ! {
+    eprintln!("Failed to read {} - {e}", path.display());
+    process::exit(1);
+}
+
+fn usage() -> ! {
+    let targets: Vec<_> = fuzz::TARGETS.iter().map(|(name, _)| *name).collect();
+    eprintln!(
+        "usage: replay <{}> <file or directory>...",
+        targets.join("|")
+    );
+    process::exit(2);
+}
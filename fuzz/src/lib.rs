@@ -0,0 +1,141 @@
+//! Harnesses shared by the fuzz targets and the `replay` binary
+//!
+//! Each harness takes the raw bytes from the fuzzer so a crashing input can be replayed on
+//! stable Rust without libFuzzer, see `src/bin/replay.rs`.
+
+use arbitrary::{Arbitrary, Unstructured};
+use aucpace::{ClientMessage, ServerMessage};
+use protocol::fragment::{Fragment, Reassembler};
+use protocol::framing::{self, Checksum, FrameReceiver, DELIMITER};
+
+/// Must match the client and the server
+pub const K1: usize = 16;
+
+/// Must match the client and the server
+pub const RECV_BUF_LEN: usize = 1024;
+
+/// Must match the server, the client reassembles shorter messages than it
+pub const RECV_MSG_LEN: usize = 4096;
+
+/// Every framing the client and the server can agree on
+const CHECKSUMS: [Option<Checksum>; 3] = [None, Some(Checksum::Crc16), Some(Checksum::Crc32)];
+
+/// A harness taking the raw input from the fuzzer
+pub type Harness = fn(&[u8]);
+
+/// Every harness, by the name of its fuzz target
+pub const TARGETS: &[(&str, Harness)] = &[
+    ("frame_receiver", frame_receiver),
+    ("decode_client_message", decode_client_message),
+    ("decode_server_message", decode_server_message),
+];
+
+/// Feed arbitrarily chunked reads through a `FrameReceiver`, checking it against a simple model
+///
+/// Every frame handed out is also decoded as a `Fragment`, with and without a checksum, and
+/// reassembled the same as the server and the client do with the frames they receive. Each
+/// whole message is then decoded as a `ClientMessage` and a `ServerMessage`.
+pub fn frame_receiver(data: &[u8]) {
+    let Ok(reads) = Vec::<Vec<u8>>::arbitrary_take_rest(Unstructured::new(data)) else {
+        return;
+    };
+
+    let mut frames = FrameReceiver::<RECV_BUF_LEN>::new();
+    let mut model = Model::default();
+    let mut reassemblers: [Reassembler<RECV_MSG_LEN>; CHECKSUMS.len()] = Default::default();
+
+    for read in &reads {
+        // a read can be larger than the space left, the rest then arrives in the next read
+        let mut read = read.as_slice();
+        while !read.is_empty() {
+            let spare = frames.spare();
+            assert!(!spare.is_empty(), "no space left to read into");
+            let count = spare.len().min(read.len());
+            spare[..count].copy_from_slice(&read[..count]);
+
+            assert_eq!(frames.fill(count), model.fill(&read[..count]));
+            read = &read[count..];
+
+            while let Some(frame) = frames.take_frame() {
+                let expected = model.take_frame().expect("model has no frame buffered");
+                assert_eq!(&*frame, expected.as_slice());
+                assert_eq!(
+                    frame.iter().position(|b| *b == DELIMITER),
+                    Some(frame.len() - 1)
+                );
+
+                for (checksum, reassembler) in CHECKSUMS.into_iter().zip(&mut reassemblers) {
+                    let mut frame = frame.to_vec();
+                    let Ok(fragment) = framing::decode::<Fragment>(&mut frame, checksum) else {
+                        continue;
+                    };
+                    if reassembler.push(fragment) == Ok(true) {
+                        assert!(reassembler.message().len() <= RECV_MSG_LEN);
+                        let _ = reassembler.decode::<ClientMessage<K1>>();
+                        let _ = reassembler.decode::<ServerMessage<K1>>();
+                    }
+                }
+            }
+            assert_eq!(model.take_frame(), None);
+            assert_eq!(frames.buffered(), model.pending.as_slice());
+        }
+    }
+}
+
+/// Decode arbitrary bytes as a `ClientMessage`, with and without COBS framing
+///
+/// Anything which decodes must encode again, and decode to the same encoding.
+pub fn decode_client_message(data: &[u8]) {
+    if let Ok(message) = postcard::from_bytes::<ClientMessage<K1>>(data) {
+        let encoded = postcard::to_stdvec(&message).expect("decoded message failed to encode");
+        let decoded = postcard::from_bytes::<ClientMessage<K1>>(&encoded)
+            .expect("encoded message failed to decode");
+        assert_eq!(postcard::to_stdvec(&decoded).ok(), Some(encoded));
+    }
+    let _ = postcard::from_bytes_cobs::<ClientMessage<K1>>(&mut data.to_vec());
+}
+
+/// Decode arbitrary bytes as a `ServerMessage`, with and without COBS framing
+///
+/// Anything which decodes must encode again, and decode to the same encoding.
+pub fn decode_server_message(data: &[u8]) {
+    if let Ok(message) = postcard::from_bytes::<ServerMessage<K1>>(data) {
+        let encoded = postcard::to_stdvec(&message).expect("decoded message failed to encode");
+        let decoded = postcard::from_bytes::<ServerMessage<K1>>(&encoded)
+            .expect("encoded message failed to decode");
+        assert_eq!(postcard::to_stdvec(&decoded).ok(), Some(encoded));
+    }
+    let _ = postcard::from_bytes_cobs::<ServerMessage<K1>>(&mut data.to_vec());
+}
+
+/// The obviously correct version of `FrameReceiver`, using an unbounded `Vec`
+#[derive(Default)]
+struct Model {
+    pending: Vec<u8>,
+    discarding: bool,
+}
+
+impl Model {
+    fn fill(&mut self, data: &[u8]) -> bool {
+        let mut kept = true;
+        for b in data {
+            if self.discarding {
+                self.discarding = *b != DELIMITER;
+                continue;
+            }
+
+            self.pending.push(*b);
+            if self.pending.len() == RECV_BUF_LEN && !self.pending.contains(&DELIMITER) {
+                self.pending.clear();
+                self.discarding = true;
+                kept = false;
+            }
+        }
+        kept
+    }
+
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        let zi = self.pending.iter().position(|b| *b == DELIMITER)?;
+        Some(self.pending.drain(..=zi).collect())
+    }
+}
@@ -0,0 +1,277 @@
+//! An in-memory stand-in for the board and the serial link, to run the client against the server
+//!
+//! The server runs on a thread of its own as it would on the board. The client talks to it through
+//! a `Port` which times out like the serial port does when the server goes quiet.
+
+use aucpace::AuCPaceServer;
+use core::convert::Infallible;
+use embassy_futures::{block_on, yield_now};
+use embedded_io_async::ErrorType;
+use embedded_storage::nor_flash::{self, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use rand_chacha::ChaCha8Rng;
+use rand_core::SeedableRng;
+use server_core::config::LOCKOUT_THRESHOLD;
+use server_core::database::SingleUserDatabase;
+use server_core::handshake::{Handshake, Session, SessionError};
+use server_core::lockout::LockoutStore;
+use server_core::transport::{Clock, Link};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "baud_negotiation")]
+use protocol::link::LinkConfig;
+
+/// How long the client waits for a byte before giving up, the same as on the serial port
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+const SECTOR: usize = 256;
+
+/// Stands in for the secret the board generates on its first boot
+const DEVICE_SECRET: [u8; 32] = [7; 32];
+
+type Buffer = Arc<Mutex<VecDeque<u8>>>;
+
+/// A server running its side of the protocol on a thread of its own
+pub struct Server {
+    thread: JoinHandle<Vec<Result<Session, SessionError>>>,
+}
+
+impl Server {
+    /// Start a server which waits for a user to register and then runs `sessions` sessions
+    pub fn spawn(sessions: usize) -> (Self, ClientPort) {
+        let to_client = Buffer::default();
+        let to_server = Buffer::default();
+        let port = ClientPort {
+            tx: to_server.clone(),
+            rx: to_client.clone(),
+        };
+
+        let thread = thread::spawn(move || {
+            let clock = StdClock(Instant::now());
+            let database = SingleUserDatabase::default()
+                .with_lockout_threshold(LOCKOUT_THRESHOLD)
+                .with_fake_secret(&DEVICE_SECRET);
+            let lockout = LockoutStore::new(RamFlash::new(), 0, SECTOR as u32);
+            let mut handshake = Handshake::new(
+                Duplex {
+                    to_client: to_client.clone(),
+                    to_server: to_server.clone(),
+                },
+                ServerTx(to_client),
+                ServerRx(to_server),
+                clock,
+                AuCPaceServer::new(ChaCha8Rng::seed_from_u64(1)),
+                database,
+                lockout,
+                &DEVICE_SECRET,
+                &mut ChaCha8Rng::seed_from_u64(2),
+            );
+
+            block_on(handshake.register());
+            (0..sessions)
+                .map(|_| block_on(handshake.session()))
+                .collect()
+        });
+
+        (Self { thread }, port)
+    }
+
+    /// Wait for the server to finish its sessions, returns how each of them ended
+    pub fn join(self) -> Vec<Result<Session, SessionError>> {
+        self.thread.join().expect("the server panicked")
+    }
+}
+
+/// The client's end of the link
+#[derive(Clone)]
+pub struct ClientPort {
+    tx: Buffer,
+    rx: Buffer,
+}
+
+impl Read for ClientPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        loop {
+            let count = take(&self.rx, buf);
+            if count > 0 {
+                return Ok(count);
+            }
+            if start.elapsed() >= READ_TIMEOUT {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            thread::sleep(Duration::from_micros(100));
+        }
+    }
+}
+
+impl Write for ClientPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.lock().unwrap().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Without baud rate negotiation every `Read + Write` transport is a `Port` already
+#[cfg(feature = "baud_negotiation")]
+impl client::Port for ClientPort {
+    /// The link doesn't have a baud rate, so there is nothing to change
+    fn reconfigure(&mut self, _link: &LinkConfig) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Move as much as fits in `buf` out of `buffer`, returns how much that was
+fn take(buffer: &Buffer, buf: &mut [u8]) -> usize {
+    let mut pending = buffer.lock().unwrap();
+    let count = buf.len().min(pending.len());
+    for (b, p) in buf.iter_mut().zip(pending.drain(..count)) {
+        *b = p;
+    }
+    count
+}
+
+/// Let the client's thread run before polling again, `block_on` has nothing to wake it up
+async fn idle() {
+    thread::sleep(Duration::from_micros(100));
+    yield_now().await;
+}
+
+/// The server's end of the link
+struct Duplex {
+    #[cfg_attr(not(feature = "baud_negotiation"), allow(dead_code))]
+    to_client: Buffer,
+    #[cfg_attr(not(feature = "baud_negotiation"), allow(dead_code))]
+    to_server: Buffer,
+}
+
+struct ServerTx(Buffer);
+
+struct ServerRx(Buffer);
+
+impl Link for Duplex {
+    type Tx = ServerTx;
+    type Rx = ServerRx;
+
+    /// The link doesn't have a baud rate, so this just hands out the same halves again
+    #[cfg(feature = "baud_negotiation")]
+    unsafe fn reopen(&mut self, _config: &LinkConfig) -> (ServerTx, ServerRx) {
+        (
+            ServerTx(self.to_client.clone()),
+            ServerRx(self.to_server.clone()),
+        )
+    }
+}
+
+impl ErrorType for ServerTx {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Write for ServerTx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        self.0.lock().unwrap().extend(buf);
+        Ok(buf.len())
+    }
+}
+
+impl ErrorType for ServerRx {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for ServerRx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        loop {
+            let count = take(&self.0, buf);
+            if count > 0 {
+                return Ok(count);
+            }
+            idle().await;
+        }
+    }
+}
+
+/// Time since the server started
+#[derive(Clone)]
+struct StdClock(Instant);
+
+impl Clock for StdClock {
+    fn now_micros(&self) -> u64 {
+        self.0.elapsed().as_micros() as u64
+    }
+
+    async fn wait_until_ms(&self, deadline_ms: u64) {
+        while self.now_ms() < deadline_ms {
+            idle().await;
+        }
+    }
+}
+
+/// Flash held in RAM for the lockout records, which like the real thing can only clear bits
+struct RamFlash(Vec<u8>);
+
+impl RamFlash {
+    fn new() -> Self {
+        Self(vec![0xFF; SECTOR])
+    }
+}
+
+#[derive(Debug)]
+struct FlashError(NorFlashErrorKind);
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl nor_flash::ErrorType for RamFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let start = offset as usize;
+        let Some(data) = self.0.get(start..start + bytes.len()) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        let Some(data) = self.0.get_mut(from as usize..to as usize) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        data.fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let start = offset as usize;
+        let Some(data) = self.0.get_mut(start..start + bytes.len()) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        for (d, b) in data.iter_mut().zip(bytes) {
+            *d &= *b;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! Runs the handshake tests for the other combinations of the features
+//!
+//! Cargo builds each crate once per run with the features everything in the run asked for, so the
+//! other combinations need a run of their own. They share a target directory apart from the
+//! workspace's, which keeps them from throwing away each other's builds and the workspace's.
+#![cfg(not(any(
+    feature = "strong",
+    feature = "partial",
+    feature = "implicit",
+    feature = "static_ssid",
+    feature = "resumption",
+    feature = "baud_negotiation",
+    feature = "crc16",
+    feature = "crc32",
+    feature = "reliable",
+    feature = "event_log",
+    feature = "timings"
+)))]
+
+use std::path::Path;
+use std::process::Command;
+
+/// The variants of AuCPace, which change the messages both sides send
+const FEATURES: [&str; 4] = ["strong", "partial", "implicit", "static_ssid"];
+
+/// The extensions around AuCPace, on their own and in the combinations most likely to trip over
+/// each other
+///
+/// Every combination of them would be too many runs, and some of them can't be built together:
+/// `resumption` needs explicit authentication, `reliable` needs one of the checksums and only one
+/// checksum can be enabled.
+const EXTENSIONS: [&str; 10] = [
+    "resumption",
+    "baud_negotiation",
+    "crc16",
+    "crc32",
+    "reliable,crc16",
+    "event_log",
+    "timings",
+    "implicit,event_log",
+    "strong,resumption,baud_negotiation,reliable,crc32,event_log,timings",
+    "implicit,static_ssid,baud_negotiation,reliable,crc16,event_log,timings",
+];
+
+/// The features switched on by the bits of `combination`
+fn features(combination: u32) -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| combination & 1 << i != 0)
+        .map(|(_, feature)| *feature)
+        .collect()
+}
+
+/// The features as cargo takes them for the integration crate
+fn cargo_features(features: &[&str]) -> String {
+    features
+        .iter()
+        .map(|feature| format!("integration/{feature}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[test]
+fn every_combination_of_the_features_passes() {
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("features");
+    // this run covers the combination without any of them
+    let combinations = (1..1 << FEATURES.len()).map(features).chain(
+        EXTENSIONS
+            .iter()
+            .map(|features| features.split(',').collect()),
+    );
+    let failed: Vec<_> = combinations
+        .map(|features| cargo_features(&features))
+        .filter(|features| {
+            let output = Command::new(env!("CARGO"))
+                .args(["test", "--no-fail-fast"])
+                .args(["-p", "integration", "-p", "server-core"])
+                .args(["--tests", "--features", features])
+                .env("CARGO_TARGET_DIR", &target)
+                .current_dir(env!("CARGO_MANIFEST_DIR"))
+                .output()
+                .expect("failed to run cargo");
+            if !output.status.success() {
+                eprintln!("{}", String::from_utf8_lossy(&output.stdout));
+                eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+            }
+            !output.status.success()
+        })
+        .collect();
+
+    assert!(failed.is_empty(), "failed with --features {failed:?}");
+}
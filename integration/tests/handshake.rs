@@ -0,0 +1,306 @@
+//! Full exchanges between the client and the server over an in-memory link
+//!
+//! Both sides are built with this crate's features, so the tests cover one combination of them at
+//! a time. `tests/features.rs` runs them for the other combinations of the AuCPace features.
+
+use client::{Error, Options};
+use integration::{ClientPort, Server};
+use protocol::key_schedule::KeySchedule;
+use protocol::phase::Phase;
+use scrypt::Params;
+use server_core::config::LOCKOUT_THRESHOLD;
+use server_core::handshake::{Session, SessionError};
+
+#[cfg(feature = "event_log")]
+use protocol::events::EventKind;
+#[cfg(feature = "resumption")]
+use std::fs;
+#[cfg(feature = "resumption")]
+use std::path::{Path, PathBuf};
+
+const USER: &str = "alice";
+const PASSWORD: &str = "correct horse battery staple";
+
+/// Cheap parameters so the tests don't spend their time hashing passwords
+fn params() -> Params {
+    Params::new(4, 8, 1, 32).unwrap()
+}
+
+/// Options for a server on the in-memory link
+// which fields there are to update depends on the features
+#[allow(clippy::needless_update)]
+fn options() -> Options {
+    Options {
+        params: params(),
+        ..Options::default()
+    }
+}
+
+/// Read back the server's event log at the end of the session too
+#[cfg(feature = "event_log")]
+fn with_events() -> Options {
+    Options {
+        download_events: true,
+        ..options()
+    }
+}
+
+/// Keep the resumption ticket in `path` between sessions
+#[cfg(feature = "resumption")]
+fn with_ticket(path: &Path) -> Options {
+    Options {
+        ticket: Some(path.to_path_buf()),
+        ..options()
+    }
+}
+
+/// A ticket file of the test's own, the tests run in parallel
+#[cfg(feature = "resumption")]
+fn ticket_file(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("aucpace-{}-{test}.ticket", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Register `USER` with the server on the other end of `port`
+fn register(port: &ClientPort) {
+    client::register(port.clone(), USER, PASSWORD, &options()).unwrap();
+}
+
+fn same_keys(client: &KeySchedule, server: &KeySchedule) -> bool {
+    client.client_to_server().as_slice() == server.client_to_server().as_slice()
+        && client.server_to_client().as_slice() == server.server_to_client().as_slice()
+}
+
+/// How a session ended for the client and for the server
+type Outcome = (
+    Result<client::Session, Error>,
+    Result<Session, SessionError>,
+);
+
+/// Register `USER` and then try a session as `username` with `password`
+fn attempt(username: &str, password: &str) -> Outcome {
+    let (server, port) = Server::spawn(1);
+    register(&port);
+    let client = client::authenticate(port, username, password, &options());
+    (client, server.join().remove(0))
+}
+
+/// The server gives up without answering, so the client times out waiting for its authenticator
+#[cfg(not(feature = "implicit"))]
+fn assert_rejected((client, server): Outcome) {
+    assert!(matches!(client, Err(Error::AuthFailed)));
+    assert!(matches!(server, Err(SessionError::AuthFailed)));
+}
+
+/// Neither side can tell, but nothing sent under the client's keys can be read with the server's
+#[cfg(feature = "implicit")]
+fn assert_rejected((client, server): Outcome) {
+    let (client, server) = (client.unwrap(), server.unwrap());
+    assert!(!same_keys(&client.schedule, &server.schedule));
+}
+
+#[test]
+fn registered_users_agree_on_the_keys() {
+    let (server, port) = Server::spawn(1);
+    register(&port);
+    let client = client::authenticate(port, USER, PASSWORD, &options()).unwrap();
+    let server = server.join().remove(0).unwrap();
+
+    assert!(same_keys(&client.schedule, &server.schedule));
+    assert!(!client.resumed);
+    assert!(!server.resumed);
+}
+
+#[test]
+fn sessions_can_follow_each_other() {
+    let (server, port) = Server::spawn(3);
+    register(&port);
+    let clients: Vec<_> = (0..3)
+        .map(|_| client::authenticate(port.clone(), USER, PASSWORD, &options()).unwrap())
+        .collect();
+
+    let servers = server.join();
+    for (client, server) in clients.iter().zip(servers) {
+        assert!(same_keys(&client.schedule, &server.unwrap().schedule));
+    }
+    // every session derives keys of its own
+    assert!(!same_keys(&clients[0].schedule, &clients[1].schedule));
+}
+
+#[test]
+fn both_sides_time_the_phases() {
+    let (server, port) = Server::spawn(1);
+    register(&port);
+    let client = client::authenticate(port, USER, PASSWORD, &options()).unwrap();
+    let server = server.join().remove(0).unwrap();
+
+    // only the client hashes the password
+    assert!(client.timings.get(Phase::Pbkdf) > 0);
+    assert_eq!(server.timings.get(Phase::Pbkdf), 0);
+    assert!(server.timings.total() > 0);
+    #[cfg(feature = "timings")]
+    assert_eq!(client.server_timings, server.timings);
+}
+
+#[test]
+fn wrong_passwords_are_rejected() {
+    assert_rejected(attempt(USER, "hunter2"));
+}
+
+#[test]
+fn unknown_users_are_rejected() {
+    assert_rejected(attempt("mallory", PASSWORD));
+}
+
+/// In implicit mode the server counts every session in which the client's key confirmation fails
+#[test]
+fn repeated_failures_are_throttled() {
+    let (server, port) = Server::spawn(5);
+    register(&port);
+    // the first few failures are free, the fourth sets the clock running
+    for _ in 0..4 {
+        let failed = client::authenticate(port.clone(), USER, "hunter2", &options());
+        #[cfg(not(feature = "implicit"))]
+        assert!(matches!(failed, Err(Error::AuthFailed)));
+        #[cfg(feature = "implicit")]
+        assert!(failed.is_ok());
+    }
+    let throttled = client::authenticate(port, USER, PASSWORD, &options());
+    server.join();
+
+    assert!(matches!(throttled, Err(Error::Throttled { retry_after }) if !retry_after.is_zero()));
+}
+
+#[cfg(feature = "event_log")]
+#[test]
+fn authenticated_clients_can_read_the_event_log() {
+    let (server, port) = Server::spawn(1);
+    register(&port);
+    let session = client::authenticate(port, USER, PASSWORD, &with_events()).unwrap();
+    server.join();
+
+    let log = session.events.unwrap();
+    assert_eq!(log.recorded as usize, log.events.len());
+    assert_eq!(log.events[0].kind, EventKind::Registered);
+    let last = log.events.last().unwrap();
+    assert_eq!((last.session, last.kind), (1, EventKind::AuthSucceeded));
+}
+
+#[cfg(feature = "event_log")]
+#[test]
+fn failed_attempts_show_up_in_the_event_log() {
+    let (server, port) = Server::spawn(2);
+    register(&port);
+    let failed = client::authenticate(port.clone(), USER, "hunter2", &with_events());
+    assert!(matches!(failed, Err(Error::AuthFailed)));
+    let session = client::authenticate(port, USER, PASSWORD, &with_events()).unwrap();
+    server.join();
+
+    let log = session.events.unwrap();
+    assert!(log
+        .events
+        .iter()
+        .any(|event| event.session == 1 && event.kind == EventKind::AuthFailed));
+}
+
+#[cfg(feature = "resumption")]
+#[test]
+fn tickets_resume_the_session() {
+    let path = ticket_file("round-trip");
+    let (server, port) = Server::spawn(2);
+    register(&port);
+    let full = client::authenticate(port.clone(), USER, PASSWORD, &with_ticket(&path)).unwrap();
+    assert!(path.exists());
+    let resumed = client::authenticate(port, USER, PASSWORD, &with_ticket(&path)).unwrap();
+    let servers = server.join();
+
+    assert!(!full.resumed);
+    assert!(resumed.resumed);
+    let server = servers.into_iter().nth(1).unwrap().unwrap();
+    assert!(server.resumed);
+    assert!(same_keys(&resumed.schedule, &server.schedule));
+    assert!(!same_keys(&full.schedule, &resumed.schedule));
+    // the used ticket was swapped for a fresh one
+    assert!(path.exists());
+    fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "resumption")]
+#[test]
+fn replayed_tickets_are_rejected() {
+    let path = ticket_file("replay");
+    let (server, port) = Server::spawn(3);
+    register(&port);
+    client::authenticate(port.clone(), USER, PASSWORD, &with_ticket(&path)).unwrap();
+    let used = fs::read(&path).unwrap();
+    let resumed = client::authenticate(port.clone(), USER, PASSWORD, &with_ticket(&path)).unwrap();
+    assert!(resumed.resumed);
+
+    // present the ticket which was just redeemed again
+    fs::write(&path, used).unwrap();
+    let replayed = client::authenticate(port, USER, PASSWORD, &with_ticket(&path)).unwrap();
+    let server = server.join().remove(2).unwrap();
+
+    assert!(!replayed.resumed);
+    assert!(!server.resumed);
+    assert!(same_keys(&replayed.schedule, &server.schedule));
+    fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "resumption")]
+#[test]
+fn rejected_tickets_fall_back_to_a_full_handshake() {
+    let path = ticket_file("forged");
+    let (server, port) = Server::spawn(2);
+    register(&port);
+    client::authenticate(port.clone(), USER, PASSWORD, &with_ticket(&path)).unwrap();
+
+    // the file holds the ticket's length and then the ticket, flip a bit of its tag
+    let mut stored = fs::read(&path).unwrap();
+    let tag = usize::from(stored[0]);
+    stored[tag] ^= 1;
+    fs::write(&path, stored).unwrap();
+    let session = client::authenticate(port, USER, PASSWORD, &with_ticket(&path)).unwrap();
+    let server = server.join().remove(1).unwrap();
+
+    assert!(!session.resumed);
+    assert!(!server.resumed);
+    assert!(same_keys(&session.schedule, &server.schedule));
+    // the full handshake handed out a ticket to replace the rejected one
+    assert!(path.exists());
+    fs::remove_file(path).unwrap();
+}
+
+/// Confirming the keys clears the client's earlier failures, in implicit mode too
+#[test]
+fn the_right_password_clears_the_failures() {
+    let (server, port) = Server::spawn(8);
+    register(&port);
+    // three failures are free, without the success in between the second run would be throttled
+    for _ in 0..2 {
+        for _ in 0..3 {
+            let failed = client::authenticate(port.clone(), USER, "hunter2", &options());
+            #[cfg(not(feature = "implicit"))]
+            assert!(matches!(failed, Err(Error::AuthFailed)));
+            #[cfg(feature = "implicit")]
+            assert!(failed.is_ok());
+        }
+        client::authenticate(port.clone(), USER, PASSWORD, &options()).unwrap();
+    }
+    server.join();
+}
+
+#[test]
+fn the_right_password_never_locks_the_account() {
+    let sessions = LOCKOUT_THRESHOLD as usize + 1;
+    let (server, port) = Server::spawn(sessions);
+    register(&port);
+    let clients: Vec<_> = (0..sessions)
+        .map(|_| client::authenticate(port.clone(), USER, PASSWORD, &options()).unwrap())
+        .collect();
+
+    for (client, server) in clients.iter().zip(server.join()) {
+        assert!(same_keys(&client.schedule, &server.unwrap().schedule));
+    }
+}
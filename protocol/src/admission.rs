@@ -0,0 +1,13 @@
+//! The server's decision on whether to continue a handshake once it knows the username
+
+use serde::{Deserialize, Serialize};
+
+/// Sent by the server after receiving the username and before any augmentation info
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Admission {
+    /// The handshake continues
+    Proceed,
+
+    /// Too many attempts have failed recently, the client should wait before trying again
+    RetryAfter { millis: u32 },
+}
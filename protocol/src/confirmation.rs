@@ -0,0 +1,50 @@
+//! Key confirmation for implicit mutual authentication
+//!
+//! Without the explicit authenticators the server can't tell from the exchange itself whether the
+//! client knew the password. So in implicit mode the client follows the key schedule with a MAC
+//! under its `client_to_server` subkey, which only a client that derived the same keys can
+//! produce. The server doesn't answer it, so the client still can't tell whether the server
+//! agreed:
+//!
+//! ```text
+//! Client                                     Server
+//!   KeyConfirmation { mac }                ->
+//! ```
+
+use crate::key_schedule::SUBKEY_LEN;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Length of the MAC on a `KeyConfirmation`
+pub const MAC_LEN: usize = 32;
+
+const LABEL: &[u8] = b"key confirmation";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The client's proof that it holds the session's keys
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyConfirmation {
+    pub mac: [u8; MAC_LEN],
+}
+
+fn mac(key: &[u8; SUBKEY_LEN]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take a key of any length");
+    mac.update(LABEL);
+    mac
+}
+
+impl KeyConfirmation {
+    /// Confirm the `client_to_server` subkey
+    pub fn new(key: &[u8; SUBKEY_LEN]) -> Self {
+        Self {
+            mac: mac(key).finalize().into_bytes().into(),
+        }
+    }
+
+    /// Check the MAC against the `client_to_server` subkey in constant time
+    pub fn verify(&self, key: &[u8; SUBKEY_LEN]) -> bool {
+        mac(key).verify_slice(&self.mac).is_ok()
+    }
+}
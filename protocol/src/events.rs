@@ -0,0 +1,185 @@
+//! The events the server keeps a log of, and how an authenticated client reads them back
+//!
+//! Once a session's keys are derived the client either skips the log or asks for it with a MAC
+//! under its `client_to_server` subkey. Only a client holding the session's keys can produce the
+//! MAC, which works the same whether the mutual authentication was explicit or implicit. The
+//! server answers with its events encoded one after another, MAC'd under its `server_to_client`
+//! subkey:
+//!
+//! ```text
+//! Client                                     Server
+//!   LogRequest::Download { mac }           ->
+//!                                          <- LogResponse::Events { recorded, events, mac }
+//! ```
+
+use crate::key_schedule::SUBKEY_LEN;
+use crate::phase::Phase;
+use core::fmt;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Length of the MACs on the request and the response
+pub const MAC_LEN: usize = 32;
+
+/// Longest an encoded `Event` can be, with every varint at its longest
+pub const MAX_EVENT_LEN: usize = 10 + 5 + 1 + 5;
+
+const REQUEST_LABEL: &[u8] = b"event log request";
+const RESPONSE_LABEL: &[u8] = b"event log";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Something which happened on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Milliseconds since the server booted
+    pub at_ms: u64,
+    /// Sessions started since boot when this happened, zero before the first one
+    pub session: u32,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    /// A user registered
+    Registered,
+    /// The server accepted a client's preamble
+    SessionStarted,
+    /// The session got as far as the start of a phase
+    PhaseReached(Phase),
+    /// The client proved it knows the password, or holds a valid ticket
+    AuthSucceeded,
+    /// The client failed to prove it knows the password, or holds a valid ticket
+    AuthFailed,
+    /// The client was told to wait after too many failures
+    Throttled { millis: u32 },
+    /// A message from the client didn't parse
+    ParseError,
+    /// The client stopped answering
+    Timeout,
+}
+
+/// The client's last message of a session when the event log is enabled
+#[derive(Debug, Serialize, Deserialize)]
+pub enum LogRequest {
+    /// The client doesn't want the log
+    Skip,
+    /// Send the log, the MAC proves the client holds the session's keys
+    Download { mac: [u8; MAC_LEN] },
+}
+
+/// The server's answer to a `LogRequest::Download`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum LogResponse<'a> {
+    /// The oldest events still held first, `recorded` counts the ones which have been overwritten too
+    Events {
+        recorded: u32,
+        events: &'a [u8],
+        mac: [u8; MAC_LEN],
+    },
+    /// The request's MAC was wrong
+    Refused,
+}
+
+fn mac(key: &[u8; SUBKEY_LEN], label: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take a key of any length");
+    mac.update(label);
+    mac
+}
+
+/// The MAC on a `LogRequest::Download`, under the `client_to_server` subkey
+pub fn request_mac(key: &[u8; SUBKEY_LEN]) -> [u8; MAC_LEN] {
+    mac(key, REQUEST_LABEL).finalize().into_bytes().into()
+}
+
+/// Check the MAC on a `LogRequest::Download` in constant time
+pub fn verify_request(key: &[u8; SUBKEY_LEN], tag: &[u8; MAC_LEN]) -> bool {
+    mac(key, REQUEST_LABEL).verify_slice(tag).is_ok()
+}
+
+fn response(key: &[u8; SUBKEY_LEN], recorded: u32, events: &[u8]) -> HmacSha256 {
+    let mut mac = mac(key, RESPONSE_LABEL);
+    mac.update(&recorded.to_le_bytes());
+    mac.update(events);
+    mac
+}
+
+/// The MAC on `LogResponse::Events`, under the `server_to_client` subkey
+pub fn response_mac(key: &[u8; SUBKEY_LEN], recorded: u32, events: &[u8]) -> [u8; MAC_LEN] {
+    response(key, recorded, events)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Check the MAC on `LogResponse::Events` in constant time
+pub fn verify_response(
+    key: &[u8; SUBKEY_LEN],
+    recorded: u32,
+    events: &[u8],
+    tag: &[u8; MAC_LEN],
+) -> bool {
+    response(key, recorded, events).verify_slice(tag).is_ok()
+}
+
+/// Encode `events` one after another into `buf`, which needs `MAX_EVENT_LEN` bytes for each
+pub fn encode<'a, 'b>(
+    events: impl Iterator<Item = &'b Event>,
+    buf: &'a mut [u8],
+) -> postcard::Result<&'a [u8]> {
+    let mut len = 0;
+    for event in events {
+        len += postcard::to_slice(event, &mut buf[len..])?.len();
+    }
+    Ok(&buf[..len])
+}
+
+/// Decode the events in a `LogResponse::Events`, stopping at the first which doesn't parse
+pub fn decode(mut events: &[u8]) -> impl Iterator<Item = postcard::Result<Event>> + '_ {
+    core::iter::from_fn(move || {
+        if events.is_empty() {
+            return None;
+        }
+        match postcard::take_from_bytes(events) {
+            Ok((event, rest)) => {
+                events = rest;
+                Some(Ok(event))
+            }
+            Err(e) => {
+                events = &[];
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// Formats as the time since boot, the session and what happened in columns, e.g.
+/// `    12.345s #3    auth failed`
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>6}.{:03}s #{:<4} {}",
+            self.at_ms / 1000,
+            self.at_ms % 1000,
+            self.session,
+            self.kind
+        )
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Registered => f.write_str("user registered"),
+            EventKind::SessionStarted => f.write_str("session started"),
+            EventKind::PhaseReached(phase) => write!(f, "reached {phase}"),
+            EventKind::AuthSucceeded => f.write_str("auth succeeded"),
+            EventKind::AuthFailed => f.write_str("auth failed"),
+            EventKind::Throttled { millis } => write!(f, "throttled for {millis}ms"),
+            EventKind::ParseError => f.write_str("message failed to parse"),
+            EventKind::Timeout => f.write_str("client timed out"),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Splitting messages into fragments small enough to send in a single frame
+//!
+//! Every message after the preamble is postcard encoded on its own and sent as one or more
+//! `Fragment`s of at most `FRAGMENT_LEN` bytes, each in a frame of its own. However long the
+//! message, its frames then fit comfortably in the peer's receive buffer.
+//!
+//! `Fragmenter` and `Reassembler` hold the whole encoded message, so each side bounds the length
+//! of the messages it sends and receives. A message which is too long is an error on either
+//! side rather than a panic or a message cut short. Fragments have to arrive in order, one out of
+//! place drops the message it belongs to.
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// The most bytes of a message carried by one fragment
+pub const FRAGMENT_LEN: usize = 512;
+
+/// The number of fragments a message of `len` bytes is split into
+pub const fn fragment_count(len: usize) -> usize {
+    if len == 0 {
+        1
+    } else {
+        len.div_ceil(FRAGMENT_LEN)
+    }
+}
+
+/// A piece of a message sent in a frame of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fragment<'a> {
+    /// Position of the fragment in its message, wrapping around after 255
+    pub index: u8,
+    /// Whether this is the last fragment of the message
+    pub last: bool,
+    pub data: &'a [u8],
+}
+
+/// Why a message couldn't be split up or put back together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+    /// The encoded message is longer than the `limit` bytes which can be held
+    TooLong { limit: usize },
+    /// Fragment `got` arrived when `expected` was next, so part of the message went missing
+    OutOfOrder { expected: u8, got: u8 },
+    /// The message couldn't be encoded for a reason other than its length
+    Encode(postcard::Error),
+}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::TooLong { limit } => {
+                write!(f, "message is longer than the limit of {limit} bytes")
+            }
+            FragmentError::OutOfOrder { expected, got } => {
+                write!(
+                    f,
+                    "expected fragment {expected} but received fragment {got}"
+                )
+            }
+            FragmentError::Encode(e) => write!(f, "failed to encode message - {e}"),
+        }
+    }
+}
+
+/// Holds the encoding of a message of up to `N` bytes while it is handed out in fragments
+pub struct Fragmenter<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    // start of the next fragment's data
+    pos: usize,
+    index: u8,
+    done: bool,
+}
+
+impl<const N: usize> Fragmenter<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            len: 0,
+            pos: 0,
+            index: 0,
+            done: true,
+        }
+    }
+
+    /// Encode `msg` to be handed out by `next_fragment`, dropping what is left of the last message
+    pub fn start<T: Serialize + ?Sized>(&mut self, msg: &T) -> Result<(), FragmentError> {
+        self.clear();
+        self.len = match postcard::to_slice(msg, &mut self.buf) {
+            Ok(encoded) => encoded.len(),
+            Err(e) => {
+                // a failed encoding can still have written part of the message
+                self.buf.zeroize();
+                return Err(match e {
+                    postcard::Error::SerializeBufferFull => FragmentError::TooLong { limit: N },
+                    e => FragmentError::Encode(e),
+                });
+            }
+        };
+        self.done = false;
+        Ok(())
+    }
+
+    /// The next fragment of the message, `None` once every fragment has been handed out
+    ///
+    /// The message is zeroized by the call returning `None`.
+    pub fn next_fragment(&mut self) -> Option<Fragment<'_>> {
+        if self.done {
+            self.clear();
+            return None;
+        }
+
+        let end = self.len.min(self.pos + FRAGMENT_LEN);
+        let fragment = Fragment {
+            index: self.index,
+            last: end == self.len,
+            data: &self.buf[self.pos..end],
+        };
+        self.pos = end;
+        self.index = self.index.wrapping_add(1);
+        self.done = fragment.last;
+        Some(fragment)
+    }
+
+    fn clear(&mut self) {
+        self.buf[..self.len].zeroize();
+        self.len = 0;
+        self.pos = 0;
+        self.index = 0;
+        self.done = true;
+    }
+}
+
+impl<const N: usize> Default for Fragmenter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Drop for Fragmenter<N> {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
+
+/// Puts the fragments of a message of up to `N` bytes back together
+pub struct Reassembler<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    next: u8,
+    // the message in `buf` is whole
+    complete: bool,
+    // part of the message went missing, so its remaining fragments are dropped
+    skipping: bool,
+}
+
+impl<const N: usize> Reassembler<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            len: 0,
+            next: 0,
+            complete: false,
+            skipping: false,
+        }
+    }
+
+    /// Add the next fragment of a message, returns whether the message is now whole
+    ///
+    /// The first fragment of a message drops anything held of the one before. After an error
+    /// the rest of the message's fragments are dropped without any more errors.
+    pub fn push(&mut self, fragment: Fragment<'_>) -> Result<bool, FragmentError> {
+        if self.complete || fragment.index == 0 {
+            self.reset();
+        }
+        if self.skipping {
+            self.skipping = !fragment.last;
+            return Ok(false);
+        }
+
+        if fragment.index != self.next {
+            let expected = self.next;
+            self.drop_message(fragment.last);
+            return Err(FragmentError::OutOfOrder {
+                expected,
+                got: fragment.index,
+            });
+        }
+
+        let end = self.len + fragment.data.len();
+        if end > N {
+            self.drop_message(fragment.last);
+            return Err(FragmentError::TooLong { limit: N });
+        }
+
+        self.buf[self.len..end].copy_from_slice(fragment.data);
+        self.len = end;
+        self.next = self.next.wrapping_add(1);
+        self.complete = fragment.last;
+        Ok(self.complete)
+    }
+
+    /// The whole message once `push` has returned `true`, empty before then
+    pub fn message(&self) -> &[u8] {
+        if self.complete {
+            &self.buf[..self.len]
+        } else {
+            &[]
+        }
+    }
+
+    /// Decode the whole message as a `T`
+    pub fn decode<'a, T: Deserialize<'a>>(&'a self) -> postcard::Result<T> {
+        postcard::from_bytes(self.message())
+    }
+
+    /// Drop anything held of a message
+    pub fn reset(&mut self) {
+        self.buf[..self.len].zeroize();
+        self.len = 0;
+        self.next = 0;
+        self.complete = false;
+        self.skipping = false;
+    }
+
+    /// Drop the message after an error, skipping its remaining fragments unless `last` was the last
+    fn drop_message(&mut self, last: bool) {
+        self.reset();
+        self.skipping = !last;
+    }
+}
+
+impl<const N: usize> Default for Reassembler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Drop for Reassembler<N> {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
@@ -0,0 +1,395 @@
+//! Splitting the bytes read off the serial link into COBS frames
+//!
+//! Reads off the wire don't line up with frames, a read can hold the end of one frame, several
+//! whole frames and the start of another. `FrameReceiver` holds on to everything read but not yet
+//! handed out as a frame, leaving the actual reading to the client and server.
+//!
+//! A frame which doesn't fit in the buffer can't be decoded, so once the buffer fills without a
+//! delimiter everything up to and including the next delimiter is discarded. The frame after it
+//! is then received as normal, so the link recovers without either side resetting.
+//!
+//! Frames can optionally carry a `Checksum`, which is checked before the message is decoded.
+//! The postcard encoded message is then preceded by a kind byte and followed by the checksum
+//! before being COBS encoded. Besides messages this allows a frame asking the peer to send its
+//! last frame again, so a frame corrupted on the wire can be recovered. Only the last frame is
+//! kept, so this can't recover from a frame which never arrived or a lost resend request.
+
+use crate::fragment::FragmentError;
+use core::fmt;
+use crc::{Crc, CRC_16_IBM_3740, CRC_32_ISO_HDLC};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// The byte which ends every COBS frame
+pub const DELIMITER: u8 = 0;
+
+/// The longest a resend request can be, whichever `Checksum` it uses
+pub const RESEND_REQUEST_MAX_LEN: usize = 8;
+
+// the kinds of frame which carry a checksum, the reliable kinds are followed by a sequence number
+const KIND_MESSAGE: u8 = 0;
+const KIND_RESEND_REQUEST: u8 = 1;
+pub(crate) const KIND_DATA: u8 = 2;
+pub(crate) const KIND_ACK: u8 = 3;
+pub(crate) const KIND_NAK: u8 = 4;
+
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// The checksum appended to frames, both sides have to use the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-16/IBM-3740, also known as CRC-16/CCITT-FALSE
+    Crc16,
+    /// CRC-32/ISO-HDLC, the CRC used by Ethernet and zlib
+    Crc32,
+}
+
+impl Checksum {
+    /// The number of bytes the checksum adds to a frame
+    pub const fn trailer_len(self) -> usize {
+        match self {
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// Write the little endian checksum of `data` into `out`, which must be `trailer_len` bytes long
+    fn write(self, data: &[u8], out: &mut [u8]) {
+        match self {
+            Checksum::Crc16 => out.copy_from_slice(&CRC16.checksum(data).to_le_bytes()),
+            Checksum::Crc32 => out.copy_from_slice(&CRC32.checksum(data).to_le_bytes()),
+        }
+    }
+
+    /// Whether `data` is followed by its checksum
+    fn verify(self, data: &[u8]) -> bool {
+        let Some(split) = data.len().checked_sub(self.trailer_len()) else {
+            return false;
+        };
+        let (data, checksum) = data.split_at(split);
+        let mut expected = [0u8; 4];
+        self.write(data, &mut expected[..self.trailer_len()]);
+        checksum == &expected[..self.trailer_len()]
+    }
+}
+
+/// Why a received frame didn't hold a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame isn't valid COBS or is too short to hold a kind and a checksum
+    Malformed,
+    /// The checksum doesn't match, the frame was corrupted on the wire
+    Checksum,
+    /// The frame arrived intact but doesn't hold the expected message
+    Message(postcard::Error),
+    /// The frame is the peer asking for our last frame again
+    ResendRequest,
+    /// The frame held a fragment which doesn't fit with the message it belongs to
+    Fragment(FragmentError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Malformed => f.write_str("malformed frame"),
+            FrameError::Checksum => f.write_str("frame failed its checksum"),
+            FrameError::Message(e) => write!(f, "frame doesn't hold the expected message - {e}"),
+            FrameError::ResendRequest => f.write_str("peer asked for the last frame again"),
+            FrameError::Fragment(e) => write!(f, "failed to reassemble message - {e}"),
+        }
+    }
+}
+
+/// Counters describing the health of the link
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameStats {
+    /// Frames handed out, whether or not they decoded
+    pub frames: u32,
+    /// Frames discarded for being too long to fit in the buffer
+    pub oversized: u32,
+    /// Frames which failed to decode
+    pub corrupt: u32,
+    /// Corrupt frames caught by their checksum
+    pub bad_checksum: u32,
+    /// Frames the peer asked us to send again
+    pub resend_requests: u32,
+    /// Bytes thrown away while discarding oversized frames
+    pub discarded_bytes: u32,
+}
+
+/// Buffers bytes read off the wire and hands them out one frame at a time
+///
+/// Each frame stays valid until the receiver is next used, at which point it is zeroized and
+/// any bytes read after it are moved to the start of the buffer.
+pub struct FrameReceiver<const N: usize> {
+    buf: [u8; N],
+    idx: usize,
+    // length of the frame handed out last, including its delimiter
+    taken: Option<usize>,
+    // set while throwing away the rest of an oversized frame
+    discarding: bool,
+    stats: FrameStats,
+}
+
+impl<const N: usize> FrameReceiver<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            idx: 0,
+            taken: None,
+            discarding: false,
+            stats: FrameStats {
+                frames: 0,
+                oversized: 0,
+                corrupt: 0,
+                bad_checksum: 0,
+                resend_requests: 0,
+                discarded_bytes: 0,
+            },
+        }
+    }
+
+    /// The counters since this receiver was created
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Drop the frame handed out by the last call to `take_frame`
+    fn release(&mut self) {
+        let Some(len) = self.taken.take() else {
+            return;
+        };
+        self.remove(0, len);
+    }
+
+    /// Remove `len` bytes starting at `start` from the buffered data
+    fn remove(&mut self, start: usize, len: usize) {
+        let end = self.idx;
+        self.buf.copy_within(start + len..end, start);
+        self.idx = end - len;
+        // don't leave the old data lying around after the data we kept
+        self.buf[self.idx..end].zeroize();
+    }
+
+    /// The bytes buffered but not yet handed out as a frame
+    pub fn buffered(&mut self) -> &[u8] {
+        self.release();
+        &self.buf[..self.idx]
+    }
+
+    /// The length of the next complete frame including its delimiter, if one has been read
+    pub fn next_frame_len(&mut self) -> Option<usize> {
+        self.buffered()
+            .iter()
+            .position(|b| *b == DELIMITER)
+            .map(|zi| zi + 1)
+    }
+
+    /// The free space at the end of the buffer for the next read to go into
+    pub fn spare(&mut self) -> &mut [u8] {
+        self.release();
+        &mut self.buf[self.idx..]
+    }
+
+    /// Record that `count` bytes were read into `spare`
+    ///
+    /// Returns `false` if this filled the buffer without completing a frame, everything up to
+    /// and including the frame's delimiter is then discarded, including bytes not yet read.
+    pub fn fill(&mut self, count: usize) -> bool {
+        self.release();
+        let start = self.idx;
+        self.idx = (start + count).min(N);
+
+        if self.discarding {
+            let read = &self.buf[start..self.idx];
+            let (len, found) = match read.iter().position(|b| *b == DELIMITER) {
+                Some(zi) => (zi + 1, true),
+                None => (read.len(), false),
+            };
+            self.stats.discarded_bytes = self.stats.discarded_bytes.saturating_add(len as u32);
+            self.remove(start, len);
+            self.discarding = !found;
+            return true;
+        }
+
+        if self.idx == N && self.next_frame_len().is_none() {
+            self.stats.oversized = self.stats.oversized.saturating_add(1);
+            self.stats.discarded_bytes = self.stats.discarded_bytes.saturating_add(N as u32);
+            self.buf.zeroize();
+            self.idx = 0;
+            self.discarding = true;
+            return false;
+        }
+
+        true
+    }
+
+    /// Throw away everything buffered, for when the link changes and it can only be garbage
+    pub fn clear(&mut self) {
+        self.buf.zeroize();
+        self.idx = 0;
+        self.taken = None;
+        self.discarding = false;
+    }
+
+    /// Take the next complete frame including its delimiter
+    pub fn take_frame(&mut self) -> Option<&mut [u8]> {
+        let len = self.next_frame_len()?;
+        self.taken = Some(len);
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        Some(&mut self.buf[..len])
+    }
+
+    /// Decode the next complete frame with `decode`, counting it as corrupt if that fails
+    pub fn decode_frame<'a, T>(
+        &'a mut self,
+        decode: impl FnOnce(&'a mut [u8]) -> Result<T, FrameError>,
+    ) -> Option<Result<T, FrameError>> {
+        let len = self.next_frame_len()?;
+        self.taken = Some(len);
+        self.stats.frames = self.stats.frames.saturating_add(1);
+
+        let decoded = decode(&mut self.buf[..len]);
+        let counter = match decoded {
+            Ok(_) => None,
+            Err(FrameError::ResendRequest) => Some(&mut self.stats.resend_requests),
+            Err(FrameError::Checksum) => {
+                self.stats.bad_checksum = self.stats.bad_checksum.saturating_add(1);
+                Some(&mut self.stats.corrupt)
+            }
+            Err(_) => Some(&mut self.stats.corrupt),
+        };
+        if let Some(counter) = counter {
+            *counter = counter.saturating_add(1);
+        }
+        Some(decoded)
+    }
+}
+
+/// Encode `msg` into a frame in `out`, with `checksum` if there is one
+///
+/// With a checksum the message is first serialised into `scratch`, which is zeroized afterwards.
+/// Fails if the frame doesn't fit in `out` or the message doesn't fit in `scratch`.
+pub fn encode<'b, T: Serialize + ?Sized>(
+    msg: &T,
+    checksum: Option<Checksum>,
+    scratch: &mut [u8],
+    out: &'b mut [u8],
+) -> postcard::Result<&'b mut [u8]> {
+    let Some(checksum) = checksum else {
+        return postcard::to_slice_cobs(msg, out);
+    };
+
+    encode_checksummed(&[KIND_MESSAGE], msg, checksum, scratch, out)
+}
+
+/// Encode `header` followed by `msg` into a checksummed frame, zeroizing `scratch` afterwards
+pub(crate) fn encode_checksummed<'b, T: Serialize + ?Sized>(
+    header: &[u8],
+    msg: &T,
+    checksum: Checksum,
+    scratch: &mut [u8],
+    out: &'b mut [u8],
+) -> postcard::Result<&'b mut [u8]> {
+    let encoded = encode_body(header, msg, checksum, scratch, out);
+    scratch.zeroize();
+    encoded
+}
+
+fn encode_body<'b, T: Serialize + ?Sized>(
+    header: &[u8],
+    msg: &T,
+    checksum: Checksum,
+    scratch: &mut [u8],
+    out: &'b mut [u8],
+) -> postcard::Result<&'b mut [u8]> {
+    if header.len() > scratch.len() {
+        return Err(postcard::Error::SerializeBufferFull);
+    }
+    let (head, rest) = scratch.split_at_mut(header.len());
+    head.copy_from_slice(header);
+    let len = header.len() + postcard::to_slice(msg, rest)?.len();
+    let end = len + checksum.trailer_len();
+    if end > scratch.len() {
+        return Err(postcard::Error::SerializeBufferFull);
+    }
+    let (body, trailer) = scratch[..end].split_at_mut(len);
+    checksum.write(body, trailer);
+
+    cobs_frame(&scratch[..end], out).ok_or(postcard::Error::SerializeBufferFull)
+}
+
+/// Encode a checksummed frame holding only `header`, which is at most two bytes
+pub(crate) fn encode_control<'b>(
+    header: &[u8],
+    checksum: Checksum,
+    out: &'b mut [u8],
+) -> Option<&'b [u8]> {
+    let mut body = [0u8; 6];
+    let end = header.len() + checksum.trailer_len();
+    let (head, trailer) = body.get_mut(..end)?.split_at_mut(header.len());
+    head.copy_from_slice(header);
+    checksum.write(head, trailer);
+
+    cobs_frame(&body[..end], out).map(|frame| &*frame)
+}
+
+/// COBS encode `body` into `out` followed by the delimiter
+fn cobs_frame<'b>(body: &[u8], out: &'b mut [u8]) -> Option<&'b mut [u8]> {
+    let len = cobs::try_encode(body, out).ok()?;
+    *out.get_mut(len)? = DELIMITER;
+    Some(&mut out[..=len])
+}
+
+/// Write the frame asking the peer to resend its last frame into `out`
+pub fn resend_request(checksum: Checksum, out: &mut [u8; RESEND_REQUEST_MAX_LEN]) -> &[u8] {
+    encode_control(&[KIND_RESEND_REQUEST], checksum, out)
+        .expect("a resend request fits in RESEND_REQUEST_MAX_LEN")
+}
+
+/// Decode a whole frame including its delimiter, checking `checksum` if there is one
+///
+/// The frame is decoded in place so `msg` can borrow from it.
+pub fn decode<'a, T: Deserialize<'a>>(
+    frame: &'a mut [u8],
+    checksum: Option<Checksum>,
+) -> Result<T, FrameError> {
+    let Some(checksum) = checksum else {
+        return postcard::from_bytes_cobs(frame).map_err(FrameError::Message);
+    };
+
+    match decode_checksummed(frame, checksum)? {
+        [KIND_MESSAGE, msg @ ..] => postcard::from_bytes(msg).map_err(FrameError::Message),
+        [KIND_RESEND_REQUEST] => Err(FrameError::ResendRequest),
+        _ => Err(FrameError::Malformed),
+    }
+}
+
+/// Decode a whole checksummed frame in place, returning everything before the checksum
+pub(crate) fn decode_checksummed(
+    frame: &mut [u8],
+    checksum: Checksum,
+) -> Result<&[u8], FrameError> {
+    let len = cobs::decode_in_place(frame).map_err(|_| FrameError::Malformed)?;
+    let body = &frame[..len];
+    if len < 1 + checksum.trailer_len() {
+        return Err(FrameError::Malformed);
+    }
+    if !checksum.verify(body) {
+        return Err(FrameError::Checksum);
+    }
+    Ok(&body[..len - checksum.trailer_len()])
+}
+
+impl<const N: usize> Default for FrameReceiver<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Drop for FrameReceiver<N> {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+    }
+}
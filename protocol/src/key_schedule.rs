@@ -0,0 +1,119 @@
+//! Key schedule deriving labelled subkeys from the key agreed by a session
+//!
+//! The raw key output by AuCPace (or by session resumption) is only ever used as the input
+//! keying material to HKDF-SHA512. The salt is the hash of every frame sent in each direction,
+//! binding the subkeys to the exact messages both sides saw. Each subkey is then expanded with
+//! its own label so keys used for different purposes are independent of each other.
+
+use hkdf::{Hkdf, InvalidLength};
+use sha2::{Digest, Sha512};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Length of the subkeys derived by the key schedule
+pub const SUBKEY_LEN: usize = 32;
+
+/// Length of the hash of a transcript
+pub const TRANSCRIPT_HASH_LEN: usize = 64;
+
+/// Running hash over the frames sent in one direction of a session
+#[derive(Debug, Clone, Default)]
+pub struct Transcript(Sha512);
+
+impl Transcript {
+    /// Add a frame exactly as it appeared on the wire
+    pub fn update(&mut self, frame: &[u8]) {
+        self.0.update((frame.len() as u64).to_le_bytes());
+        self.0.update(frame);
+    }
+
+    /// The hash of every frame added so far
+    pub fn hash(&self) -> [u8; TRANSCRIPT_HASH_LEN] {
+        self.0.clone().finalize().into()
+    }
+}
+
+/// The labels of the subkeys which can be derived from the key schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    /// Protects traffic sent by the client to the server
+    ClientToServer,
+    /// Protects traffic sent by the server to the client
+    ServerToClient,
+    /// Base secret for application specific exported keys
+    Exporter,
+    /// Secret held in a resumption ticket
+    Resumption,
+}
+
+impl Label {
+    fn info(self) -> &'static [u8] {
+        match self {
+            Label::ClientToServer => b"AuCPace client_to_server",
+            Label::ServerToClient => b"AuCPace server_to_client",
+            Label::Exporter => b"AuCPace exporter",
+            Label::Resumption => b"AuCPace resumption",
+        }
+    }
+}
+
+/// Derives labelled subkeys from a session key and the session's transcripts
+///
+/// The pseudorandom key is zeroized when the schedule is dropped, as are the subkeys it hands out.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct KeySchedule {
+    prk: [u8; 64],
+}
+
+impl KeySchedule {
+    /// Create the key schedule for a session
+    ///
+    /// `client_to_server` and `server_to_client` are the transcripts of the frames sent by the
+    /// client and by the server respectively, so each side passes its own send and receive
+    /// transcripts in opposite orders.
+    pub fn new(key: &[u8], client_to_server: &Transcript, server_to_client: &Transcript) -> Self {
+        let mut salt = [0u8; 2 * TRANSCRIPT_HASH_LEN];
+        salt[..TRANSCRIPT_HASH_LEN].copy_from_slice(&client_to_server.hash());
+        salt[TRANSCRIPT_HASH_LEN..].copy_from_slice(&server_to_client.hash());
+
+        let (mut prk, _) = Hkdf::<Sha512>::extract(Some(&salt), key);
+        let schedule = Self { prk: prk.into() };
+        prk.zeroize();
+        schedule
+    }
+
+    fn hkdf(&self) -> Hkdf<Sha512> {
+        Hkdf::from_prk(&self.prk).expect("the PRK is the output of HKDF-SHA512 extract")
+    }
+
+    /// Derive the subkey for `label`
+    pub fn subkey(&self, label: Label) -> Zeroizing<[u8; SUBKEY_LEN]> {
+        let mut subkey = Zeroizing::new([0u8; SUBKEY_LEN]);
+        self.hkdf()
+            .expand(label.info(), subkey.as_mut())
+            .expect("SUBKEY_LEN is a valid HKDF-SHA512 output length");
+        subkey
+    }
+
+    /// The key protecting traffic from the client to the server
+    pub fn client_to_server(&self) -> Zeroizing<[u8; SUBKEY_LEN]> {
+        self.subkey(Label::ClientToServer)
+    }
+
+    /// The key protecting traffic from the server to the client
+    pub fn server_to_client(&self) -> Zeroizing<[u8; SUBKEY_LEN]> {
+        self.subkey(Label::ServerToClient)
+    }
+
+    /// The secret stored in a resumption ticket for this session
+    pub fn resumption(&self) -> Zeroizing<[u8; SUBKEY_LEN]> {
+        self.subkey(Label::Resumption)
+    }
+
+    /// Export keying material for an application specific `context`
+    ///
+    /// Fails if `out` is longer than HKDF-SHA512 can produce.
+    pub fn exporter(&self, context: &[u8], out: &mut [u8]) -> Result<(), InvalidLength> {
+        let exporter = self.subkey(Label::Exporter);
+        Hkdf::<Sha512>::new(None, exporter.as_ref()).expand(context, out)
+    }
+}
@@ -0,0 +1,16 @@
+#![no_std]
+
+//! Wire types and helpers shared between the client and the server which aren't part of AuCPace
+
+pub mod admission;
+pub mod confirmation;
+pub mod events;
+pub mod fragment;
+pub mod framing;
+pub mod key_schedule;
+pub mod link;
+pub mod phase;
+pub mod preamble;
+pub mod reliable;
+pub mod resumption;
+pub mod sas;
@@ -0,0 +1,140 @@
+//! Serial link settings and the in-band negotiation of a faster baud rate
+//!
+//! Both sides start at the same configured `LinkConfig`. With negotiation the client asks for
+//! a baud rate with a `BaudRequest`, the server answers with the rate it will switch to and both
+//! switch after `SWITCH_DELAY_MS`. The client then sends a `LinkCheck` at the new rate which the
+//! server echoes back.
+//!
+//! Either side which doesn't hear from the other within `CHECK_TIMEOUT_MS` of switching goes
+//! back to the configured rate. The client waits out the server's timeout before carrying on so
+//! both sides are back at the configured rate before anything else is sent.
+
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// Baud rate used unless configured otherwise
+pub const DEFAULT_BAUDRATE: u32 = 28800;
+
+/// How long to wait after the `BaudResponse` before switching, so it isn't cut off
+pub const SWITCH_DELAY_MS: u64 = 20;
+
+/// How long either side waits to hear from the other at the new rate before falling back
+pub const CHECK_TIMEOUT_MS: u64 = 500;
+
+/// Parity bit sent with each byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits sent after each byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Settings both ends of the serial link have to agree on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkConfig {
+    pub baudrate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl LinkConfig {
+    /// 8N1 at `DEFAULT_BAUDRATE`
+    pub const DEFAULT: Self = Self {
+        baudrate: DEFAULT_BAUDRATE,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+
+    /// The same settings at a different baud rate
+    pub const fn with_baudrate(self, baudrate: u32) -> Self {
+        Self { baudrate, ..self }
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Sent by the client to ask for a different baud rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaudRequest {
+    pub baudrate: u32,
+}
+
+/// The baud rate the server is switching to, which may differ from the one requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaudResponse {
+    pub baudrate: u32,
+}
+
+/// Sent by the client at the new baud rate and echoed back by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkCheck {
+    pub nonce: u32,
+}
+
+/// Error parsing a `Parity` or `StopBits` from a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unrecognised serial setting")
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+impl FromStr for Parity {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Parity::None),
+            "even" => Ok(Parity::Even),
+            "odd" => Ok(Parity::Odd),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+impl fmt::Display for Parity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Parity::None => "none",
+            Parity::Even => "even",
+            Parity::Odd => "odd",
+        })
+    }
+}
+
+impl FromStr for StopBits {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(StopBits::One),
+            "2" => Ok(StopBits::Two),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+impl fmt::Display for StopBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StopBits::One => "1",
+            StopBits::Two => "2",
+        })
+    }
+}
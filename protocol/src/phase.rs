@@ -0,0 +1,74 @@
+//! The phases of a session, named the same on the client and the server
+//!
+//! Each side adds up the time it spends computing in each phase rather than waiting for the
+//! other, so the two can be put side by side to see where a session's time goes.
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Phase {
+    /// Agreeing on the session identifier, or starting from the static one
+    Ssid,
+    /// The augmentation layer, from the username to the server's salt and public key
+    Augmentation,
+    /// Hashing the password, only the client does this
+    Pbkdf,
+    /// The CPace substep, exchanging public keys
+    CPace,
+    /// Explicit mutual authentication
+    Authenticator,
+    /// Resuming an earlier session with a ticket instead of the phases above, or issuing one
+    Resumption,
+}
+
+impl Phase {
+    /// Every phase in the order a session goes through them
+    pub const ALL: [Phase; 6] = [
+        Phase::Ssid,
+        Phase::Augmentation,
+        Phase::Pbkdf,
+        Phase::CPace,
+        Phase::Authenticator,
+        Phase::Resumption,
+    ];
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Ssid => "SSID establishment",
+            Phase::Augmentation => "augmentation",
+            Phase::Pbkdf => "PBKDF",
+            Phase::CPace => "CPace public key",
+            Phase::Authenticator => "authenticator",
+            Phase::Resumption => "resumption",
+        })
+    }
+}
+
+/// Microseconds spent computing in each phase of a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseTimings {
+    micros: [u64; Phase::ALL.len()],
+}
+
+impl PhaseTimings {
+    pub fn add(&mut self, phase: Phase, micros: u64) {
+        self.micros[phase as usize] += micros;
+    }
+
+    pub fn get(&self, phase: Phase) -> u64 {
+        self.micros[phase as usize]
+    }
+
+    /// Time spent computing over the whole session
+    pub fn total(&self) -> u64 {
+        self.micros.iter().sum()
+    }
+
+    /// Every phase with the time spent in it, in the order a session goes through them
+    pub fn iter(&self) -> impl Iterator<Item = (Phase, u64)> + '_ {
+        Phase::ALL.into_iter().map(|phase| (phase, self.get(phase)))
+    }
+}
@@ -0,0 +1,194 @@
+//! Handshake preamble identifying the protocol spoken by each side
+//!
+//! The client starts both the registration and every session with a `Preamble`, which the
+//! server answers with a `PreambleResponse`. Anything which changes what goes over the wire has
+//! to show up in the preamble, so a mismatched client and server report exactly what differs
+//! instead of failing to parse each other's messages.
+//!
+//! The layout of `Preamble` and `PreambleResponse` must never change, they are how versions
+//! which can't otherwise talk to each other find that out. For the same reason they are always
+//! sent without a checksum.
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Identifies the frame as the start of this protocol
+pub const MAGIC: u32 = u32::from_le_bytes(*b"AuCP");
+
+/// Bumped whenever the order of the exchanges changes
+pub const VERSION: u16 = 1;
+
+/// Channel identifier CPace binds the session keys to
+///
+/// Sides with different identifiers derive different keys without noticing, so it's part of the
+/// fingerprint as well.
+pub const CHANNEL_ID: &str = "Server-USART2-Client-SerialPort";
+
+/// Description of every message sent over the link
+///
+/// Starts with the version of the aucpace crate, which decides what goes in its messages as well
+/// as their shape. Everything after it is derived from the message types by `tests/schema.rs`,
+/// which fails until this is updated after a change to them. Which of the AuCPace variants are
+/// used depends on the features in `FEATURES`, and every message after `Fragment` is carried in
+/// fragments.
+pub const SCHEMA: &str = "\
+    aucpace@237b48d;\
+    ClientMessage{Nonce([u8;16]),Username([u8]),StrongUsername{username:[u8],blinded:[u8;32]},\
+    PublicKey([u8;32]),Authenticator([u8;64]),\
+    Registration{username:[u8],salt:str,params:str,verifier:[u8;32]},\
+    StrongRegistration{username:[u8],secret_exponent:[u8;32],params:str,verifier:[u8;32]}};\
+    ServerMessage{Nonce([u8;16]),\
+    AugmentationInfo{group:str,x_pub:[u8;32],salt:str,pbkdf_params:str},\
+    StrongAugmentationInfo{group:str,x_pub:[u8;32],blinded_salt:[u8;32],pbkdf_params:str},\
+    PublicKey([u8;32]),Authenticator([u8;64])};\
+    Preamble{magic:u32,version:u16,features:u32,fingerprint:u32};\
+    PreambleResponse{Accepted,Mismatch(Preamble)};\
+    Fragment{index:u8,last:bool,data:[u8]};\
+    BaudRequest{baudrate:u32};BaudResponse{baudrate:u32};LinkCheck{nonce:u32};\
+    SessionStart{Full,Resume{ticket:[u8],nonce:[u8;16]}};\
+    ResumeResponse{Accepted{nonce:[u8;16],mac:[u8;32]},Rejected};ResumeFinish{mac:[u8;32]};\
+    NewTicket{ticket:[u8],lifetime_secs:u32};\
+    Admission{Proceed,RetryAfter{millis:u32}};KeyConfirmation{mac:[u8;32]};\
+    LogRequest{Skip,Download{mac:[u8;32]}};\
+    LogResponse{Events{recorded:u32,events:[u8],mac:[u8;32]},Refused};\
+    Event{at_ms:u64,session:u32,kind:EventKind};\
+    EventKind{Registered,SessionStarted,PhaseReached(Phase),AuthSucceeded,AuthFailed,\
+    Throttled{millis:u32},ParseError,Timeout};\
+    Phase{Ssid,Augmentation,Pbkdf,CPace,Authenticator,Resumption};\
+    PhaseTimings{micros:[u64;6]}";
+
+/// Strong AuCPace
+pub const STRONG: u32 = 1 << 0;
+/// Implicit rather than explicit mutual authentication
+pub const IMPLICIT: u32 = 1 << 1;
+/// A static SSID instead of exchanging nonces
+pub const STATIC_SSID: u32 = 1 << 2;
+/// Session resumption tickets
+pub const RESUMPTION: u32 = 1 << 3;
+/// Baud rate negotiation at the start of each session
+pub const BAUD_NEGOTIATION: u32 = 1 << 4;
+/// CRC-16 trailer on every frame after the preamble
+pub const CRC16: u32 = 1 << 5;
+/// CRC-32 trailer on every frame after the preamble
+pub const CRC32: u32 = 1 << 6;
+/// Sequence numbers, ACKs and retransmission of every frame after the preamble
+pub const RELIABLE: u32 = 1 << 7;
+/// Reading the server's event log back at the end of every session
+pub const EVENT_LOG: u32 = 1 << 8;
+/// The server's per phase timings sent to the client at the end of every session
+pub const TIMINGS: u32 = 1 << 9;
+
+/// Every feature which changes the wire format and the name of the cargo feature enabling it
+///
+/// The partially augmented version of the protocol is missing as the client can't tell.
+pub const FEATURES: [(u32, &str); 10] = [
+    (STRONG, "strong"),
+    (IMPLICIT, "implicit"),
+    (STATIC_SSID, "static_ssid"),
+    (RESUMPTION, "resumption"),
+    (BAUD_NEGOTIATION, "baud_negotiation"),
+    (CRC16, "crc16"),
+    (CRC32, "crc32"),
+    (RELIABLE, "reliable"),
+    (EVENT_LOG, "event_log"),
+    (TIMINGS, "timings"),
+];
+
+/// Sent by the client at the start of every exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Preamble {
+    pub magic: u32,
+    pub version: u16,
+    /// The flags from `FEATURES` the sender was built with
+    pub features: u32,
+    /// Truncated hash of `SCHEMA`, `CHANNEL_ID` and `features`
+    pub fingerprint: u32,
+}
+
+/// The server's answer to a `Preamble`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PreambleResponse {
+    Accepted,
+    /// The server's own preamble, for the client to report what differs
+    Mismatch(Preamble),
+}
+
+/// How a peer's preamble differs from ours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The peer isn't speaking this protocol at all
+    Magic,
+    Version {
+        ours: u16,
+        theirs: u16,
+    },
+    Features {
+        ours: u32,
+        theirs: u32,
+    },
+    /// Same version and features but the messages have changed
+    Schema,
+}
+
+impl Preamble {
+    /// The preamble for a side built with `features`
+    pub fn new(features: u32) -> Self {
+        let digest = Sha256::new()
+            .chain_update(SCHEMA)
+            .chain_update(CHANNEL_ID)
+            .chain_update(features.to_le_bytes())
+            .finalize();
+
+        Self {
+            magic: MAGIC,
+            version: VERSION,
+            features,
+            fingerprint: u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]),
+        }
+    }
+
+    /// Check the preamble `theirs` received from the peer against ours
+    pub fn check(&self, theirs: &Preamble) -> Result<(), Mismatch> {
+        if theirs.magic != self.magic {
+            Err(Mismatch::Magic)
+        } else if theirs.version != self.version {
+            Err(Mismatch::Version {
+                ours: self.version,
+                theirs: theirs.version,
+            })
+        } else if theirs.features != self.features {
+            Err(Mismatch::Features {
+                ours: self.features,
+                theirs: theirs.features,
+            })
+        } else if theirs.fingerprint != self.fingerprint {
+            Err(Mismatch::Schema)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Mismatch::Magic => f.write_str("peer is not speaking this protocol"),
+            Mismatch::Version { ours, theirs } => {
+                write!(f, "protocol version {theirs} but we speak version {ours}")
+            }
+            Mismatch::Features { ours, theirs } => {
+                f.write_str("feature mismatch -")?;
+                for (flag, name) in FEATURES {
+                    match (ours & flag != 0, theirs & flag != 0) {
+                        (true, false) => write!(f, " peer lacks {name}")?,
+                        (false, true) => write!(f, " peer has {name}")?,
+                        _ => {}
+                    }
+                }
+                Ok(())
+            }
+            Mismatch::Schema => f.write_str("message schema differs despite matching versions"),
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Optional reliable delivery of messages beneath the protocol
+//!
+//! Every message is sent in a checksummed data frame carrying an 8 bit sequence number. The
+//! receiver acknowledges each data frame with an ACK of the last sequence number it delivered,
+//! so one ACK covers every frame before it. Frames are only delivered in order, a frame after
+//! a missing one is dropped and answered with a NAK of the sequence number expected instead,
+//! as is any frame which fails its checksum.
+//!
+//! `SendWindow` holds on to up to `W` frames which haven't been acknowledged yet. Each is sent
+//! again once its retransmit timer runs out, or straight away on a NAK, and the sender gives up
+//! after `Config::max_retries` attempts. A data frame received a second time because its ACK was
+//! lost is acknowledged again but not delivered twice.
+//!
+//! None of this does any IO or reads a clock, times are milliseconds since any fixed point, so
+//! the same code runs on the firmware and in tests over a simulated link.
+
+use crate::framing::{self, Checksum, FrameError, KIND_ACK, KIND_DATA, KIND_NAK};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// The longest an ACK or NAK can be, whichever `Checksum` it uses
+pub const CONTROL_MAX_LEN: usize = 8;
+
+/// Timing of retransmissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// How long to wait for an ACK before sending a frame again
+    pub retransmit_timeout_ms: u64,
+    /// How many times a frame is sent again before giving up on the link
+    pub max_retries: u8,
+}
+
+impl Config {
+    /// Long enough for a whole receive buffer to be sent at 9600 baud and acknowledged
+    pub const DEFAULT: Self = Self {
+        retransmit_timeout_ms: 1500,
+        max_retries: 5,
+    };
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A frame sent by the reliability layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame<T> {
+    Data {
+        seq: u8,
+        msg: T,
+    },
+    /// Every frame up to and including `seq` has been received
+    Ack(u8),
+    /// Everything from `seq` onwards has to be sent again
+    Nak(u8),
+}
+
+/// What a received frame means for the receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received<T> {
+    /// The next message, which has to be acknowledged with `ack`
+    Message { msg: T, ack: u8 },
+    /// A message which was already delivered, its ACK was lost so send `ack` again
+    Duplicate { ack: u8 },
+    /// A message after a lost one, NAK `expected` to have it sent again
+    Gap { expected: u8 },
+    /// The peer received every frame up to and including this one
+    Ack(u8),
+    /// The peer wants every frame from this one onwards again
+    Nak(u8),
+}
+
+/// Counters for the sending side
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendStats {
+    /// Frames sent again, after a timeout or a NAK
+    pub retransmits: u32,
+    /// Times a frame ran out of retries
+    pub give_ups: u32,
+}
+
+/// Counters for the receiving side
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecvStats {
+    /// Messages received again after they were delivered
+    pub duplicates: u32,
+    /// Messages dropped because one before them went missing
+    pub out_of_order: u32,
+}
+
+/// Why a frame couldn't be added to a `SendWindow`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowError {
+    /// Every slot holds a frame which hasn't been acknowledged
+    Full,
+    /// The frame is longer than the window's slots
+    TooLong,
+}
+
+/// The oldest frame ran out of retries without being acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GaveUp {
+    pub seq: u8,
+}
+
+/// Encode `msg` into a data frame with sequence number `seq`
+///
+/// `scratch` is used the same as by `framing::encode` and zeroized afterwards.
+pub fn encode_data<'b, T: Serialize + ?Sized>(
+    seq: u8,
+    msg: &T,
+    checksum: Checksum,
+    scratch: &mut [u8],
+    out: &'b mut [u8],
+) -> postcard::Result<&'b mut [u8]> {
+    framing::encode_checksummed(&[KIND_DATA, seq], msg, checksum, scratch, out)
+}
+
+/// Encode an ACK of every frame up to and including `seq`
+pub fn encode_ack(seq: u8, checksum: Checksum, out: &mut [u8; CONTROL_MAX_LEN]) -> &[u8] {
+    framing::encode_control(&[KIND_ACK, seq], checksum, out)
+        .expect("an ACK fits in CONTROL_MAX_LEN")
+}
+
+/// Encode a NAK asking for every frame from `seq` onwards again
+pub fn encode_nak(seq: u8, checksum: Checksum, out: &mut [u8; CONTROL_MAX_LEN]) -> &[u8] {
+    framing::encode_control(&[KIND_NAK, seq], checksum, out).expect("a NAK fits in CONTROL_MAX_LEN")
+}
+
+/// Decode a whole frame including its delimiter, leaving the message postcard encoded
+///
+/// The frame is decoded in place so the message borrows from it.
+pub fn decode(frame: &mut [u8], checksum: Checksum) -> Result<Frame<&[u8]>, FrameError> {
+    match framing::decode_checksummed(frame, checksum)? {
+        [KIND_DATA, seq, msg @ ..] => Ok(Frame::Data { seq: *seq, msg }),
+        [KIND_ACK, seq] => Ok(Frame::Ack(*seq)),
+        [KIND_NAK, seq] => Ok(Frame::Nak(*seq)),
+        _ => Err(FrameError::Malformed),
+    }
+}
+
+struct Slot<const N: usize> {
+    frame: [u8; N],
+    len: usize,
+    deadline: u64,
+    retries: u8,
+}
+
+impl<const N: usize> Slot<N> {
+    const EMPTY: Self = Self {
+        frame: [0u8; N],
+        len: 0,
+        deadline: 0,
+        retries: 0,
+    };
+}
+
+/// The frames sent but not yet acknowledged, oldest first
+///
+/// Holds up to `W` frames of up to `N` bytes, which are zeroized once acknowledged.
+pub struct SendWindow<const W: usize, const N: usize> {
+    slots: [Slot<N>; W],
+    // index of the oldest frame in `slots`
+    head: usize,
+    len: usize,
+    next_seq: u8,
+    config: Config,
+    stats: SendStats,
+}
+
+impl<const W: usize, const N: usize> SendWindow<W, N> {
+    pub const fn new(config: Config) -> Self {
+        assert!(
+            W > 0 && W < 128,
+            "the window has to fit in half the sequence numbers"
+        );
+        Self {
+            slots: [Slot::EMPTY; W],
+            head: 0,
+            len: 0,
+            next_seq: 0,
+            config,
+            stats: SendStats {
+                retransmits: 0,
+                give_ups: 0,
+            },
+        }
+    }
+
+    /// The counters since this window was created
+    pub fn stats(&self) -> SendStats {
+        self.stats
+    }
+
+    /// Forget every frame and start again from sequence number zero
+    pub fn reset(&mut self) {
+        while self.len > 0 {
+            self.pop();
+        }
+        self.head = 0;
+        self.next_seq = 0;
+    }
+
+    /// Whether every frame sent has been acknowledged
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The sequence number to send the next frame with, `None` while the window is full
+    pub fn next_seq(&self) -> Option<u8> {
+        (self.len < W).then_some(self.next_seq)
+    }
+
+    /// Hold on to `frame`, sent at `now` with the sequence number from `next_seq`
+    pub fn push(&mut self, frame: &[u8], now: u64) -> Result<(), WindowError> {
+        if self.len == W {
+            return Err(WindowError::Full);
+        }
+        if frame.len() > N {
+            return Err(WindowError::TooLong);
+        }
+
+        let slot = &mut self.slots[(self.head + self.len) % W];
+        slot.frame[..frame.len()].copy_from_slice(frame);
+        slot.len = frame.len();
+        slot.deadline = now.saturating_add(self.config.retransmit_timeout_ms);
+        slot.retries = 0;
+        self.len += 1;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        Ok(())
+    }
+
+    /// The peer received every frame up to and including `seq`
+    ///
+    /// ACKs of frames which were already acknowledged, or never sent, are ignored.
+    pub fn ack(&mut self, seq: u8) {
+        let oldest = self.next_seq.wrapping_sub(self.len as u8);
+        let acked = seq.wrapping_sub(oldest).wrapping_add(1) as usize;
+        if acked <= self.len {
+            for _ in 0..acked {
+                self.pop();
+            }
+        }
+    }
+
+    /// The peer wants every frame from `seq` onwards again, so has every frame before it
+    pub fn nak(&mut self, seq: u8) {
+        self.ack(seq.wrapping_sub(1));
+        for i in 0..self.len {
+            self.slots[(self.head + i) % W].deadline = 0;
+        }
+    }
+
+    /// When the next frame is due to be sent again, `None` if nothing is waiting for an ACK
+    pub fn deadline(&self) -> Option<u64> {
+        (0..self.len)
+            .map(|i| self.slots[(self.head + i) % W].deadline)
+            .min()
+    }
+
+    /// The next frame due to be sent again at `now`, call until it returns `None`
+    ///
+    /// Fails once a frame runs out of retries, the link has most likely gone and the window
+    /// should be reset.
+    pub fn due(&mut self, now: u64) -> Result<Option<&[u8]>, GaveUp> {
+        let oldest = self.next_seq.wrapping_sub(self.len as u8);
+        let Some(i) = (0..self.len).find(|i| self.slots[(self.head + i) % W].deadline <= now)
+        else {
+            return Ok(None);
+        };
+
+        let slot = &mut self.slots[(self.head + i) % W];
+        if slot.retries >= self.config.max_retries {
+            self.stats.give_ups = self.stats.give_ups.saturating_add(1);
+            return Err(GaveUp {
+                seq: oldest.wrapping_add(i as u8),
+            });
+        }
+        slot.retries += 1;
+        slot.deadline = now.saturating_add(self.config.retransmit_timeout_ms);
+        self.stats.retransmits = self.stats.retransmits.saturating_add(1);
+        Ok(Some(&slot.frame[..slot.len]))
+    }
+
+    /// Drop the oldest frame
+    fn pop(&mut self) {
+        let slot = &mut self.slots[self.head];
+        slot.frame[..slot.len].zeroize();
+        slot.len = 0;
+        self.head = (self.head + 1) % W;
+        self.len -= 1;
+    }
+}
+
+impl<const W: usize, const N: usize> Drop for SendWindow<W, N> {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+/// Tracks the next sequence number expected from the peer
+#[derive(Debug, Default)]
+pub struct RecvWindow {
+    expected: u8,
+    stats: RecvStats,
+}
+
+impl RecvWindow {
+    pub const fn new() -> Self {
+        Self {
+            expected: 0,
+            stats: RecvStats {
+                duplicates: 0,
+                out_of_order: 0,
+            },
+        }
+    }
+
+    /// The counters since this window was created
+    pub fn stats(&self) -> RecvStats {
+        self.stats
+    }
+
+    /// Start again from sequence number zero
+    pub fn reset(&mut self) {
+        self.expected = 0;
+    }
+
+    /// The sequence number of the next message, to NAK when a frame arrives corrupted
+    pub fn expected(&self) -> u8 {
+        self.expected
+    }
+
+    /// Decode a whole frame and work out what to do with it
+    ///
+    /// Only a new message is decoded as a `T`, so duplicates of earlier messages of other types
+    /// are still recognised. A new message which fails to decode is still delivered, the error
+    /// is returned in its place and the ACK for it is `expected() - 1`.
+    pub fn decode<'a, T: Deserialize<'a>>(
+        &mut self,
+        frame: &'a mut [u8],
+        checksum: Checksum,
+    ) -> Result<Received<T>, FrameError> {
+        Ok(match self.receive(decode(frame, checksum)?) {
+            Received::Message { msg, ack } => Received::Message {
+                msg: postcard::from_bytes(msg).map_err(FrameError::Message)?,
+                ack,
+            },
+            Received::Duplicate { ack } => Received::Duplicate { ack },
+            Received::Gap { expected } => Received::Gap { expected },
+            Received::Ack(seq) => Received::Ack(seq),
+            Received::Nak(seq) => Received::Nak(seq),
+        })
+    }
+
+    /// Work out what to do with a received frame
+    pub fn receive<T>(&mut self, frame: Frame<T>) -> Received<T> {
+        let (seq, msg) = match frame {
+            Frame::Data { seq, msg } => (seq, msg),
+            Frame::Ack(seq) => return Received::Ack(seq),
+            Frame::Nak(seq) => return Received::Nak(seq),
+        };
+
+        if seq == self.expected {
+            self.expected = seq.wrapping_add(1);
+            return Received::Message { msg, ack: seq };
+        }
+
+        // frames up to half the sequence numbers behind were delivered already
+        let behind = self.expected.wrapping_sub(seq);
+        if behind <= 128 {
+            self.stats.duplicates = self.stats.duplicates.saturating_add(1);
+            Received::Duplicate {
+                ack: self.expected.wrapping_sub(1),
+            }
+        } else {
+            self.stats.out_of_order = self.stats.out_of_order.saturating_add(1);
+            Received::Gap {
+                expected: self.expected,
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Messages and key derivation for the abbreviated, ticket based, session resumption handshake
+//!
+//! After a successful explicit mutual authentication the server may hand the client an opaque
+//! ticket. Both sides derive the same resumption secret from the key schedule, so a later
+//! connection can present the ticket and prove knowledge of the secret instead of running the
+//! full AuCPace protocol again:
+//!
+//! ```text
+//! Client                                     Server
+//!   SessionStart::Resume { ticket, nonce } ->
+//!                                         <- ResumeResponse::Accepted { nonce, mac }
+//!   ResumeFinish { mac }                   ->
+//!                                         <- NewTicket { ticket, lifetime_secs }
+//! ```
+//!
+//! If the server answers with `ResumeResponse::Rejected` the client sends `SessionStart::Full`
+//! and the same session carries on with the full AuCPace protocol.
+
+use crate::key_schedule::SUBKEY_LEN;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::Zeroizing;
+
+/// Length of the nonces exchanged during resumption
+pub const NONCE_LEN: usize = 16;
+
+/// Length of the resumption secret held in a ticket
+pub const SECRET_LEN: usize = SUBKEY_LEN;
+
+/// Length of the MACs exchanged during resumption
+pub const MAC_LEN: usize = 32;
+
+/// Length of the session key produced by a resumed session, matches the AuCPace output
+pub const KEY_LEN: usize = 64;
+
+const KEY_INFO: &[u8] = b"AuCPace resumed session key";
+const SERVER_LABEL: &[u8] = b"server finished";
+const CLIENT_LABEL: &[u8] = b"client finished";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The first message of every session when resumption is enabled
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SessionStart<'a> {
+    /// Perform the full AuCPace protocol
+    Full,
+
+    /// Attempt to resume a previous session with a ticket issued by the server
+    Resume {
+        ticket: &'a [u8],
+        nonce: [u8; NONCE_LEN],
+    },
+}
+
+/// The server's reply to a `SessionStart::Resume`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ResumeResponse {
+    /// The ticket was valid, the MAC proves the server holds the resumption secret
+    Accepted {
+        nonce: [u8; NONCE_LEN],
+        mac: [u8; MAC_LEN],
+    },
+
+    /// The ticket was invalid, expired or has already been used
+    Rejected,
+}
+
+/// The client's proof that it holds the resumption secret
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResumeFinish {
+    pub mac: [u8; MAC_LEN],
+}
+
+/// A new ticket sent by the server after a successful handshake
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewTicket<'a> {
+    pub ticket: &'a [u8],
+    pub lifetime_secs: u32,
+}
+
+/// The values both sides bind the resumed session to
+#[derive(Debug, Clone)]
+pub struct ResumeTranscript {
+    ticket_hash: [u8; 32],
+    client_nonce: [u8; NONCE_LEN],
+    server_nonce: [u8; NONCE_LEN],
+}
+
+impl ResumeTranscript {
+    pub fn new(
+        ticket: &[u8],
+        client_nonce: [u8; NONCE_LEN],
+        server_nonce: [u8; NONCE_LEN],
+    ) -> Self {
+        Self {
+            ticket_hash: Sha256::digest(ticket).into(),
+            client_nonce,
+            server_nonce,
+        }
+    }
+
+    fn mac(&self, secret: &[u8; SECRET_LEN], label: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(secret).expect("HMAC can take a key of any length");
+        mac.update(label);
+        mac.update(&self.ticket_hash);
+        mac.update(&self.client_nonce);
+        mac.update(&self.server_nonce);
+        mac
+    }
+
+    /// The MAC the server sends to prove it holds the resumption secret
+    pub fn server_mac(&self, secret: &[u8; SECRET_LEN]) -> [u8; MAC_LEN] {
+        self.mac(secret, SERVER_LABEL)
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// The MAC the client sends to prove it holds the resumption secret
+    pub fn client_mac(&self, secret: &[u8; SECRET_LEN]) -> [u8; MAC_LEN] {
+        self.mac(secret, CLIENT_LABEL)
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// Check the server's MAC in constant time
+    pub fn verify_server_mac(&self, secret: &[u8; SECRET_LEN], mac: &[u8; MAC_LEN]) -> bool {
+        self.mac(secret, SERVER_LABEL).verify_slice(mac).is_ok()
+    }
+
+    /// Check the client's MAC in constant time
+    pub fn verify_client_mac(&self, secret: &[u8; SECRET_LEN], mac: &[u8; MAC_LEN]) -> bool {
+        self.mac(secret, CLIENT_LABEL).verify_slice(mac).is_ok()
+    }
+
+    /// Derive the key for the resumed session
+    pub fn session_key(&self, secret: &[u8; SECRET_LEN]) -> Zeroizing<[u8; KEY_LEN]> {
+        let mut salt = [0u8; 2 * NONCE_LEN];
+        salt[..NONCE_LEN].copy_from_slice(&self.client_nonce);
+        salt[NONCE_LEN..].copy_from_slice(&self.server_nonce);
+
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        Hkdf::<Sha512>::new(Some(&salt), secret)
+            .expand_multi_info(&[KEY_INFO, &self.ticket_hash], key.as_mut())
+            .expect("KEY_LEN is a valid HKDF-SHA512 output length");
+        key
+    }
+}
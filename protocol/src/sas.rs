@@ -0,0 +1,39 @@
+//! Short authentication strings for comparing keys out-of-band
+//!
+//! Both sides derive the same six digit code from the key schedule, so an operator can check
+//! that they agree by reading the codes off each end, even in implicit mode where no
+//! authenticator is exchanged.
+
+use crate::key_schedule::KeySchedule;
+use core::fmt;
+
+const CONTEXT: &[u8] = b"AuCPace short authentication string";
+const MODULUS: u64 = 1_000_000;
+
+/// A six digit code derived from the key schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortAuthString(u32);
+
+impl ShortAuthString {
+    pub fn new(schedule: &KeySchedule) -> Self {
+        let mut bytes = [0u8; 8];
+        schedule
+            .exporter(CONTEXT, &mut bytes)
+            .expect("8 bytes is a valid HKDF-SHA512 output length");
+
+        // the bias from reducing a 64-bit value is far too small to matter here
+        Self((u64::from_le_bytes(bytes) % MODULUS) as u32)
+    }
+
+    /// The code as a number between 0 and 999999
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Formats as two groups of three digits, e.g. `042 917`
+impl fmt::Display for ShortAuthString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03} {:03}", self.0 / 1000, self.0 % 1000)
+    }
+}
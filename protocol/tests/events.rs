@@ -0,0 +1,60 @@
+use protocol::events::{self, Event, EventKind, MAX_EVENT_LEN};
+use protocol::key_schedule::SUBKEY_LEN;
+use protocol::phase::Phase;
+
+const KEY: [u8; SUBKEY_LEN] = [0x42; SUBKEY_LEN];
+
+fn event(session: u32, kind: EventKind) -> Event {
+    Event {
+        at_ms: 1000 * session as u64,
+        session,
+        kind,
+    }
+}
+
+#[test]
+fn events_survive_encoding() {
+    let log = [
+        event(1, EventKind::SessionStarted),
+        event(1, EventKind::PhaseReached(Phase::Augmentation)),
+        event(2, EventKind::Throttled { millis: 4000 }),
+    ];
+    let mut buf = [0u8; 3 * MAX_EVENT_LEN];
+    let encoded = events::encode(log.iter(), &mut buf).unwrap();
+
+    let decoded: Vec<Event> = events::decode(encoded).map(Result::unwrap).collect();
+    assert_eq!(decoded, log);
+}
+
+#[test]
+fn the_longest_event_fits() {
+    let longest = Event {
+        at_ms: u64::MAX,
+        session: u32::MAX,
+        kind: EventKind::Throttled { millis: u32::MAX },
+    };
+    let mut buf = [0u8; MAX_EVENT_LEN];
+    assert!(events::encode([longest].iter(), &mut buf).is_ok());
+}
+
+#[test]
+fn truncated_logs_end_in_an_error() {
+    let mut buf = [0u8; MAX_EVENT_LEN];
+    let encoded = events::encode([event(300, EventKind::AuthFailed)].iter(), &mut buf).unwrap();
+    let truncated = &encoded[..encoded.len() - 1];
+    let decoded: Vec<_> = events::decode(truncated).collect();
+    assert!(matches!(decoded[..], [Err(_)]));
+}
+
+#[test]
+fn macs_need_the_right_key() {
+    let other = [0x43; SUBKEY_LEN];
+    assert!(events::verify_request(&KEY, &events::request_mac(&KEY)));
+    assert!(!events::verify_request(&other, &events::request_mac(&KEY)));
+
+    let mac = events::response_mac(&KEY, 3, b"events");
+    assert!(events::verify_response(&KEY, 3, b"events", &mac));
+    assert!(!events::verify_response(&KEY, 4, b"events", &mac));
+    assert!(!events::verify_response(&KEY, 3, b"evenTs", &mac));
+    assert!(!events::verify_response(&other, 3, b"events", &mac));
+}
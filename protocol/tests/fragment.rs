@@ -0,0 +1,227 @@
+use protocol::fragment::{
+    fragment_count, Fragment, FragmentError, Fragmenter, Reassembler, FRAGMENT_LEN,
+};
+use protocol::framing::{self, Checksum, FrameReceiver};
+use protocol::reliable;
+use serde::{Deserialize, Serialize};
+
+/// Stands in for a registration carrying a lot of user attached data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Registration<'a> {
+    username: &'a str,
+    #[serde(borrow)]
+    data: &'a [u8],
+}
+
+/// Every fragment of `msg` as owned bytes, alongside its index and whether it is the last
+fn split<const N: usize, T: Serialize + ?Sized>(msg: &T) -> Vec<(u8, bool, Vec<u8>)> {
+    let mut fragmenter = Fragmenter::<N>::new();
+    fragmenter.start(msg).unwrap();
+    let mut fragments = Vec::new();
+    while let Some(fragment) = fragmenter.next_fragment() {
+        fragments.push((fragment.index, fragment.last, fragment.data.to_vec()));
+    }
+    fragments
+}
+
+fn fragment((index, last, data): &(u8, bool, Vec<u8>)) -> Fragment<'_> {
+    Fragment {
+        index: *index,
+        last: *last,
+        data,
+    }
+}
+
+#[test]
+fn messages_are_split_at_fragment_boundaries() {
+    for len in [
+        0,
+        1,
+        FRAGMENT_LEN - 1,
+        FRAGMENT_LEN,
+        FRAGMENT_LEN + 1,
+        3 * FRAGMENT_LEN,
+    ] {
+        let data = vec![0xA5u8; len];
+        let mut fragmenter = Fragmenter::<4096>::new();
+        fragmenter.start(&data[..]).unwrap();
+        let mut buf = [0u8; 4096];
+        let encoded = postcard::to_slice(&data[..], &mut buf).unwrap();
+
+        let mut reassembled = Vec::new();
+        let mut count = 0;
+        while let Some(fragment) = fragmenter.next_fragment() {
+            assert_eq!(fragment.index, count as u8);
+            assert!(fragment.data.len() <= FRAGMENT_LEN);
+            assert_eq!(
+                fragment.last,
+                reassembled.len() + fragment.data.len() == encoded.len()
+            );
+            reassembled.extend_from_slice(fragment.data);
+            count += 1;
+        }
+        assert_eq!(reassembled, encoded);
+        assert_eq!(count, fragment_count(encoded.len()));
+    }
+}
+
+#[test]
+fn empty_message_is_a_single_fragment() {
+    let fragments = split::<16, ()>(&());
+    assert_eq!(fragments, vec![(0, true, vec![])]);
+
+    let mut reassembler = Reassembler::<16>::new();
+    assert_eq!(reassembler.push(fragment(&fragments[0])), Ok(true));
+    assert_eq!(postcard::from_bytes::<()>(reassembler.message()), Ok(()));
+}
+
+#[test]
+fn large_message_round_trips_through_frames() {
+    let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    let msg = Registration {
+        username: "alice",
+        data: &data,
+    };
+
+    for checksum in [None, Some(Checksum::Crc16), Some(Checksum::Crc32)] {
+        let mut fragmenter = Fragmenter::<4096>::new();
+        fragmenter.start(&msg).unwrap();
+        let mut frames = FrameReceiver::<1024>::new();
+        let mut reassembler = Reassembler::<4096>::new();
+        let mut scratch = [0u8; 1024];
+        let mut out = [0u8; 1024];
+
+        let mut complete = false;
+        while let Some(fragment) = fragmenter.next_fragment() {
+            let frame = framing::encode(&fragment, checksum, &mut scratch, &mut out).unwrap();
+            frames.spare()[..frame.len()].copy_from_slice(frame);
+            assert!(frames.fill(frame.len()));
+
+            let received = frames
+                .decode_frame(|frame| framing::decode::<Fragment>(frame, checksum))
+                .unwrap()
+                .unwrap();
+            assert!(!complete);
+            complete = reassembler.push(received).unwrap();
+        }
+
+        assert!(complete);
+        assert_eq!(
+            postcard::from_bytes::<Registration>(reassembler.message()),
+            Ok(msg.clone())
+        );
+    }
+}
+
+#[test]
+fn full_fragment_fits_in_a_reliable_frame() {
+    let data = [0xFFu8; FRAGMENT_LEN];
+    let fragment = Fragment {
+        index: u8::MAX,
+        last: true,
+        data: &data,
+    };
+    let mut scratch = [0u8; 1024];
+    let mut out = [0u8; 1024];
+    let frame =
+        reliable::encode_data(u8::MAX, &fragment, Checksum::Crc32, &mut scratch, &mut out).unwrap();
+    assert!(frame.len() < 600, "{} byte frame", frame.len());
+}
+
+#[test]
+fn sending_a_message_longer_than_the_buffer_fails() {
+    let data = [1u8; 100];
+    let mut fragmenter = Fragmenter::<64>::new();
+    assert_eq!(
+        fragmenter.start(&data[..]),
+        Err(FragmentError::TooLong { limit: 64 })
+    );
+    assert!(fragmenter.next_fragment().is_none());
+
+    // the fragmenter can still be used for a shorter message
+    fragmenter.start(&data[..10]).unwrap();
+    assert!(fragmenter.next_fragment().unwrap().last);
+}
+
+#[test]
+fn receiving_a_message_longer_than_the_buffer_fails() {
+    let data = vec![7u8; 3 * FRAGMENT_LEN];
+    let fragments = split::<4096, _>(&data[..]);
+    assert_eq!(fragments.len(), 4);
+
+    let mut reassembler = Reassembler::<{ 2 * FRAGMENT_LEN }>::new();
+    assert_eq!(reassembler.push(fragment(&fragments[0])), Ok(false));
+    assert_eq!(reassembler.push(fragment(&fragments[1])), Ok(false));
+    assert_eq!(
+        reassembler.push(fragment(&fragments[2])),
+        Err(FragmentError::TooLong {
+            limit: 2 * FRAGMENT_LEN
+        })
+    );
+    // the rest of the message is dropped quietly
+    assert_eq!(reassembler.push(fragment(&fragments[3])), Ok(false));
+    assert_eq!(reassembler.message(), &[]);
+
+    // and the next message is received as normal
+    let fragments = split::<64, _>(&[1u8, 2, 3][..]);
+    assert_eq!(reassembler.push(fragment(&fragments[0])), Ok(true));
+    assert_eq!(
+        postcard::from_bytes::<&[u8]>(reassembler.message()),
+        Ok(&[1u8, 2, 3][..])
+    );
+}
+
+#[test]
+fn missing_fragment_drops_the_message() {
+    let data = vec![9u8; 3 * FRAGMENT_LEN];
+    let fragments = split::<4096, _>(&data[..]);
+
+    let mut reassembler = Reassembler::<4096>::new();
+    assert_eq!(reassembler.push(fragment(&fragments[0])), Ok(false));
+    assert_eq!(
+        reassembler.push(fragment(&fragments[2])),
+        Err(FragmentError::OutOfOrder {
+            expected: 1,
+            got: 2
+        })
+    );
+    assert_eq!(reassembler.push(fragment(&fragments[3])), Ok(false));
+    assert_eq!(reassembler.message(), &[]);
+}
+
+#[test]
+fn first_fragment_starts_a_new_message() {
+    let long = vec![3u8; 2 * FRAGMENT_LEN];
+    let long = split::<4096, _>(&long[..]);
+    let short = split::<64, _>(&[4u8; 4][..]);
+
+    // a message cut short by the start of another is replaced by it
+    let mut reassembler = Reassembler::<4096>::new();
+    assert_eq!(reassembler.push(fragment(&long[0])), Ok(false));
+    assert_eq!(reassembler.push(fragment(&short[0])), Ok(true));
+    assert_eq!(
+        postcard::from_bytes::<&[u8]>(reassembler.message()),
+        Ok(&[4u8; 4][..])
+    );
+
+    // as is one being skipped after an error
+    assert!(reassembler.push(fragment(&long[1])).is_err());
+    assert_eq!(reassembler.push(fragment(&short[0])), Ok(true));
+}
+
+#[test]
+fn message_starting_part_way_through_is_dropped() {
+    let data = vec![5u8; 2 * FRAGMENT_LEN];
+    let fragments = split::<4096, _>(&data[..]);
+
+    let mut reassembler = Reassembler::<4096>::new();
+    assert_eq!(
+        reassembler.push(fragment(&fragments[1])),
+        Err(FragmentError::OutOfOrder {
+            expected: 0,
+            got: 1
+        })
+    );
+    assert_eq!(reassembler.push(fragment(&fragments[2])), Ok(false));
+    assert_eq!(reassembler.push(fragment(&fragments[0])), Ok(false));
+}
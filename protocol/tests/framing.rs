@@ -0,0 +1,206 @@
+use protocol::framing::{
+    self, Checksum, FrameError, FrameReceiver, FrameStats, RESEND_REQUEST_MAX_LEN,
+};
+use protocol::link::LinkCheck;
+
+/// Copy `data` into the receiver as a single read
+fn read<const N: usize>(frames: &mut FrameReceiver<N>, data: &[u8]) -> bool {
+    frames.spare()[..data.len()].copy_from_slice(data);
+    frames.fill(data.len())
+}
+
+fn take<const N: usize>(frames: &mut FrameReceiver<N>) -> Option<Vec<u8>> {
+    frames.take_frame().map(|frame| frame.to_vec())
+}
+
+#[test]
+fn frame_split_across_reads() {
+    let mut frames = FrameReceiver::<16>::new();
+
+    assert!(read(&mut frames, &[3, 1]));
+    assert_eq!(take(&mut frames), None);
+    assert!(read(&mut frames, &[2]));
+    assert_eq!(take(&mut frames), None);
+    assert!(read(&mut frames, &[0, 2]));
+
+    assert_eq!(take(&mut frames).as_deref(), Some(&[3, 1, 2, 0][..]));
+    assert_eq!(take(&mut frames), None);
+    assert_eq!(frames.buffered(), &[2]);
+}
+
+#[test]
+fn several_frames_in_one_read() {
+    let mut frames = FrameReceiver::<16>::new();
+
+    assert!(read(&mut frames, &[2, 1, 0, 1, 0, 3, 4, 5, 0, 7]));
+
+    assert_eq!(take(&mut frames).as_deref(), Some(&[2, 1, 0][..]));
+    assert_eq!(take(&mut frames).as_deref(), Some(&[1, 0][..]));
+    assert_eq!(take(&mut frames).as_deref(), Some(&[3, 4, 5, 0][..]));
+    assert_eq!(take(&mut frames), None);
+
+    // the start of the next frame is kept for the following read
+    assert!(read(&mut frames, &[8, 0]));
+    assert_eq!(take(&mut frames).as_deref(), Some(&[7, 8, 0][..]));
+    assert_eq!(frames.stats().frames, 4);
+}
+
+#[test]
+fn oversized_frame_is_discarded_up_to_its_delimiter() {
+    let mut frames = FrameReceiver::<8>::new();
+
+    assert!(read(&mut frames, &[1; 5]));
+    assert!(!read(&mut frames, &[1; 3]));
+    assert_eq!(frames.buffered(), &[]);
+
+    // the rest of the oversized frame is thrown away, the frame after it is kept
+    assert!(read(&mut frames, &[1, 1, 0, 2, 9]));
+    assert_eq!(take(&mut frames), None);
+    assert!(read(&mut frames, &[0]));
+    assert_eq!(take(&mut frames).as_deref(), Some(&[2, 9, 0][..]));
+
+    assert_eq!(
+        frames.stats(),
+        FrameStats {
+            frames: 1,
+            oversized: 1,
+            corrupt: 0,
+            bad_checksum: 0,
+            resend_requests: 0,
+            discarded_bytes: 11,
+        }
+    );
+}
+
+#[test]
+fn oversized_frame_spanning_several_buffers() {
+    let mut frames = FrameReceiver::<4>::new();
+
+    assert!(!read(&mut frames, &[1; 4]));
+    assert!(read(&mut frames, &[1; 4]));
+    assert!(read(&mut frames, &[1; 4]));
+    assert!(read(&mut frames, &[0, 1, 0]));
+
+    assert_eq!(take(&mut frames).as_deref(), Some(&[1, 0][..]));
+    assert_eq!(frames.stats().oversized, 1);
+    assert_eq!(frames.stats().discarded_bytes, 13);
+}
+
+#[test]
+fn full_buffer_of_complete_frames_is_kept() {
+    let mut frames = FrameReceiver::<4>::new();
+
+    assert!(read(&mut frames, &[1, 0, 2, 0]));
+    assert_eq!(take(&mut frames).as_deref(), Some(&[1, 0][..]));
+    assert_eq!(take(&mut frames).as_deref(), Some(&[2, 0][..]));
+    assert_eq!(frames.stats().oversized, 0);
+}
+
+#[test]
+fn failed_decodes_are_counted_as_corrupt() {
+    let mut frames = FrameReceiver::<16>::new();
+    assert!(read(&mut frames, &[1, 0, 2, 0]));
+
+    let check = |frame: &mut [u8]| {
+        if frame[0] == 1 {
+            Ok(())
+        } else {
+            Err(FrameError::Malformed)
+        }
+    };
+    assert_eq!(frames.decode_frame(check), Some(Ok(())));
+    assert_eq!(frames.decode_frame(check), Some(Err(FrameError::Malformed)));
+    assert_eq!(frames.decode_frame(check), None);
+
+    assert_eq!(frames.stats().frames, 2);
+    assert_eq!(frames.stats().corrupt, 1);
+}
+
+/// Encode `msg` with `checksum` into an owned frame
+fn encode(msg: &LinkCheck, checksum: Option<Checksum>) -> Vec<u8> {
+    let mut scratch = [0u8; 32];
+    let mut out = [0u8; 32];
+    framing::encode(msg, checksum, &mut scratch, &mut out)
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn frames_round_trip_with_each_checksum() {
+    let msg = LinkCheck { nonce: 0xDEAD_0000 };
+    for checksum in [None, Some(Checksum::Crc16), Some(Checksum::Crc32)] {
+        let mut frame = encode(&msg, checksum);
+        assert_eq!(frame.iter().position(|b| *b == 0), Some(frame.len() - 1));
+        assert_eq!(framing::decode(&mut frame, checksum), Ok(msg));
+    }
+}
+
+#[test]
+fn corrupted_frames_fail_their_checksum() {
+    let msg = LinkCheck { nonce: 0x1234_5678 };
+    for checksum in [Checksum::Crc16, Checksum::Crc32] {
+        let frame = encode(&msg, Some(checksum));
+        // flip every bit which doesn't introduce a delimiter, the COBS code bytes included
+        for i in 0..frame.len() - 1 {
+            for bit in 0..8 {
+                let mut corrupted = frame.clone();
+                corrupted[i] ^= 1 << bit;
+                if corrupted[i] == 0 {
+                    continue;
+                }
+                let decoded = framing::decode::<LinkCheck>(&mut corrupted, Some(checksum));
+                assert!(
+                    matches!(decoded, Err(FrameError::Checksum | FrameError::Malformed)),
+                    "flipped bit {bit} of byte {i} - {decoded:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn checksum_failures_and_resend_requests_are_counted() {
+    let checksum = Checksum::Crc16;
+    let mut corrupted = encode(&LinkCheck { nonce: 7 }, Some(checksum));
+    corrupted[2] ^= 0x10;
+    let mut request = [0u8; RESEND_REQUEST_MAX_LEN];
+    let request = framing::resend_request(checksum, &mut request).to_vec();
+
+    let mut frames = FrameReceiver::<64>::new();
+    assert!(read(&mut frames, &corrupted));
+    assert!(read(&mut frames, &request));
+
+    let decode = |frame: &mut [u8]| framing::decode::<LinkCheck>(frame, Some(checksum));
+    assert_eq!(frames.decode_frame(decode), Some(Err(FrameError::Checksum)));
+    assert_eq!(
+        frames.decode_frame(decode),
+        Some(Err(FrameError::ResendRequest))
+    );
+
+    let stats = frames.stats();
+    assert_eq!(stats.frames, 2);
+    assert_eq!(stats.corrupt, 1);
+    assert_eq!(stats.bad_checksum, 1);
+    assert_eq!(stats.resend_requests, 1);
+}
+
+#[test]
+fn frames_without_a_checksum_are_rejected() {
+    let mut frame = encode(&LinkCheck { nonce: 7 }, None);
+    assert!(framing::decode::<LinkCheck>(&mut frame, Some(Checksum::Crc32)).is_err());
+}
+
+#[test]
+fn encoding_fails_when_the_frame_does_not_fit() {
+    let msg = LinkCheck { nonce: u32::MAX };
+    let mut scratch = [0u8; 32];
+    let mut out = [0u8; 6];
+    assert!(framing::encode(&msg, Some(Checksum::Crc32), &mut scratch, &mut out).is_err());
+    assert!(framing::encode(
+        &msg,
+        Some(Checksum::Crc32),
+        &mut scratch[..4],
+        &mut [0u8; 32]
+    )
+    .is_err());
+}
@@ -0,0 +1,105 @@
+use protocol::key_schedule::{KeySchedule, Label, Transcript, SUBKEY_LEN};
+
+const KEY: [u8; SUBKEY_LEN] = [0x42; SUBKEY_LEN];
+const LABELS: [Label; 4] = [
+    Label::ClientToServer,
+    Label::ServerToClient,
+    Label::Exporter,
+    Label::Resumption,
+];
+
+fn transcript(frames: &[&[u8]]) -> Transcript {
+    let mut transcript = Transcript::default();
+    for frame in frames {
+        transcript.update(frame);
+    }
+    transcript
+}
+
+fn client_to_server() -> Transcript {
+    transcript(&[b"preamble", b"client message"])
+}
+
+fn server_to_client() -> Transcript {
+    transcript(&[b"preamble response", b"server message"])
+}
+
+fn subkeys(schedule: &KeySchedule) -> Vec<[u8; SUBKEY_LEN]> {
+    LABELS
+        .iter()
+        .map(|&label| *schedule.subkey(label))
+        .collect()
+}
+
+#[test]
+fn labels_give_independent_subkeys() {
+    let schedule = KeySchedule::new(&KEY, &client_to_server(), &server_to_client());
+    let keys = subkeys(&schedule);
+
+    for (i, a) in keys.iter().enumerate() {
+        for b in &keys[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+    assert_eq!(*schedule.client_to_server(), keys[0]);
+    assert_eq!(*schedule.server_to_client(), keys[1]);
+    assert_eq!(*schedule.resumption(), keys[3]);
+}
+
+#[test]
+fn every_key_depends_on_both_transcripts() {
+    let schedule = KeySchedule::new(&KEY, &client_to_server(), &server_to_client());
+    let keys = subkeys(&schedule);
+
+    let changed_client = transcript(&[b"preamble", b"client messagf"]);
+    let changed_server = transcript(&[b"preamble response", b"server messagf"]);
+    for changed in [
+        KeySchedule::new(&KEY, &changed_client, &server_to_client()),
+        KeySchedule::new(&KEY, &client_to_server(), &changed_server),
+    ] {
+        for (key, other) in keys.iter().zip(subkeys(&changed)) {
+            assert_ne!(*key, other);
+        }
+    }
+}
+
+#[test]
+fn transcripts_are_not_interchangeable() {
+    let schedule = KeySchedule::new(&KEY, &client_to_server(), &server_to_client());
+    let swapped = KeySchedule::new(&KEY, &server_to_client(), &client_to_server());
+
+    for (key, other) in subkeys(&schedule).iter().zip(subkeys(&swapped)) {
+        assert_ne!(*key, other);
+    }
+}
+
+#[test]
+fn both_sides_derive_the_same_schedule() {
+    // each side keeps its own send and receive transcripts, fed the same frames
+    let (mut client_sent, mut client_received) = (Transcript::default(), Transcript::default());
+    let (mut server_sent, mut server_received) = (Transcript::default(), Transcript::default());
+    for frame in [&b"preamble"[..], b"client message"] {
+        client_sent.update(frame);
+        server_received.update(frame);
+    }
+    for frame in [&b"preamble response"[..], b"server message"] {
+        server_sent.update(frame);
+        client_received.update(frame);
+    }
+
+    let client = KeySchedule::new(&KEY, &client_sent, &client_received);
+    let server = KeySchedule::new(&KEY, &server_received, &server_sent);
+    assert_eq!(subkeys(&client), subkeys(&server));
+
+    let (mut client_out, mut server_out) = ([0u8; 48], [0u8; 48]);
+    client.exporter(b"context", &mut client_out).unwrap();
+    server.exporter(b"context", &mut server_out).unwrap();
+    assert_eq!(client_out, server_out);
+}
+
+#[test]
+fn frame_boundaries_are_part_of_the_transcript() {
+    let split = transcript(&[b"ab", b"c"]);
+    let joined = transcript(&[b"a", b"bc"]);
+    assert_ne!(split.hash(), joined.hash());
+}
@@ -0,0 +1,26 @@
+use protocol::phase::{Phase, PhaseTimings};
+
+#[test]
+fn timings_add_up_per_phase() {
+    let mut timings = PhaseTimings::default();
+    timings.add(Phase::Ssid, 10);
+    timings.add(Phase::Pbkdf, 2000);
+    timings.add(Phase::Ssid, 5);
+
+    assert_eq!(timings.get(Phase::Ssid), 15);
+    assert_eq!(timings.get(Phase::Resumption), 0);
+    assert_eq!(timings.total(), 2015);
+    let phases: Vec<Phase> = timings.iter().map(|(phase, _)| phase).collect();
+    assert_eq!(phases, Phase::ALL);
+}
+
+#[test]
+fn timings_survive_encoding() {
+    let mut timings = PhaseTimings::default();
+    for (i, phase) in Phase::ALL.into_iter().enumerate() {
+        timings.add(phase, 1 << (8 * i));
+    }
+    let mut buf = [0u8; 64];
+    let encoded = postcard::to_slice(&timings, &mut buf).unwrap();
+    assert_eq!(postcard::from_bytes::<PhaseTimings>(encoded), Ok(timings));
+}
@@ -0,0 +1,56 @@
+use protocol::preamble::{Mismatch, Preamble, IMPLICIT, RESUMPTION, STRONG, VERSION};
+
+#[test]
+fn matching_preambles_are_accepted() {
+    let ours = Preamble::new(STRONG | RESUMPTION);
+    assert_eq!(ours.check(&Preamble::new(STRONG | RESUMPTION)), Ok(()));
+}
+
+#[test]
+fn feature_mismatch_names_the_features() {
+    let ours = Preamble::new(STRONG | RESUMPTION);
+    let theirs = Preamble::new(STRONG | IMPLICIT);
+
+    let mismatch = ours.check(&theirs).unwrap_err();
+    assert_eq!(
+        mismatch,
+        Mismatch::Features {
+            ours: STRONG | RESUMPTION,
+            theirs: STRONG | IMPLICIT,
+        }
+    );
+    assert_eq!(
+        mismatch.to_string(),
+        "feature mismatch - peer has implicit peer lacks resumption"
+    );
+}
+
+#[test]
+fn version_is_checked_before_features() {
+    let ours = Preamble::new(0);
+    let theirs = Preamble {
+        version: VERSION + 1,
+        ..Preamble::new(STRONG)
+    };
+
+    assert_eq!(
+        ours.check(&theirs),
+        Err(Mismatch::Version {
+            ours: VERSION,
+            theirs: VERSION + 1,
+        })
+    );
+}
+
+#[test]
+fn fingerprint_covers_the_schema() {
+    let ours = Preamble::new(0);
+    let theirs = Preamble {
+        fingerprint: !ours.fingerprint,
+        ..ours
+    };
+    assert_eq!(ours.check(&theirs), Err(Mismatch::Schema));
+
+    let theirs = Preamble { magic: 0, ..ours };
+    assert_eq!(ours.check(&theirs), Err(Mismatch::Magic));
+}
@@ -0,0 +1,347 @@
+use protocol::framing::{Checksum, FrameError, FrameReceiver};
+use protocol::reliable::{
+    self, Config, Frame, GaveUp, Received, RecvWindow, SendWindow, WindowError, CONTROL_MAX_LEN,
+};
+use std::collections::VecDeque;
+
+const CHECKSUM: Checksum = Checksum::Crc16;
+const FRAME_LEN: usize = 64;
+const CONFIG: Config = Config {
+    retransmit_timeout_ms: 50,
+    max_retries: 20,
+};
+
+/// Deterministic xorshift so failures can be reproduced
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// `true` with a probability of `percent` in 100
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+/// One direction of a serial link which loses and corrupts frames
+struct Wire {
+    in_flight: VecDeque<(u64, Vec<u8>)>,
+    latency_ms: u64,
+    loss_percent: u64,
+    corrupt_percent: u64,
+}
+
+impl Wire {
+    fn new(latency_ms: u64, loss_percent: u64, corrupt_percent: u64) -> Self {
+        Self {
+            in_flight: VecDeque::new(),
+            latency_ms,
+            loss_percent,
+            corrupt_percent,
+        }
+    }
+
+    fn send(&mut self, frame: &[u8], now: u64, rng: &mut Rng) {
+        if rng.chance(self.loss_percent) {
+            return;
+        }
+        let mut frame = frame.to_vec();
+        if rng.chance(self.corrupt_percent) {
+            let i = rng.next() as usize % frame.len();
+            frame[i] ^= 1 << (rng.next() % 8);
+        }
+        self.in_flight.push_back((now + self.latency_ms, frame));
+    }
+
+    /// Everything which has arrived by `now`
+    fn arrived(&mut self, now: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while self.in_flight.front().is_some_and(|(at, _)| *at <= now) {
+            bytes.extend(self.in_flight.pop_front().unwrap().1);
+        }
+        bytes
+    }
+}
+
+/// One side of the link, sending `u32`s and recording what it delivers
+struct Endpoint {
+    sent: SendWindow<4, FRAME_LEN>,
+    received: RecvWindow,
+    frames: FrameReceiver<256>,
+    to_send: VecDeque<u32>,
+    delivered: Vec<u32>,
+}
+
+impl Endpoint {
+    fn new(to_send: impl IntoIterator<Item = u32>) -> Self {
+        Self {
+            sent: SendWindow::new(CONFIG),
+            received: RecvWindow::new(),
+            frames: FrameReceiver::new(),
+            to_send: to_send.into_iter().collect(),
+            delivered: Vec::new(),
+        }
+    }
+
+    /// Run the endpoint for one tick, sending anything it needs to over `wire`
+    fn step(&mut self, incoming: &[u8], wire: &mut Wire, now: u64, rng: &mut Rng) {
+        let mut incoming = incoming;
+        while !incoming.is_empty() {
+            let spare = self.frames.spare();
+            let count = spare.len().min(incoming.len());
+            spare[..count].copy_from_slice(&incoming[..count]);
+            self.frames.fill(count);
+            incoming = &incoming[count..];
+
+            while self.frames.next_frame_len().is_some() {
+                let received = &mut self.received;
+                let decoded = self
+                    .frames
+                    .decode_frame(|frame| received.decode::<u32>(frame, CHECKSUM));
+                let mut control = [0u8; CONTROL_MAX_LEN];
+                match decoded.unwrap() {
+                    Ok(Received::Message { msg, ack }) => {
+                        self.delivered.push(msg);
+                        wire.send(reliable::encode_ack(ack, CHECKSUM, &mut control), now, rng);
+                    }
+                    Ok(Received::Duplicate { ack }) => {
+                        wire.send(reliable::encode_ack(ack, CHECKSUM, &mut control), now, rng);
+                    }
+                    Ok(Received::Gap { expected }) => {
+                        wire.send(
+                            reliable::encode_nak(expected, CHECKSUM, &mut control),
+                            now,
+                            rng,
+                        );
+                    }
+                    Ok(Received::Ack(seq)) => self.sent.ack(seq),
+                    Ok(Received::Nak(seq)) => self.sent.nak(seq),
+                    Err(FrameError::Checksum | FrameError::Malformed) => {
+                        let expected = self.received.expected();
+                        wire.send(
+                            reliable::encode_nak(expected, CHECKSUM, &mut control),
+                            now,
+                            rng,
+                        );
+                    }
+                    Err(e) => panic!("unexpected frame error - {e:?}"),
+                }
+            }
+        }
+
+        while let Some(seq) = self.sent.next_seq() {
+            let Some(msg) = self.to_send.pop_front() else {
+                break;
+            };
+            let mut scratch = [0u8; FRAME_LEN];
+            let mut out = [0u8; FRAME_LEN];
+            let frame = reliable::encode_data(seq, &msg, CHECKSUM, &mut scratch, &mut out).unwrap();
+            self.sent.push(frame, now).unwrap();
+            wire.send(frame, now, rng);
+        }
+
+        while let Some(frame) = self.sent.due(now).expect("gave up on a lossy link") {
+            wire.send(frame, now, rng);
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.to_send.is_empty() && self.sent.is_empty()
+    }
+}
+
+/// Exchange `count` messages each way, returning both endpoints once everything is acknowledged
+fn exchange(
+    count: u32,
+    loss_percent: u64,
+    corrupt_percent: u64,
+    seed: u64,
+) -> (Endpoint, Endpoint) {
+    let mut rng = Rng(seed);
+    let mut a = Endpoint::new(0..count);
+    let mut b = Endpoint::new((0..count).map(|n| n + 1000));
+    let mut a_to_b = Wire::new(3, loss_percent, corrupt_percent);
+    let mut b_to_a = Wire::new(3, loss_percent, corrupt_percent);
+
+    for now in 0..100_000 {
+        let incoming = b_to_a.arrived(now);
+        a.step(&incoming, &mut a_to_b, now, &mut rng);
+        let incoming = a_to_b.arrived(now);
+        b.step(&incoming, &mut b_to_a, now, &mut rng);
+
+        if a.done() && b.done() {
+            return (a, b);
+        }
+    }
+    panic!("the exchange didn't finish");
+}
+
+#[test]
+fn perfect_link_needs_no_retransmits() {
+    let (a, b) = exchange(300, 0, 0, 1);
+    assert_eq!(b.delivered, (0..300).collect::<Vec<_>>());
+    assert_eq!(a.delivered, (1000..1300).collect::<Vec<_>>());
+    assert_eq!(a.sent.stats().retransmits, 0);
+    assert_eq!(b.received.stats().duplicates, 0);
+}
+
+#[test]
+fn every_message_is_delivered_once_in_order_over_a_lossy_link() {
+    for seed in 1..20 {
+        let (a, b) = exchange(300, 10, 10, seed);
+        assert_eq!(b.delivered, (0..300).collect::<Vec<_>>(), "seed {seed}");
+        assert_eq!(a.delivered, (1000..1300).collect::<Vec<_>>(), "seed {seed}");
+        assert!(a.sent.stats().retransmits > 0);
+        assert!(b.sent.stats().retransmits > 0);
+    }
+}
+
+#[test]
+fn lost_ack_leads_to_a_duplicate_which_is_acknowledged_again() {
+    let mut received = RecvWindow::new();
+    let data = |seq| Frame::Data { seq, msg: seq };
+
+    assert_eq!(
+        received.receive(data(0)),
+        Received::Message { msg: 0, ack: 0 }
+    );
+    assert_eq!(received.receive(data(0)), Received::Duplicate { ack: 0 });
+    assert_eq!(received.receive(data(2)), Received::Gap { expected: 1 });
+    assert_eq!(
+        received.receive(data(1)),
+        Received::Message { msg: 1, ack: 1 }
+    );
+    assert_eq!(received.stats().duplicates, 1);
+    assert_eq!(received.stats().out_of_order, 1);
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let mut sent = SendWindow::<2, 8>::new(CONFIG);
+    let mut received = RecvWindow::new();
+    for n in 0..600u32 {
+        let seq = sent.next_seq().unwrap();
+        sent.push(&[1], 0).unwrap();
+        assert_eq!(
+            received.receive(Frame::Data { seq, msg: n }),
+            Received::Message { msg: n, ack: seq }
+        );
+        sent.ack(seq);
+        assert!(sent.is_empty());
+    }
+}
+
+#[test]
+fn acks_are_cumulative_and_stale_ones_are_ignored() {
+    let mut sent = SendWindow::<4, 8>::new(CONFIG);
+    for _ in 0..3 {
+        sent.push(&[1], 0).unwrap();
+    }
+
+    sent.ack(1);
+    assert_eq!(sent.next_seq(), Some(3));
+    assert_eq!(sent.deadline(), Some(50));
+    // an ACK of a frame already acknowledged and one of a frame never sent
+    sent.ack(0);
+    sent.ack(200);
+    assert!(!sent.is_empty());
+    sent.ack(2);
+    assert!(sent.is_empty());
+    assert_eq!(sent.deadline(), None);
+}
+
+#[test]
+fn window_limits_frames_in_flight() {
+    let mut sent = SendWindow::<2, 4>::new(CONFIG);
+    assert_eq!(sent.push(&[1; 5], 0), Err(WindowError::TooLong));
+    sent.push(&[1], 0).unwrap();
+    sent.push(&[2], 0).unwrap();
+    assert_eq!(sent.next_seq(), None);
+    assert_eq!(sent.push(&[3], 0), Err(WindowError::Full));
+}
+
+#[test]
+fn nak_resends_everything_from_the_missing_frame() {
+    let mut sent = SendWindow::<4, 8>::new(CONFIG);
+    for n in 0..3 {
+        sent.push(&[n + 1], 0).unwrap();
+    }
+
+    // the peer has frame 0 but not frame 1
+    sent.nak(1);
+    assert_eq!(sent.due(1), Ok(Some(&[2][..])));
+    assert_eq!(sent.due(1), Ok(Some(&[3][..])));
+    assert_eq!(sent.due(1), Ok(None));
+    assert_eq!(sent.stats().retransmits, 2);
+}
+
+#[test]
+fn sender_gives_up_after_max_retries() {
+    let config = Config {
+        retransmit_timeout_ms: 10,
+        max_retries: 3,
+    };
+    let mut sent = SendWindow::<2, 8>::new(config);
+    sent.push(&[1], 0).unwrap();
+
+    assert_eq!(sent.due(9), Ok(None));
+    for retry in 1..=3 {
+        assert_eq!(sent.due(retry * 10), Ok(Some(&[1][..])));
+    }
+    assert_eq!(sent.due(40), Err(GaveUp { seq: 0 }));
+    assert_eq!(sent.stats().give_ups, 1);
+
+    sent.reset();
+    assert!(sent.is_empty());
+    assert_eq!(sent.next_seq(), Some(0));
+}
+
+#[test]
+fn control_frames_round_trip() {
+    for checksum in [Checksum::Crc16, Checksum::Crc32] {
+        let mut out = [0u8; CONTROL_MAX_LEN];
+        let mut ack = reliable::encode_ack(7, checksum, &mut out).to_vec();
+        assert_eq!(reliable::decode(&mut ack, checksum), Ok(Frame::Ack(7)));
+
+        let mut nak = reliable::encode_nak(0, checksum, &mut out).to_vec();
+        assert_eq!(reliable::decode(&mut nak, checksum), Ok(Frame::Nak(0)));
+    }
+}
+
+#[test]
+fn duplicates_are_recognised_without_decoding_them() {
+    let mut received = RecvWindow::new();
+    let mut scratch = [0u8; FRAME_LEN];
+    let mut out = [0u8; FRAME_LEN];
+    let frame = reliable::encode_data(0, &500u32, CHECKSUM, &mut scratch, &mut out).unwrap();
+    let mut again = frame.to_vec();
+
+    assert_eq!(
+        received.decode::<u32>(frame, CHECKSUM),
+        Ok(Received::Message { msg: 500, ack: 0 })
+    );
+    // the next message is a different type which the duplicate doesn't decode as
+    assert_eq!(
+        received.decode::<bool>(&mut again, CHECKSUM),
+        Ok(Received::Duplicate { ack: 0 })
+    );
+}
+
+#[test]
+fn new_messages_which_fail_to_decode_are_still_delivered() {
+    let mut received = RecvWindow::new();
+    let mut scratch = [0u8; FRAME_LEN];
+    let mut out = [0u8; FRAME_LEN];
+    let frame = reliable::encode_data(0, &500u32, CHECKSUM, &mut scratch, &mut out).unwrap();
+
+    assert!(matches!(
+        received.decode::<bool>(frame, CHECKSUM),
+        Err(FrameError::Message(_))
+    ));
+    assert_eq!(received.expected(), 1);
+}
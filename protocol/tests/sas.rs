@@ -0,0 +1,76 @@
+use protocol::key_schedule::{KeySchedule, Transcript, SUBKEY_LEN};
+use protocol::sas::ShortAuthString;
+
+fn transcript(frame: &[u8]) -> Transcript {
+    let mut transcript = Transcript::default();
+    transcript.update(frame);
+    transcript
+}
+
+fn schedule(key: [u8; SUBKEY_LEN], client_to_server: &[u8]) -> KeySchedule {
+    KeySchedule::new(
+        &key,
+        &transcript(client_to_server),
+        &transcript(b"server message"),
+    )
+}
+
+fn sas(seed: u8) -> ShortAuthString {
+    ShortAuthString::new(&schedule([seed; SUBKEY_LEN], b"client message"))
+}
+
+#[test]
+fn both_sides_derive_the_same_string() {
+    let (sent, received) = (transcript(b"client message"), transcript(b"server message"));
+    let client = KeySchedule::new(&[0x42; SUBKEY_LEN], &sent, &received);
+    let server = KeySchedule::new(&[0x42; SUBKEY_LEN], &sent, &received);
+
+    let code = ShortAuthString::new(&client);
+    assert_eq!(code, ShortAuthString::new(&server));
+    assert_eq!(code.to_string(), ShortAuthString::new(&server).to_string());
+}
+
+#[test]
+fn the_string_depends_on_the_transcript() {
+    let key = [0x42; SUBKEY_LEN];
+    let ours = ShortAuthString::new(&schedule(key, b"client message"));
+    let theirs = ShortAuthString::new(&schedule(key, b"client messagf"));
+    assert_ne!(ours, theirs);
+}
+
+#[test]
+fn the_string_depends_on_the_exporter_label() {
+    let schedule = schedule([0x42; SUBKEY_LEN], b"client message");
+    let code = ShortAuthString::new(&schedule).code();
+
+    // the same schedule exported under another context gives an unrelated code
+    let mut bytes = [0u8; 8];
+    schedule.exporter(b"another context", &mut bytes).unwrap();
+    assert_ne!(code, (u64::from_le_bytes(bytes) % 1_000_000) as u32);
+}
+
+#[test]
+fn codes_are_six_digits() {
+    for seed in 0..=u8::MAX {
+        assert!(sas(seed).code() < 1_000_000);
+    }
+}
+
+#[test]
+fn groups_are_zero_padded() {
+    // find keys whose codes have a leading zero in each group
+    let mut codes = (0..=u8::MAX).map(sas);
+    let high = codes.clone().find(|sas| sas.code() < 100_000).unwrap();
+    let low = codes.find(|sas| sas.code() % 1000 < 100).unwrap();
+
+    for sas in [high, low] {
+        let code = sas.code();
+        assert_eq!(
+            sas.to_string(),
+            format!("{:03} {:03}", code / 1000, code % 1000)
+        );
+    }
+    assert!(high.to_string().starts_with('0'));
+    assert_eq!(low.to_string().as_bytes()[4], b'0');
+    assert_eq!(high.to_string().len(), "042 917".len());
+}
@@ -0,0 +1,453 @@
+//! Derives the description of the messages from their types and checks `SCHEMA` against it
+//!
+//! Each message type is deserialized from a tracer, which answers every request for data with a
+//! placeholder and writes down what was asked for. An enum only gets to pick one variant each
+//! time, so the messages are traced over and over until every variant has been seen. Strings are
+//! checked by whoever reads them, so a pass which fails on a string is run again with another.
+
+use aucpace::{ClientMessage, ServerMessage};
+use protocol::admission::Admission;
+use protocol::confirmation::KeyConfirmation;
+use protocol::events::{Event, LogRequest, LogResponse};
+use protocol::fragment::Fragment;
+use protocol::link::{BaudRequest, BaudResponse, LinkCheck};
+use protocol::phase::PhaseTimings;
+use protocol::preamble::{Preamble, PreambleResponse, SCHEMA};
+use protocol::resumption::{NewTicket, ResumeFinish, ResumeResponse, SessionStart};
+use serde::de::value::{Error, U32Deserializer};
+use serde::de::{self, DeserializeSeed, EnumAccess, SeqAccess, VariantAccess, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize};
+use std::fmt::Write;
+
+/// The nonce length both sides use, `K1` in the server's config and in the client
+const K1: usize = 16;
+
+/// Strings to hand out in turn until whoever reads one accepts it, e.g. a salt or scrypt's params
+const STRINGS: [&str; 2] = ["", "AAAAAAAAAAAAAAAAAAAAAA"];
+
+/// What a value is sent as
+#[derive(Debug, Clone, Default)]
+enum Format {
+    #[default]
+    Unknown,
+    Primitive(&'static str),
+    Bytes,
+    Array(Box<Format>, usize),
+    Named(&'static str),
+}
+
+#[derive(Debug)]
+enum Variant {
+    Unit,
+    Newtype(Format),
+    Struct(Vec<(&'static str, Format)>),
+}
+
+#[derive(Debug)]
+enum Container {
+    Struct(Vec<(&'static str, Format)>),
+    Enum {
+        variants: Vec<(&'static str, Option<Variant>)>,
+        /// The variant to pick the next time the enum is traced
+        next: usize,
+    },
+}
+
+/// Every named type traced so far, in the order they were first seen
+#[derive(Default)]
+struct Schema {
+    types: Vec<(&'static str, Container)>,
+    /// The string handed out at each point a string was read during this pass
+    strings: Vec<usize>,
+    /// How many strings have been read during this pass
+    position: usize,
+}
+
+impl Schema {
+    fn get(&mut self, name: &str) -> Option<&mut Container> {
+        self.types
+            .iter_mut()
+            .find(|(n, _)| *n == name)
+            .map(|(_, container)| container)
+    }
+
+    /// Whether every variant of every enum seen so far has been traced
+    fn is_complete(&self) -> bool {
+        self.types.iter().all(|(_, container)| match container {
+            Container::Struct(_) => true,
+            Container::Enum { variants, .. } => variants.iter().all(|(_, v)| v.is_some()),
+        })
+    }
+
+    /// The variant each enum will pick next
+    fn cursors(&self) -> Vec<usize> {
+        self.types
+            .iter()
+            .map(|(_, container)| match container {
+                Container::Struct(_) => 0,
+                Container::Enum { next, .. } => *next,
+            })
+            .collect()
+    }
+
+    /// Pick the same variants as when `cursors` were taken
+    fn rewind(&mut self, cursors: &[usize]) {
+        for (i, (_, container)) in self.types.iter_mut().enumerate() {
+            if let Container::Enum { next, .. } = container {
+                *next = cursors.get(i).copied().unwrap_or(0);
+            }
+        }
+    }
+
+    /// Trace `T` until every variant of the enums it holds has been seen
+    fn trace<T: Deserialize<'static>>(&mut self) {
+        loop {
+            let cursors = self.cursors();
+            self.position = 0;
+            let traced = T::deserialize(Tracer {
+                schema: self,
+                format: &mut Format::Unknown,
+            });
+            match traced {
+                Ok(_) => self.strings.clear(),
+                // the last string read was rejected, try the next one on the same variants
+                Err(e) => match self.strings.get_mut(self.position.wrapping_sub(1)) {
+                    Some(string) if *string + 1 < STRINGS.len() => {
+                        *string += 1;
+                        self.strings.truncate(self.position);
+                        self.rewind(&cursors);
+                        continue;
+                    }
+                    _ => panic!("failed to trace {}: {e}", std::any::type_name::<T>()),
+                },
+            }
+            if self.is_complete() {
+                return;
+            }
+        }
+    }
+
+    /// The schema in `SCHEMA`'s notation, one type after another
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, container) in &self.types {
+            if !out.is_empty() {
+                out.push(';');
+            }
+            out.push_str(name);
+            match container {
+                Container::Struct(fields) => render_fields(&mut out, fields),
+                Container::Enum { variants, .. } => {
+                    out.push('{');
+                    for (i, (variant, content)) in variants.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        out.push_str(variant);
+                        match content.as_ref().unwrap() {
+                            Variant::Unit => {}
+                            Variant::Newtype(format) => write!(out, "({format})").unwrap(),
+                            Variant::Struct(fields) => render_fields(&mut out, fields),
+                        }
+                    }
+                    out.push('}');
+                }
+            }
+        }
+        out
+    }
+}
+
+fn render_fields(out: &mut String, fields: &[(&'static str, Format)]) {
+    out.push('{');
+    for (i, (field, format)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write!(out, "{field}:{format}").unwrap();
+    }
+    out.push('}');
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Unknown => f.write_str("?"),
+            Format::Primitive(name) | Format::Named(name) => f.write_str(name),
+            Format::Bytes => f.write_str("[u8]"),
+            Format::Array(element, len) => write!(f, "[{element};{len}]"),
+        }
+    }
+}
+
+/// Deserializes a placeholder for every value, writing down what it was asked for in `format`
+struct Tracer<'a> {
+    schema: &'a mut Schema,
+    format: &'a mut Format,
+}
+
+impl<'a> Tracer<'a> {
+    fn trace<'de, T: DeserializeSeed<'de>>(
+        schema: &mut Schema,
+        seed: T,
+    ) -> Result<(T::Value, Format), Error> {
+        let mut format = Format::Unknown;
+        let value = seed.deserialize(Tracer {
+            schema,
+            format: &mut format,
+        })?;
+        Ok((value, format))
+    }
+}
+
+macro_rules! primitive {
+    ($($method:ident $visit:ident $ty:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                *self.format = Format::Primitive(stringify!($ty));
+                visitor.$visit(Default::default())
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Tracer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom(
+            "the tracer only knows the types the messages use",
+        ))
+    }
+
+    primitive!(
+        deserialize_bool visit_bool bool,
+        deserialize_u8 visit_u8 u8,
+        deserialize_u16 visit_u16 u16,
+        deserialize_u32 visit_u32 u32,
+        deserialize_u64 visit_u64 u64
+    );
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        *self.format = Format::Primitive("str");
+        let position = self.schema.position;
+        self.schema.position += 1;
+        if self.schema.strings.len() == position {
+            self.schema.strings.push(0);
+        }
+        visitor.visit_borrowed_str(STRINGS[self.schema.strings[position]])
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        *self.format = Format::Bytes;
+        visitor.visit_borrowed_bytes(&[])
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        let mut elements = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            schema: self.schema,
+            names: &[],
+            formats: &mut elements,
+            remaining: len,
+        })?;
+        let (_, element) = elements.into_iter().next().unwrap_or_default();
+        *self.format = Format::Array(Box::new(element), len);
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        *self.format = Format::Named(name);
+        if self.schema.get(name).is_none() {
+            self.schema
+                .types
+                .push((name, Container::Struct(Vec::new())));
+        }
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            schema: self.schema,
+            names: fields,
+            formats: &mut formats,
+            remaining: fields.len(),
+        })?;
+        *self.schema.get(name).unwrap() = Container::Struct(formats);
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        *self.format = Format::Named(name);
+        if self.schema.get(name).is_none() {
+            let variants = variants.iter().map(|variant| (*variant, None)).collect();
+            let container = Container::Enum { variants, next: 0 };
+            self.schema.types.push((name, container));
+        }
+        // take turns so the enums inside a variant get traced too
+        let Some(Container::Enum { next, .. }) = self.schema.get(name) else {
+            unreachable!("{name} was traced as a struct");
+        };
+        let index = *next;
+        *next = (index + 1) % variants.len();
+
+        visitor.visit_enum(Enum {
+            schema: self.schema,
+            name,
+            index,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u128 f32 f64 char byte_buf option unit unit_struct
+        newtype_struct seq tuple_struct map identifier ignored_any
+    }
+}
+
+/// The fields of a struct or the elements of an array, each traced in turn
+struct Elements<'a> {
+    schema: &'a mut Schema,
+    names: &'static [&'static str],
+    formats: &'a mut Vec<(&'static str, Format)>,
+    remaining: usize,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let (value, format) = Tracer::trace(self.schema, seed)?;
+        let name = self.names.get(self.formats.len()).copied().unwrap_or("");
+        self.formats.push((name, format));
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+/// The variant picked for this pass over the enum `name`
+struct Enum<'a> {
+    schema: &'a mut Schema,
+    name: &'static str,
+    index: usize,
+}
+
+impl Enum<'_> {
+    fn record(self, variant: Variant) {
+        let Some(Container::Enum { variants, .. }) = self.schema.get(self.name) else {
+            unreachable!();
+        };
+        variants[self.index].1 = Some(variant);
+    }
+}
+
+impl<'de, 'a> EnumAccess<'de> for Enum<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let index = U32Deserializer::<Error>::new(self.index as u32);
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Enum<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        self.record(Variant::Unit);
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        let (value, format) = Tracer::trace(self.schema, seed)?;
+        self.record(Variant::Newtype(format));
+        Ok(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom(
+            "none of the messages have tuple variants",
+        ))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            schema: self.schema,
+            names: fields,
+            formats: &mut formats,
+            remaining: fields.len(),
+        })?;
+        self.record(Variant::Struct(formats));
+        Ok(value)
+    }
+}
+
+/// Every message in the order of a session, and the events carried in the log
+fn messages() -> String {
+    let mut schema = Schema::default();
+    schema.trace::<ClientMessage<K1>>();
+    schema.trace::<ServerMessage<K1>>();
+    schema.trace::<Preamble>();
+    schema.trace::<PreambleResponse>();
+    schema.trace::<Fragment>();
+    schema.trace::<BaudRequest>();
+    schema.trace::<BaudResponse>();
+    schema.trace::<LinkCheck>();
+    schema.trace::<SessionStart>();
+    schema.trace::<ResumeResponse>();
+    schema.trace::<ResumeFinish>();
+    schema.trace::<NewTicket>();
+    schema.trace::<Admission>();
+    schema.trace::<KeyConfirmation>();
+    schema.trace::<LogRequest>();
+    schema.trace::<LogResponse>();
+    schema.trace::<Event>();
+    schema.trace::<PhaseTimings>();
+    schema.render()
+}
+
+#[test]
+fn schema_describes_the_messages() {
+    let (aucpace, ours) = SCHEMA.split_once(';').unwrap();
+    assert!(aucpace.starts_with("aucpace@"));
+    let derived = messages();
+    assert!(
+        ours == derived,
+        "the messages have changed, update SCHEMA to\n{aucpace};{derived}"
+    );
+}
+
+#[test]
+fn every_variant_is_described() {
+    let derived = messages();
+    assert!(derived.contains("Phase{Ssid,Augmentation,Pbkdf,CPace,Authenticator,Resumption}"));
+    assert!(derived.contains("PhaseReached(Phase)"));
+    assert!(derived.contains("Throttled{millis:u32}"));
+    assert!(derived.contains("Registration{username:[u8],salt:str,params:str"));
+}
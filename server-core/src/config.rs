@@ -0,0 +1,144 @@
+//! Settings which have to match the client, and the limits the server runs with
+//!
+//! Anything specific to a board, like where in flash things are kept, belongs with the board.
+
+use crate::throttle::{Backoff, ThrottlePolicy};
+use core::time::Duration;
+use protocol::framing::Checksum;
+use protocol::link::LinkConfig;
+use protocol::preamble;
+
+#[cfg(feature = "reliable")]
+use protocol::fragment::fragment_count;
+
+#[cfg(all(feature = "resumption", feature = "implicit"))]
+compile_error!("Resumption tickets are only issued after explicit mutual authentication");
+
+#[cfg(all(feature = "crc16", feature = "crc32"))]
+compile_error!("Only one of the crc16 and crc32 features can be enabled");
+
+#[cfg(all(feature = "reliable", not(any(feature = "crc16", feature = "crc32"))))]
+compile_error!("Reliable delivery needs the crc16 or crc32 feature to spot corrupted frames");
+
+pub const K1: usize = 16;
+pub const RECV_BUF_LEN: usize = 1024;
+
+/// The client can't receive frames any longer than this
+pub const SEND_BUF_LEN: usize = 1024;
+
+/// Longest message the server sends, every one of them is far shorter
+pub const SEND_MSG_LEN: usize = 1024;
+
+/// Longest message the client can send, registrations with user attached data are the longest
+pub const RECV_MSG_LEN: usize = 4096;
+
+/// How long the client has to send its next message before the server gives up on the session,
+/// long enough for the client to hash its password with expensive parameters
+pub const RECV_TIMEOUT_MS: u64 = 30_000;
+
+/// Longest username which can be registered
+pub const MAX_USERNAME_LEN: usize = 100;
+
+/// Features which change the wire format, the client has to be built with the same
+pub const FEATURES: u32 = {
+    let mut features = 0;
+    if cfg!(feature = "strong") {
+        features |= preamble::STRONG;
+    }
+    if cfg!(feature = "implicit") {
+        features |= preamble::IMPLICIT;
+    }
+    if cfg!(feature = "static_ssid") {
+        features |= preamble::STATIC_SSID;
+    }
+    if cfg!(feature = "resumption") {
+        features |= preamble::RESUMPTION;
+    }
+    if cfg!(feature = "baud_negotiation") {
+        features |= preamble::BAUD_NEGOTIATION;
+    }
+    if cfg!(feature = "crc16") {
+        features |= preamble::CRC16;
+    }
+    if cfg!(feature = "crc32") {
+        features |= preamble::CRC32;
+    }
+    if cfg!(feature = "reliable") {
+        features |= preamble::RELIABLE;
+    }
+    if cfg!(feature = "event_log") {
+        features |= preamble::EVENT_LOG;
+    }
+    if cfg!(feature = "timings") {
+        features |= preamble::TIMINGS;
+    }
+    features
+};
+
+/// Checksum on every frame after the preamble, the client has to use the same
+pub const CHECKSUM: Option<Checksum> = if cfg!(feature = "crc16") {
+    Some(Checksum::Crc16)
+} else if cfg!(feature = "crc32") {
+    Some(Checksum::Crc32)
+} else {
+    None
+};
+
+/// Checksum on every frame of the reliable delivery layer
+#[cfg(feature = "reliable")]
+pub const RELIABLE_CHECKSUM: Checksum = match CHECKSUM {
+    Some(checksum) => checksum,
+    None => core::panic!("reliable delivery needs a checksum"),
+};
+
+/// Number of frames which can be waiting for the client to acknowledge them
+///
+/// The protocol never sends more than a couple of messages without hearing back from the client.
+#[cfg(feature = "reliable")]
+pub const WINDOW: usize = 2 * fragment_count(SEND_MSG_LEN);
+
+/// Settings of the serial link every session starts with, the client has to use the same
+///
+/// These are fixed when the firmware is built, so other settings mean editing them here and
+/// rebuilding. Only the baud rate can change at run time, and only for a session which negotiates
+/// it.
+pub const LINK_CONFIG: LinkConfig = LinkConfig::DEFAULT;
+
+/// Baud rates the client can negotiate, faster rates are clamped to `MAX_BAUDRATE`
+#[cfg(feature = "baud_negotiation")]
+pub const MIN_BAUDRATE: u32 = 9600;
+#[cfg(feature = "baud_negotiation")]
+pub const MAX_BAUDRATE: u32 = 921_600;
+
+#[cfg(feature = "resumption")]
+pub const TICKET_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Number of consecutive failures after which the registered user is locked out
+pub const LOCKOUT_THRESHOLD: u32 = 10;
+
+/// Number of events kept for the client to read back, the whole log has to fit in one message
+pub const EVENT_LOG_LEN: usize = 32;
+
+/// Number of usernames the throttle tracks failures for individually
+pub const THROTTLE_USERS: usize = 8;
+
+pub const THROTTLE_POLICY: ThrottlePolicy = ThrottlePolicy {
+    per_user: Backoff {
+        free_attempts: 3,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(5 * 60),
+        reset_after: Duration::from_secs(60 * 60),
+    },
+    global: Backoff {
+        free_attempts: 10,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(60),
+        reset_after: Duration::from_secs(10 * 60),
+    },
+};
+
+#[cfg(feature = "static_ssid")]
+pub const SSID: [u8; 32] = [
+    60, 173, 56, 252, 74, 141, 171, 146, 102, 169, 149, 169, 158, 106, 87, 232, 220, 141, 251, 73,
+    39, 130, 105, 184, 93, 87, 195, 23, 246, 158, 85, 226,
+];
@@ -0,0 +1,332 @@
+#[cfg(not(feature = "strong"))]
+use aucpace::Database;
+
+#[cfg(not(feature = "strong"))]
+mod conditional_imports {
+    pub use password_hash::{Salt, SaltString};
+
+    // normal AuCPace uses a raw salt string to store the salt
+    pub type DbSalt = SaltString;
+}
+
+#[cfg(feature = "strong")]
+mod conditional_imports {
+    pub use aucpace::StrongDatabase;
+    pub use curve25519_dalek::Scalar;
+
+    // Strong AuCPace uses a blinded Scalar to store the salt
+    pub type DbSalt = Scalar;
+}
+
+#[cfg(feature = "partial")]
+use aucpace::PartialAugDatabase;
+
+#[cfg(feature = "partial")]
+use curve25519_dalek::{constants::RISTRETTO_BASEPOINT_POINT, Scalar};
+
+use crate::device_secret::SECRET_LEN;
+use crate::lockout::LockoutRecord;
+use crate::throttle::UserKey;
+use conditional_imports::*;
+use curve25519_dalek::RistrettoPoint;
+use password_hash::ParamsString;
+use sha2::{Digest, Sha512};
+use zeroize::Zeroize;
+
+/// Parameters reported for unknown users before anyone has registered,
+/// matches the `Params::recommended()` the client registers with
+const DEFAULT_FAKE_PARAMS: &str = "ln=17,r=8,p=1";
+
+/// Password Verifier database which can store the info for one user
+#[derive(Debug, Default)]
+pub struct SingleUserDatabase<const USERSIZE: usize> {
+    user: Option<([u8; USERSIZE], usize)>,
+    data: Option<(RistrettoPoint, DbSalt, ParamsString)>,
+
+    #[cfg(feature = "partial")]
+    long_term_keypair: Option<(Scalar, RistrettoPoint)>,
+
+    // accounts are never locked when this is None
+    lockout_threshold: Option<u32>,
+    lockout: Option<LockoutRecord>,
+
+    // unknown users are reported as missing when this is None
+    fake_secret: Option<[u8; SECRET_LEN]>,
+}
+
+impl<const USERSIZE: usize> SingleUserDatabase<USERSIZE> {
+    /// Lock the user after `threshold` consecutive failures
+    pub fn with_lockout_threshold(mut self, threshold: u32) -> Self {
+        self.lockout_threshold = Some(threshold);
+        self
+    }
+
+    /// Answer lookups for unknown users with fake records derived from `secret`
+    ///
+    /// The same username always gets the same fake record, so repeated probes can't tell an
+    /// unknown user apart from a registered one by the salt or parameters changing.
+    pub fn with_fake_secret(mut self, secret: &[u8; SECRET_LEN]) -> Self {
+        self.fake_secret = Some(*secret);
+        self
+    }
+
+    /// Erase the record stored for `username`, returns whether there was one
+    pub fn erase_user(&mut self, username: &[u8]) -> bool {
+        match self.user {
+            Some((ref stored_username, len)) if &stored_username[..len] == username => {
+                self.erase_record();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Erase every secret held by the database
+    pub fn wipe(&mut self) {
+        self.erase_record();
+        self.fake_secret.zeroize();
+        self.lockout = None;
+    }
+
+    fn erase_record(&mut self) {
+        if let Some((ref mut username, _)) = self.user {
+            username.zeroize();
+        }
+        self.user = None;
+
+        #[cfg_attr(not(feature = "strong"), allow(unused_variables))]
+        if let Some((ref mut verifier, ref mut salt, _)) = self.data {
+            verifier.zeroize();
+            // the salt is only secret when it is the blinding exponent of Strong AuCPace
+            #[cfg(feature = "strong")]
+            salt.zeroize();
+        }
+        self.data = None;
+
+        #[cfg(feature = "partial")]
+        {
+            if let Some((ref mut priv_key, ref mut pub_key)) = self.long_term_keypair {
+                priv_key.zeroize();
+                pub_key.zeroize();
+            }
+            self.long_term_keypair = None;
+        }
+    }
+
+    /// Restore the failure count persisted before a reset
+    ///
+    /// The board's provisioning action unlocks an account by erasing the record before it's loaded.
+    pub fn restore_lockout(&mut self, record: LockoutRecord) {
+        self.lockout = Some(record);
+    }
+
+    fn is_registered(&self, user: UserKey) -> bool {
+        match self.user {
+            Some((ref stored_username, len)) => UserKey::new(&stored_username[..len]) == user,
+            None => false,
+        }
+    }
+
+    /// Whether `user` has failed to authenticate too many times to be allowed to log in
+    pub fn is_locked(&self, user: UserKey) -> bool {
+        match (self.lockout, self.lockout_threshold) {
+            (Some(record), Some(threshold)) => record.user == user && record.failures >= threshold,
+            _ => false,
+        }
+    }
+
+    /// Count a failed authentication for `user`
+    ///
+    /// Returns the updated record which should be persisted, if `user` is registered.
+    pub fn record_failure(&mut self, user: UserKey) -> Option<LockoutRecord> {
+        if !self.is_registered(user) {
+            return None;
+        }
+
+        let failures = match self.lockout {
+            Some(record) if record.user == user => record.failures.saturating_add(1),
+            _ => 1,
+        };
+        let record = LockoutRecord { user, failures };
+        self.lockout = Some(record);
+        Some(record)
+    }
+
+    /// Reset the failure count for `user` after it authenticates successfully
+    ///
+    /// Returns the updated record which should be persisted, if anything changed.
+    pub fn record_success(&mut self, user: UserKey) -> Option<LockoutRecord> {
+        match self.lockout {
+            Some(record) if record.user == user && record.failures != 0 => {
+                let record = LockoutRecord { user, failures: 0 };
+                self.lockout = Some(record);
+                Some(record)
+            }
+            _ => None,
+        }
+    }
+
+    /// The verifier returned in place of the real one for a locked user
+    ///
+    /// Nobody knows the discrete log of a point hashed from a constant so no password can match
+    /// it, while the salt and parameters stay the same so the peer can't tell the user is locked.
+    fn locked_verifier() -> RistrettoPoint {
+        let hash: [u8; 64] = Sha512::digest(b"AuCPace locked account verifier").into();
+        RistrettoPoint::from_uniform_bytes(&hash)
+    }
+
+    fn lookup(&self, username: &[u8]) -> Option<(RistrettoPoint, DbSalt, ParamsString)> {
+        match self.user {
+            Some((ref stored_username, len)) if &stored_username[..len] == username => {
+                let (verifier, salt, params) = self.data.clone()?;
+                if self.is_locked(UserKey::new(username)) {
+                    Some((Self::locked_verifier(), salt, params))
+                } else {
+                    Some((verifier, salt, params))
+                }
+            }
+            _ => self.fake_lookup(username),
+        }
+    }
+
+    /// Derive bytes for a fake record from the fake secret, `label` separates each use
+    fn fake_bytes(&self, label: &[u8], username: &[u8]) -> Option<[u8; 64]> {
+        let secret = self.fake_secret.as_ref()?;
+        let mut hasher = Sha512::new();
+        hasher.update(secret);
+        hasher.update(label);
+        hasher.update(username);
+        Some(hasher.finalize().into())
+    }
+
+    /// A record for an unknown user which looks like a registered user's
+    fn fake_lookup(&self, username: &[u8]) -> Option<(RistrettoPoint, DbSalt, ParamsString)> {
+        // as with a locked account nobody knows the discrete log of the verifier
+        let verifier = RistrettoPoint::from_uniform_bytes(&self.fake_bytes(b"verifier", username)?);
+
+        #[cfg(not(feature = "strong"))]
+        let salt = {
+            let bytes = self.fake_bytes(b"salt", username)?;
+            SaltString::encode_b64(&bytes[..Salt::RECOMMENDED_LENGTH]).ok()?
+        };
+        #[cfg(feature = "strong")]
+        let salt = Scalar::from_bytes_mod_order_wide(&self.fake_bytes(b"exponent", username)?);
+
+        // every user registers with the same parameters so copy them from the real user
+        let params = match self.data {
+            Some((_, _, ref params)) => params.clone(),
+            None => DEFAULT_FAKE_PARAMS.parse().ok()?,
+        };
+
+        Some((verifier, salt, params))
+    }
+
+    /// A long term keypair for an unknown user which looks like a registered user's
+    #[cfg(feature = "partial")]
+    fn fake_long_term_keypair(&self, username: &[u8]) -> Option<(Scalar, RistrettoPoint)> {
+        let bytes = self.fake_bytes(b"long term keypair", username)?;
+        let priv_key = Scalar::from_bytes_mod_order_wide(&bytes);
+        Some((priv_key, RISTRETTO_BASEPOINT_POINT * priv_key))
+    }
+}
+
+impl<const USERSIZE: usize> Drop for SingleUserDatabase<USERSIZE> {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+#[cfg(not(feature = "strong"))]
+impl<const USERSIZE: usize> Database for SingleUserDatabase<USERSIZE> {
+    type PasswordVerifier = RistrettoPoint;
+
+    fn lookup_verifier(
+        &self,
+        username: &[u8],
+    ) -> Option<(Self::PasswordVerifier, SaltString, ParamsString)> {
+        self.lookup(username)
+    }
+
+    fn store_verifier(
+        &mut self,
+        username: &[u8],
+        salt: SaltString,
+        // we don't care about this for an example
+        _uad: Option<&[u8]>,
+        verifier: Self::PasswordVerifier,
+        params: ParamsString,
+    ) {
+        // silently fail because this is just an example and I'm lazy
+        if username.len() <= USERSIZE {
+            self.erase_record();
+            let mut buf = [0u8; USERSIZE];
+            buf[..username.len()].copy_from_slice(username);
+            self.user = Some((buf, username.len()));
+            self.data = Some((verifier, salt, params));
+        }
+    }
+}
+
+#[cfg(feature = "strong")]
+impl<const USERSIZE: usize> StrongDatabase for SingleUserDatabase<USERSIZE> {
+    type PasswordVerifier = RistrettoPoint;
+    type Exponent = Scalar;
+
+    fn lookup_verifier_strong(
+        &self,
+        username: &[u8],
+    ) -> Option<(Self::PasswordVerifier, Self::Exponent, ParamsString)> {
+        self.lookup(username)
+    }
+
+    fn store_verifier_strong(
+        &mut self,
+        username: &[u8],
+        _uad: Option<&[u8]>,
+        verifier: Self::PasswordVerifier,
+        secret_exponent: Self::Exponent,
+        params: ParamsString,
+    ) {
+        // silently fail because this is just an example and I'm lazy
+        if username.len() <= USERSIZE {
+            self.erase_record();
+            let mut buf = [0u8; USERSIZE];
+            buf[..username.len()].copy_from_slice(username);
+            self.user = Some((buf, username.len()));
+            self.data = Some((verifier, secret_exponent, params));
+        }
+    }
+}
+
+#[cfg(feature = "partial")]
+impl<const USERSIZE: usize> PartialAugDatabase for SingleUserDatabase<USERSIZE> {
+    type PrivateKey = Scalar;
+    type PublicKey = RistrettoPoint;
+
+    fn lookup_long_term_keypair(
+        &self,
+        username: &[u8],
+    ) -> Option<(Self::PrivateKey, Self::PublicKey)> {
+        match self.user {
+            Some((ref stored_username, len)) if &stored_username[..len] == username => {
+                self.long_term_keypair
+            }
+            _ => self.fake_long_term_keypair(username),
+        }
+    }
+
+    fn store_long_term_keypair(
+        &mut self,
+        username: &[u8],
+        priv_key: Self::PrivateKey,
+        pub_key: Self::PublicKey,
+    ) -> aucpace::Result<()> {
+        match self.user {
+            Some((ref stored_user, len)) if &stored_user[..len] == username => {
+                self.long_term_keypair = Some((priv_key, pub_key));
+                Ok(())
+            }
+            _ => Err(aucpace::Error::UserNotRegistered),
+        }
+    }
+}
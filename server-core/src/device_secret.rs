@@ -0,0 +1,45 @@
+use embedded_storage::nor_flash::NorFlash;
+use rand_core::CryptoRngCore;
+use zeroize::{Zeroize, Zeroizing};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"SCRT");
+const ERASED: u8 = 0xFF;
+
+/// Length of the device secret
+pub const SECRET_LEN: usize = 32;
+
+/// The magic number and secret, padded to a size any flash write granularity we care about divides
+const SLOT_LEN: usize = 40;
+
+/// Load the secret unique to this device, generating and storing one the first time it boots
+///
+/// The secret lives in the `size` bytes of `flash` starting at `offset`, which must be whole
+/// erase sectors that nothing else uses.
+pub fn load_or_generate<F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+    size: u32,
+    rng: &mut impl CryptoRngCore,
+) -> Result<Zeroizing<[u8; SECRET_LEN]>, F::Error> {
+    let mut slot = Zeroizing::new([0u8; SLOT_LEN]);
+    flash.read(offset, slot.as_mut())?;
+
+    let mut secret = Zeroizing::new([0u8; SECRET_LEN]);
+    if slot[..4] == MAGIC.to_le_bytes() {
+        secret.copy_from_slice(&slot[4..4 + SECRET_LEN]);
+        return Ok(secret);
+    }
+
+    // anything other than erased flash means a previous write was interrupted
+    if slot.iter().any(|b| *b != ERASED) {
+        flash.erase(offset, offset + size)?;
+    }
+
+    rng.fill_bytes(secret.as_mut());
+    slot.zeroize();
+    slot[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    slot[4..4 + SECRET_LEN].copy_from_slice(secret.as_ref());
+    flash.write(offset, slot.as_ref())?;
+
+    Ok(secret)
+}
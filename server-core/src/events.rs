@@ -0,0 +1,51 @@
+//! The server's event log, the most recent events kept in RAM for an authenticated client to read
+//!
+//! Nothing is persisted, the log starts empty on every boot.
+
+use crate::transport::Clock;
+use heapless::HistoryBuffer;
+use protocol::events::{Event, EventKind};
+
+pub struct EventLog<C, const N: usize> {
+    clock: C,
+    events: HistoryBuffer<Event, N>,
+    recorded: u32,
+    session: u32,
+}
+
+impl<C: Clock, const N: usize> EventLog<C, N> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            events: HistoryBuffer::new(),
+            recorded: 0,
+            session: 0,
+        }
+    }
+
+    /// Record `kind` as happening now, overwriting the oldest event once the log is full
+    pub fn record(&mut self, kind: EventKind) {
+        self.events.write(Event {
+            at_ms: self.clock.now_ms(),
+            session: self.session,
+            kind,
+        });
+        self.recorded = self.recorded.saturating_add(1);
+    }
+
+    /// Record the start of a session, events from here on belong to it
+    pub fn start_session(&mut self) {
+        self.session = self.session.wrapping_add(1);
+        self.record(EventKind::SessionStarted);
+    }
+
+    /// The events still held, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.oldest_ordered()
+    }
+
+    /// Events recorded since boot, including the ones which have been overwritten
+    pub fn recorded(&self) -> u32 {
+        self.recorded
+    }
+}
@@ -0,0 +1,106 @@
+//! Logging macros which go through defmt on the board and nowhere on the host
+//!
+//! Without defmt the arguments are still borrowed, so nothing only used in logs is left unused.
+#![macro_use]
+#![allow(unused_macros)]
+
+macro_rules! trace {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::trace!($s $(, $x)*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::debug!($s $(, $x)*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::info!($s $(, $x)*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+macro_rules! warn {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::warn!($s $(, $x)*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+macro_rules! error {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::error!($s $(, $x)*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! unwrap {
+    ($($x:tt)*) => {
+        ::defmt::unwrap!($($x)*)
+    };
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! unwrap {
+    ($arg:expr) => {
+        $arg.unwrap()
+    };
+    ($arg:expr, $msg:literal) => {
+        $arg.expect($msg)
+    };
+}
+
+/// Writing to a heapless::String then sending and clearing is annoying
+macro_rules! fmt_log {
+    (ERROR, $s:ident, $($arg:tt)*) => {
+        core::write!($s, $($arg)*).ok();
+        error!("{}", $s.as_str());
+        $s.clear();
+    };
+    (WARN, $s:ident, $($arg:tt)*) => {
+        core::write!($s, $($arg)*).ok();
+        warn!("{}", $s.as_str());
+        $s.clear();
+    };
+    (INFO, $s:ident, $($arg:tt)*) => {
+        core::write!($s, $($arg)*).ok();
+        info!("{}", $s.as_str());
+        $s.clear();
+    };
+    (DEBUG, $s:ident, $($arg:tt)*) => {
+        core::write!($s, $($arg)*).ok();
+        debug!("{}", $s.as_str());
+        $s.clear();
+    };
+    (TRACE, $s:ident, $($arg:tt)*) => {
+        core::write!($s, $($arg)*).ok();
+        trace!("{}", $s.as_str());
+        $s.clear();
+    };
+}
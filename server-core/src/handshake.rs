@@ -0,0 +1,1119 @@
+//! The server's side of the exchange, from registration through to the session keys
+//!
+//! `Handshake` drives one session at a time over whatever link the board gives it, the firmware
+//! runs `session` in a loop once a user has registered.
+
+use crate::config::{
+    EVENT_LOG_LEN, FEATURES, K1, LOCKOUT_THRESHOLD, MAX_USERNAME_LEN, RECV_TIMEOUT_MS,
+    THROTTLE_POLICY, THROTTLE_USERS,
+};
+use crate::database::SingleUserDatabase;
+use crate::device_secret::SECRET_LEN;
+use crate::events::EventLog;
+use crate::lockout::{LockoutRecord, LockoutStore};
+use crate::receiver::MsgReceiver;
+use crate::sender::MsgSender;
+use crate::throttle::{Throttle, UserKey};
+use crate::transport::{Clock, Link};
+use aucpace::{AuCPaceServer, ClientMessage};
+use core::fmt::Write as _;
+use embedded_io_async::{Read, Write};
+use embedded_storage::nor_flash::NorFlash;
+use heapless::String;
+use protocol::admission::Admission;
+use protocol::confirmation::KeyConfirmation;
+use protocol::events::EventKind;
+use protocol::fragment::FragmentError;
+use protocol::framing::{FrameError, FrameStats};
+use protocol::key_schedule::KeySchedule;
+use protocol::phase::{Phase, PhaseTimings};
+use protocol::preamble::{Mismatch, Preamble, PreambleResponse, CHANNEL_ID};
+use protocol::sas::ShortAuthString;
+use rand_chacha::ChaCha8Rng;
+use rand_core::{CryptoRng, CryptoRngCore, RngCore, SeedableRng};
+use sha2::Sha512;
+use zeroize::Zeroize;
+
+#[cfg(not(feature = "strong"))]
+use aucpace::Database;
+
+#[cfg(feature = "strong")]
+use aucpace::StrongDatabase;
+
+#[cfg(feature = "partial")]
+use aucpace::PartialAugDatabase;
+
+#[cfg(feature = "resumption")]
+use {
+    crate::config::TICKET_LIFETIME,
+    crate::tickets::{TicketIssuer, TICKET_LEN},
+    protocol::resumption::{
+        self, NewTicket, ResumeFinish, ResumeResponse, ResumeTranscript, SessionStart,
+    },
+};
+
+#[cfg(any(not(feature = "reliable"), feature = "baud_negotiation"))]
+use crate::transport::with_deadline;
+
+#[cfg(feature = "baud_negotiation")]
+use {
+    crate::config::{LINK_CONFIG, MAX_BAUDRATE, MIN_BAUDRATE},
+    protocol::link::{
+        BaudRequest, BaudResponse, LinkCheck, LinkConfig, CHECK_TIMEOUT_MS, SWITCH_DELAY_MS,
+    },
+};
+
+#[cfg(feature = "reliable")]
+use {
+    protocol::reliable::{Received, RecvStats, SendStats},
+    serde::de::IgnoredAny,
+};
+
+#[cfg(feature = "event_log")]
+use protocol::events::{self, LogRequest, LogResponse, MAX_EVENT_LEN};
+
+/// function like macro to wrap receiving data from the client, defaults to receiving a `ClientMessage`
+///
+/// The sender answers the client's resend requests and asks for corrupted frames again.
+/// Messages which don't parse are recorded in the event log, as is the client going quiet for
+/// `RECV_TIMEOUT_MS`, which gives `Err(SessionError::Timeout)`.
+#[cfg(not(feature = "reliable"))]
+macro_rules! recv {
+    ($recvr:ident, $sendr:ident, $events:ident, $clock:ident, $s:ident) => {
+        recv!($recvr, $sendr, $events, $clock, $s, ClientMessage<K1>)
+    };
+    ($recvr:ident, $sendr:ident, $events:ident, $clock:ident, $s:ident, $msg_ty:ty) => {{
+        let deadline = $clock.now_ms().saturating_add(RECV_TIMEOUT_MS);
+        loop {
+            let Some(parsed) =
+                with_deadline(&*$clock, deadline, $recvr.recv_msg::<$msg_ty>()).await
+            else {
+                $events.record(EventKind::Timeout);
+                warn!("The client stopped answering - giving up on the session");
+                break Err(SessionError::Timeout);
+            };
+            match parsed {
+                Ok(msg) => {
+                    fmt_log!(DEBUG, $s, "Parsed message - {msg:?}");
+                    break Ok(msg);
+                }
+                Err(FrameError::ResendRequest) => {
+                    warn!("Client asked for the last frame again");
+                    $sendr.resend_last().await;
+                }
+                Err(FrameError::Checksum) => {
+                    warn!("Received a corrupted frame - asking for it again");
+                    $sendr.request_resend().await;
+                }
+                Err(e) => {
+                    $events.record(EventKind::ParseError);
+                    fmt_log!(ERROR, $s, "Failed to parse message - {e:?}");
+                }
+            };
+        }
+    }};
+}
+
+/// function like macro to wrap receiving data from the client, defaults to receiving a `ClientMessage`
+///
+/// The sender acknowledges every message, answers the client's ACKs and NAKs,
+/// and sends frames again while it waits.
+/// Messages which don't parse are recorded in the event log, as is giving up on the client after
+/// too many retransmits or `RECV_TIMEOUT_MS` without its next message, which both give
+/// `Err(SessionError::Timeout)`.
+#[cfg(feature = "reliable")]
+macro_rules! recv {
+    ($recvr:ident, $sendr:ident, $events:ident, $clock:ident, $s:ident) => {
+        recv!($recvr, $sendr, $events, $clock, $s, ClientMessage<K1>)
+    };
+    ($recvr:ident, $sendr:ident, $events:ident, $clock:ident, $s:ident, $msg_ty:ty) => {{
+        let deadline = $clock.now_ms().saturating_add(RECV_TIMEOUT_MS);
+        loop {
+            let wake = match $sendr.retransmit_deadline() {
+                Some(retransmit) => retransmit.min(deadline),
+                None => deadline,
+            };
+            let parsed = $recvr.recv_msg::<$msg_ty>(Some(wake)).await;
+            match parsed {
+                None => {
+                    if $clock.now_ms() >= deadline || !$sendr.retransmit().await {
+                        $events.record(EventKind::Timeout);
+                        warn!("The client stopped answering - giving up on the session");
+                        break Err(SessionError::Timeout);
+                    }
+                }
+                Some(Ok(Received::Message {
+                    msg: Some(msg),
+                    ack,
+                })) => {
+                    $sendr.ack(ack).await;
+                    fmt_log!(DEBUG, $s, "Parsed message - {msg:?}");
+                    break Ok(msg);
+                }
+                // more of the message is still to come
+                Some(Ok(Received::Message { msg: None, ack })) => $sendr.ack(ack).await,
+                Some(Ok(Received::Duplicate { ack })) => {
+                    warn!("Received a message twice - acknowledging it again");
+                    $sendr.ack(ack).await;
+                }
+                Some(Ok(Received::Gap { expected })) => {
+                    warn!("Missed message {} - asking for it again", expected);
+                    $sendr.nak(expected).await;
+                }
+                Some(Ok(Received::Ack(seq))) => $sendr.acked(seq),
+                Some(Ok(Received::Nak(seq))) => {
+                    warn!("Client asked for every frame from {} again", seq);
+                    $sendr.nakked(seq).await;
+                }
+                Some(Err(FrameError::Checksum | FrameError::Malformed)) => {
+                    warn!("Received a corrupted frame - asking for it again");
+                    $sendr.nak($recvr.expected()).await;
+                }
+                Some(Err(e)) => {
+                    // the message still arrived, so it mustn't be sent again
+                    $events.record(EventKind::ParseError);
+                    fmt_log!(ERROR, $s, "Failed to parse message - {e:?}");
+                    $sendr.ack($recvr.expected().wrapping_sub(1)).await;
+                }
+            };
+        }
+    }};
+}
+
+/// A session the client authenticated
+pub struct Session {
+    /// Subkeys for the rest of the session, the raw AuCPace key is never used directly
+    pub schedule: KeySchedule,
+    /// Whether the session was resumed with a ticket rather than a full AuCPace exchange
+    pub resumed: bool,
+    /// Bytes sent to the client after the preamble
+    pub bytes_sent: usize,
+    /// Time spent computing in each phase rather than waiting for the client
+    pub timings: PhaseTimings,
+}
+
+/// Why a session ended without the client authenticating
+#[derive(Debug)]
+pub enum SessionError {
+    /// The client's preamble doesn't match ours
+    Preamble,
+    /// The client sent a message which doesn't belong at this point of the exchange
+    UnexpectedMessage,
+    /// The client failed too often recently, it was told to wait this long
+    Throttled { millis: u32 },
+    /// AuCPace rejected what the client sent
+    Protocol(aucpace::Error),
+    /// The client doesn't know the password
+    AuthFailed,
+    /// The client failed the resumption authentication check
+    #[cfg(feature = "resumption")]
+    ResumeFailed,
+    /// The client stopped answering partway through the session
+    Timeout,
+    /// A message to the client couldn't be split into fragments to send
+    Encode(FragmentError),
+}
+
+impl From<FragmentError> for SessionError {
+    fn from(e: FragmentError) -> Self {
+        SessionError::Encode(e)
+    }
+}
+
+/// Runs the server's side of the protocol with a single registered user
+pub struct Handshake<L: Link, C, R, F> {
+    #[cfg_attr(not(feature = "baud_negotiation"), allow(dead_code))]
+    link: L,
+    clock: C,
+    sender: MsgSender<L::Tx, C>,
+    receiver: MsgReceiver<L::Rx, C>,
+    server: AuCPaceServer<Sha512, R, K1>,
+    database: SingleUserDatabase<MAX_USERNAME_LEN>,
+    throttle: Throttle<THROTTLE_USERS>,
+    lockout: LockoutStore<F>,
+    events: EventLog<C, EVENT_LOG_LEN>,
+    rng: ChaCha8Rng,
+    #[cfg(feature = "resumption")]
+    tickets: TicketIssuer,
+    #[cfg(feature = "resumption")]
+    ticket_buf: [u8; TICKET_LEN],
+    #[cfg(feature = "baud_negotiation")]
+    current_link: LinkConfig,
+    s: String<1024>,
+}
+
+impl<L, C, R, F> Handshake<L, C, R, F>
+where
+    L: Link,
+    C: Clock + Clone,
+    R: RngCore + CryptoRng,
+    F: NorFlash,
+{
+    /// Talk to the client over `tx` and `rx`, the halves of `link` opened with `LINK_CONFIG`
+    ///
+    /// Failures are persisted to `lockout`, which should already have been loaded into `database`.
+    /// Every session is seeded from `rng`, and resumption tickets are sealed under a key derived
+    /// from `device_secret` and `rng`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        link: L,
+        tx: L::Tx,
+        rx: L::Rx,
+        clock: C,
+        server: AuCPaceServer<Sha512, R, K1>,
+        database: SingleUserDatabase<MAX_USERNAME_LEN>,
+        lockout: LockoutStore<F>,
+        #[cfg_attr(not(feature = "resumption"), allow(unused_variables))]
+        device_secret: &[u8; SECRET_LEN],
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        #[cfg(feature = "resumption")]
+        let tickets = {
+            let tickets = TicketIssuer::new(device_secret, rng, TICKET_LIFETIME);
+            info!("Created the ticket issuer");
+            tickets
+        };
+
+        Self {
+            link,
+            sender: MsgSender::new(tx, clock.clone()),
+            receiver: MsgReceiver::new(rx, clock.clone()),
+            events: EventLog::new(clock.clone()),
+            rng: fork(rng),
+            clock,
+            server,
+            database,
+            throttle: Throttle::new(THROTTLE_POLICY),
+            lockout,
+            #[cfg(feature = "resumption")]
+            tickets,
+            #[cfg(feature = "resumption")]
+            ticket_buf: [0u8; TICKET_LEN],
+            #[cfg(feature = "baud_negotiation")]
+            current_link: LINK_CONFIG,
+            s: String::new(),
+        }
+    }
+
+    /// The database holding the registered user
+    pub fn database(&self) -> &SingleUserDatabase<MAX_USERNAME_LEN> {
+        &self.database
+    }
+
+    /// What happened since boot, the client reads this back when the event log is enabled
+    pub fn events(&self) -> &EventLog<C, EVENT_LOG_LEN> {
+        &self.events
+    }
+
+    /// Wait for a user to register themselves, anything else the client sends is ignored
+    pub async fn register(&mut self) {
+        let Self {
+            clock,
+            sender,
+            receiver,
+            server: base_server,
+            database,
+            events,
+            s,
+            ..
+        } = self;
+
+        info!("Waiting for a registration packet.");
+        #[cfg_attr(not(feature = "partial"), allow(unused))]
+        let user = loop {
+            if !accept_preamble(sender, receiver, s).await {
+                continue;
+            }
+
+            let Ok(msg) = recv!(receiver, sender, events, clock, s) else {
+                continue;
+            };
+            #[cfg(not(feature = "strong"))]
+            if let ClientMessage::Registration {
+                username,
+                salt,
+                params,
+                verifier,
+            } = msg
+            {
+                if username.len() > MAX_USERNAME_LEN {
+                    error!("Attempted to register with a username thats too long.");
+                } else {
+                    database.store_verifier(username, salt, None, verifier, params);
+                    info!("Registered {:a} for AuCPace", username);
+                    events.record(EventKind::Registered);
+                    break username;
+                }
+            }
+
+            #[cfg(feature = "strong")]
+            if let ClientMessage::StrongRegistration {
+                username,
+                secret_exponent,
+                params,
+                verifier,
+            } = msg
+            {
+                if username.len() > MAX_USERNAME_LEN {
+                    error!("Attempted to register with a username thats too long.");
+                } else {
+                    database.store_verifier_strong(
+                        username,
+                        None,
+                        verifier,
+                        secret_exponent,
+                        params,
+                    );
+                    info!("Registered {:a} for Strong AuCPace", username);
+                    events.record(EventKind::Registered);
+                    break username;
+                }
+            }
+        };
+
+        #[cfg(feature = "partial")]
+        {
+            let (priv_key, pub_key) = base_server.generate_long_term_keypair();
+            // it is fine to unwrap here because we have already registered
+            // a verifier for the user with store_verifier
+            database
+                .store_long_term_keypair(user, priv_key, pub_key)
+                .unwrap();
+            info!("Stored a long term keypair for {:a}", user);
+        }
+        #[cfg(not(feature = "partial"))]
+        let _ = base_server;
+    }
+
+    /// Run a session with the client, from its preamble through to the session keys
+    ///
+    /// Returns once the session is over, whether or not the client authenticated.
+    pub async fn session(&mut self) -> Result<Session, SessionError> {
+        let Self {
+            #[cfg(feature = "baud_negotiation")]
+            link,
+            clock,
+            sender,
+            receiver,
+            server: base_server,
+            database,
+            throttle,
+            lockout,
+            events,
+            rng,
+            #[cfg(feature = "resumption")]
+            tickets,
+            #[cfg(feature = "resumption")]
+            ticket_buf,
+            #[cfg(feature = "baud_negotiation")]
+            current_link,
+            s,
+            ..
+        } = self;
+
+        // the client has to have every message from the last session before the next one starts
+        #[cfg(feature = "reliable")]
+        flush(sender, receiver).await;
+
+        // every session starts at the configured rate
+        #[cfg(feature = "baud_negotiation")]
+        if *current_link != LINK_CONFIG {
+            switch_link(link, sender, receiver, &LINK_CONFIG);
+            *current_link = LINK_CONFIG;
+        }
+
+        // ===== Preamble =====
+        if !accept_preamble(sender, receiver, s).await {
+            return Err(SessionError::Preamble);
+        }
+        events.start_session();
+
+        // ===== Baud Rate Negotiation =====
+        // this happens outside the transcript as frames can be lost while switching
+        #[cfg(feature = "baud_negotiation")]
+        {
+            let BaudRequest { baudrate } = recv!(receiver, sender, events, clock, s, BaudRequest)?;
+            let baudrate = baudrate.clamp(MIN_BAUDRATE, MAX_BAUDRATE);
+            sender.send_msg(&BaudResponse { baudrate }).await?;
+
+            if baudrate != current_link.baudrate {
+                clock.delay_ms(SWITCH_DELAY_MS).await;
+                let negotiated = LINK_CONFIG.with_baudrate(baudrate);
+                switch_link(link, sender, receiver, &negotiated);
+                if confirm_link(clock, sender, receiver).await {
+                    *current_link = negotiated;
+                    info!("Switched to {} baud", baudrate);
+                } else {
+                    switch_link(link, sender, receiver, &LINK_CONFIG);
+                    events.record(EventKind::Timeout);
+                    warn!(
+                        "Link check at {} baud failed - staying at {} baud",
+                        baudrate, LINK_CONFIG.baudrate
+                    );
+                }
+            }
+        }
+
+        let mut timings = PhaseTimings::default();
+
+        let start = clock.now_micros();
+        let mut session_rng = fork(rng);
+        let mut bytes_sent = 0;
+        timings.add(Phase::Ssid, clock.now_micros() - start);
+        info!("Seeded Session RNG");
+
+        // each session has its own transcript to bind the key schedule to
+        receiver.reset_transcript();
+        sender.reset_transcript();
+
+        // frames lost while switching rates don't carry over into the session
+        #[cfg(feature = "reliable")]
+        {
+            receiver.reset_window();
+            sender.reset_window();
+        }
+
+        // ===== Session Resumption =====
+        #[cfg(feature = "resumption")]
+        'resume: {
+            let start_message = recv!(receiver, sender, events, clock, s, SessionStart)?;
+            if let SessionStart::Resume {
+                ticket,
+                nonce: client_nonce,
+            } = start_message
+            {
+                info!("Received resumption ticket");
+                events.record(EventKind::PhaseReached(Phase::Resumption));
+                let t0 = clock.now_micros();
+                let mut server_nonce = [0u8; resumption::NONCE_LEN];
+                session_rng.fill_bytes(&mut server_nonce);
+                let transcript = ResumeTranscript::new(ticket, client_nonce, server_nonce);
+                let secret = match tickets.redeem(ticket, clock.now_ms()) {
+                    Ok(secret) => secret,
+                    Err(e) => {
+                        let message = ResumeResponse::Rejected;
+                        bytes_sent += sender.send_msg(&message).await?;
+                        events.record(EventKind::AuthFailed);
+                        warn!(
+                            "Rejected resumption ticket - {}, falling back to AuCPace",
+                            e
+                        );
+
+                        // the client carries on with a full handshake in the same session
+                        let SessionStart::Full =
+                            recv!(receiver, sender, events, clock, s, SessionStart)?
+                        else {
+                            error!("Client tried to resume twice in one session");
+                            return Err(SessionError::UnexpectedMessage);
+                        };
+                        break 'resume;
+                    }
+                };
+                let message = ResumeResponse::Accepted {
+                    nonce: server_nonce,
+                    mac: transcript.server_mac(&secret),
+                };
+                timings.add(Phase::Resumption, clock.now_micros() - t0);
+                bytes_sent += sender.send_msg(&message).await?;
+                info!("Sent resumption acceptance");
+
+                let ResumeFinish { mac } = recv!(receiver, sender, events, clock, s, ResumeFinish)?;
+                let t0 = clock.now_micros();
+                if !transcript.verify_client_mac(&secret, &mac) {
+                    events.record(EventKind::AuthFailed);
+                    error!("Client failed the resumption authentication check");
+                    return Err(SessionError::ResumeFailed);
+                }
+                events.record(EventKind::AuthSucceeded);
+                let key = transcript.session_key(&secret);
+                let schedule =
+                    KeySchedule::new(key.as_ref(), receiver.transcript(), sender.transcript());
+                drop(key);
+
+                // the ticket has been used up so hand out a fresh one
+                let issued = tickets.issue(&schedule.resumption(), clock.now_ms(), ticket_buf);
+                timings.add(Phase::Resumption, clock.now_micros() - t0);
+                if issued.is_some() {
+                    let message = NewTicket {
+                        ticket: &ticket_buf[..],
+                        lifetime_secs: tickets.lifetime().as_secs() as u32,
+                    };
+                    bytes_sent += sender.send_msg(&message).await?;
+                    info!("Sent new resumption ticket");
+                }
+
+                #[cfg(feature = "event_log")]
+                {
+                    let request = recv!(receiver, sender, events, clock, s, LogRequest)?;
+                    serve_event_log(sender, events, &schedule, &request).await?;
+                }
+                #[cfg(feature = "timings")]
+                sender.send_msg(&timings).await?;
+
+                info!("Resumed session");
+                log_keys(&schedule, s);
+                info!("Total bytes sent: {}", bytes_sent);
+                log_stats(receiver.stats());
+                #[cfg(feature = "reliable")]
+                log_reliable_stats(sender.reliable_stats(), receiver.reliable_stats());
+                log_timings(&timings, s);
+                return Ok(Session {
+                    schedule,
+                    resumed: true,
+                    bytes_sent,
+                    timings,
+                });
+            }
+        }
+
+        // now do a key-exchange
+        info!("Beginning AuCPace protocol");
+
+        // ===== SSID Establishment =====
+        events.record(EventKind::PhaseReached(Phase::Ssid));
+        #[cfg(feature = "static_ssid")]
+        let server = {
+            let t0 = clock.now_micros();
+            let server = base_server
+                .begin_prestablished_ssid(crate::config::SSID)
+                .unwrap();
+            timings.add(Phase::Ssid, clock.now_micros() - t0);
+            info!("Began from static SSID={:02X}", crate::config::SSID);
+            server
+        };
+
+        #[cfg(not(feature = "static_ssid"))]
+        let server = {
+            let t0 = clock.now_micros();
+            let (server, message) = base_server.begin();
+            timings.add(Phase::Ssid, clock.now_micros() - t0);
+
+            let client_message: ClientMessage<K1> = recv!(receiver, sender, events, clock, s)?;
+            let t0 = clock.now_micros();
+            let server = if let ClientMessage::Nonce(client_nonce) = client_message {
+                server.agree_ssid(client_nonce)
+            } else {
+                fmt_log!(
+                    ERROR,
+                    s,
+                    "Received invalid client message {:?} - restarting negotiation",
+                    client_message
+                );
+                return Err(SessionError::UnexpectedMessage);
+            };
+            timings.add(Phase::Ssid, clock.now_micros() - t0);
+            info!("Received Client Nonce");
+
+            // now that we have received the client nonce, send our nonce back
+            bytes_sent += sender.send_msg(&message).await?;
+            info!("Sent Nonce");
+
+            server
+        };
+
+        // ===== Augmentation Layer =====
+        let mut client_message = recv!(receiver, sender, events, clock, s)?;
+        events.record(EventKind::PhaseReached(Phase::Augmentation));
+
+        // ===== Throttling =====
+        #[cfg(not(feature = "strong"))]
+        let username = match &client_message {
+            ClientMessage::Username(username) => Some(*username),
+            _ => None,
+        };
+        #[cfg(feature = "strong")]
+        let username = match &client_message {
+            ClientMessage::StrongUsername { username, .. } => Some(*username),
+            _ => None,
+        };
+
+        // a message without a username is rejected by the augmentation layer below
+        let user_key = username.map(UserKey::new);
+        if let Some(user_key) = user_key {
+            if let Some(wait) = throttle.check(user_key, clock.now_ms()) {
+                let millis = wait.as_millis().try_into().unwrap_or(u32::MAX);
+                let message = Admission::RetryAfter { millis };
+                let _ = sender.send_msg(&message).await;
+                events.record(EventKind::Throttled { millis });
+                warn!("Throttled authentication attempt for {}ms", millis);
+                return Err(SessionError::Throttled { millis });
+            }
+
+            let message = Admission::Proceed;
+            bytes_sent += sender.send_msg(&message).await?;
+        }
+
+        let t0 = clock.now_micros();
+        #[cfg(not(feature = "strong"))]
+        let (server, message) = if let ClientMessage::Username(username) = client_message {
+            #[cfg(not(feature = "partial"))]
+            let ret = server.generate_client_info(username, &*database, &mut session_rng);
+            #[cfg(feature = "partial")]
+            let ret =
+                server.generate_client_info_partial_aug(username, &*database, &mut session_rng);
+            ret
+        } else {
+            fmt_log!(
+                ERROR,
+                s,
+                "Received invalid client message {:?} - restarting negotiation",
+                client_message
+            );
+            return Err(SessionError::UnexpectedMessage);
+        };
+
+        #[cfg(feature = "strong")]
+        let (server, message) = if let ClientMessage::StrongUsername { username, blinded } =
+            client_message
+        {
+            #[cfg(not(feature = "partial"))]
+            let ret =
+                server.generate_client_info_strong(username, blinded, &*database, &mut session_rng);
+            #[cfg(feature = "partial")]
+            let ret = server.generate_client_info_partial_strong(
+                username,
+                blinded,
+                &*database,
+                &mut session_rng,
+            );
+            match ret {
+                Ok(inner) => inner,
+                Err(e) => {
+                    fmt_log!(
+                        ERROR,
+                        s,
+                        "Receiving client Public Key returned an error {:?}",
+                        e
+                    );
+                    return Err(SessionError::Protocol(e));
+                }
+            }
+        } else {
+            fmt_log!(
+                ERROR,
+                s,
+                "Received invalid client message {:?} - restarting negotiation",
+                client_message
+            );
+            return Err(SessionError::UnexpectedMessage);
+        };
+        timings.add(Phase::Augmentation, clock.now_micros() - t0);
+
+        bytes_sent += sender.send_msg(&message).await?;
+        #[cfg(not(feature = "strong"))]
+        {
+            info!("Received Client Username");
+            info!("Sent AugmentationInfo");
+        }
+        #[cfg(feature = "strong")]
+        {
+            info!("Received Client Username and Blinded Point");
+            info!("Sent Strong Augmentation Info");
+        }
+
+        // ===== CPace substep =====
+        events.record(EventKind::PhaseReached(Phase::CPace));
+        let t0 = clock.now_micros();
+        let (server, message) = server.generate_public_key(CHANNEL_ID);
+        timings.add(Phase::CPace, clock.now_micros() - t0);
+        bytes_sent += sender.send_msg(&message).await?;
+        info!("Sent PublicKey");
+
+        client_message = recv!(receiver, sender, events, clock, s)?;
+        let ClientMessage::PublicKey(client_pubkey) = client_message else {
+            fmt_log!(
+                ERROR,
+                s,
+                "Received invalid client message {:?}",
+                client_message
+            );
+            return Err(SessionError::UnexpectedMessage);
+        };
+
+        // only messages with a username reach this far
+        let user_key = unwrap!(user_key);
+
+        let mut key = if cfg!(feature = "implicit") {
+            let t0 = clock.now_micros();
+            let key = server.implicit_auth(client_pubkey);
+            timings.add(Phase::CPace, clock.now_micros() - t0);
+            match key {
+                Ok(s) => s,
+                Err(e) => {
+                    throttle.record_failure(user_key, clock.now_ms());
+                    persist_lockout(lockout, database.record_failure(user_key));
+                    events.record(EventKind::AuthFailed);
+                    fmt_log!(
+                        ERROR,
+                        s,
+                        "Receiving client Public Key returned an error {:?}",
+                        e
+                    );
+                    return Err(SessionError::Protocol(e));
+                }
+            }
+        } else {
+            let t0 = clock.now_micros();
+            let server = match server.receive_client_pubkey(client_pubkey) {
+                Ok(s) => s,
+                Err(e) => {
+                    fmt_log!(
+                        ERROR,
+                        s,
+                        "Receiving client Public Key returned an error {:?}",
+                        e
+                    );
+                    return Err(SessionError::Protocol(e));
+                }
+            };
+            timings.add(Phase::CPace, clock.now_micros() - t0);
+            info!("Received Client PublicKey");
+
+            // ===== Explicit Mutual Authentication =====
+            client_message = recv!(receiver, sender, events, clock, s)?;
+            events.record(EventKind::PhaseReached(Phase::Authenticator));
+            let t0 = clock.now_micros();
+            let (key, message) = if let ClientMessage::Authenticator(ca) = client_message {
+                match server.receive_client_authenticator(ca) {
+                    Ok(inner) => inner,
+                    Err(e) => {
+                        throttle.record_failure(user_key, clock.now_ms());
+                        persist_lockout(lockout, database.record_failure(user_key));
+                        events.record(EventKind::AuthFailed);
+                        fmt_log!(
+                            ERROR,
+                            s,
+                            "Client failed the Explicit Mutual Authentication check - {e:?}"
+                        );
+                        return Err(SessionError::AuthFailed);
+                    }
+                }
+            } else {
+                fmt_log!(
+                    ERROR,
+                    s,
+                    "Received invalid client message {:?}",
+                    client_message
+                );
+                return Err(SessionError::UnexpectedMessage);
+            };
+
+            timings.add(Phase::Authenticator, clock.now_micros() - t0);
+            bytes_sent += sender.send_msg(&message).await?;
+            throttle.record_success(user_key);
+            persist_lockout(lockout, database.record_success(user_key));
+            events.record(EventKind::AuthSucceeded);
+
+            info!("Sent Authenticator");
+
+            key
+        };
+
+        // the keys come out of the last phase of the exchange
+        let key_phase = if cfg!(feature = "implicit") {
+            Phase::CPace
+        } else {
+            Phase::Authenticator
+        };
+        let t0 = clock.now_micros();
+        let schedule = KeySchedule::new(key.as_slice(), receiver.transcript(), sender.transcript());
+        // only the subkeys are used from here on
+        key.as_mut_slice().zeroize();
+        timings.add(key_phase, clock.now_micros() - t0);
+
+        #[cfg(feature = "resumption")]
+        {
+            let t0 = clock.now_micros();
+            let issued = tickets.issue(&schedule.resumption(), clock.now_ms(), ticket_buf);
+            timings.add(Phase::Resumption, clock.now_micros() - t0);
+            if issued.is_some() {
+                let message = NewTicket {
+                    ticket: &ticket_buf[..],
+                    lifetime_secs: tickets.lifetime().as_secs() as u32,
+                };
+                bytes_sent += sender.send_msg(&message).await?;
+                info!("Sent resumption ticket");
+            }
+        }
+
+        // in implicit mode this is the first the server hears of whether the client authenticated,
+        // a client which goes quiet instead of confirming the keys may well have guessed wrong
+        if cfg!(feature = "implicit") {
+            let confirmed = match recv!(receiver, sender, events, clock, s, KeyConfirmation) {
+                Ok(confirmation) => confirmation.verify(&schedule.client_to_server()),
+                Err(_) => false,
+            };
+            if confirmed {
+                throttle.record_success(user_key);
+                persist_lockout(lockout, database.record_success(user_key));
+                events.record(EventKind::AuthSucceeded);
+                info!("Received KeyConfirmation");
+            } else {
+                throttle.record_failure(user_key, clock.now_ms());
+                persist_lockout(lockout, database.record_failure(user_key));
+                events.record(EventKind::AuthFailed);
+                warn!("Client failed to confirm the session's keys");
+            }
+        }
+
+        // reading the log back isn't part of the exchange so it doesn't count towards `bytes_sent`
+        #[cfg(feature = "event_log")]
+        let request = recv!(receiver, sender, events, clock, s, LogRequest)?;
+
+        #[cfg(feature = "event_log")]
+        serve_event_log(sender, events, &schedule, &request).await?;
+
+        #[cfg(feature = "timings")]
+        sender.send_msg(&timings).await?;
+
+        log_keys(&schedule, s);
+        info!("Total bytes sent: {}", bytes_sent);
+        log_stats(receiver.stats());
+        #[cfg(feature = "reliable")]
+        log_reliable_stats(sender.reliable_stats(), receiver.reliable_stats());
+        log_timings(&timings, s);
+
+        Ok(Session {
+            schedule,
+            resumed: false,
+            bytes_sent,
+            timings,
+        })
+    }
+}
+
+/// A generator seeded from `rng`, which can't be worked back to it
+fn fork(rng: &mut impl CryptoRngCore) -> ChaCha8Rng {
+    let mut seed = <ChaCha8Rng as SeedableRng>::Seed::default();
+    rng.fill_bytes(&mut seed);
+    let forked = ChaCha8Rng::from_seed(seed);
+    seed.zeroize();
+    forked
+}
+
+/// Persist a changed lockout record so it survives a reset
+fn persist_lockout<F: NorFlash>(store: &mut LockoutStore<F>, record: Option<LockoutRecord>) {
+    let Some(record) = record else {
+        return;
+    };
+
+    if store.save(&record).is_err() {
+        error!("Failed to persist the lockout record");
+    }
+    if record.failures == LOCKOUT_THRESHOLD {
+        warn!(
+            "Locked the account after {} consecutive failures",
+            record.failures
+        );
+    }
+}
+
+/// Log the subkeys the application would use, the raw AuCPace key is never used directly
+///
+/// The short authentication string is what an operator compares against the client's output.
+fn log_keys(schedule: &KeySchedule, s: &mut String<1024>) {
+    info!(
+        "Derived client_to_server key: {:02X}",
+        schedule.client_to_server().as_slice()
+    );
+    info!(
+        "Derived server_to_client key: {:02X}",
+        schedule.server_to_client().as_slice()
+    );
+    fmt_log!(
+        INFO,
+        s,
+        "Short authentication string: {}",
+        ShortAuthString::new(schedule)
+    );
+}
+
+/// Log the time spent computing in each phase, named the same as in the client's report
+fn log_timings(timings: &PhaseTimings, s: &mut String<1024>) {
+    for (phase, micros) in timings.iter().filter(|(_, micros)| *micros > 0) {
+        fmt_log!(INFO, s, "{} computation time: {}us", phase, micros);
+    }
+    let total = timings.total();
+    info!("Total computation time: {}ms - {}us", total / 1000, total);
+}
+
+/// Log the health of the link, anything but zero oversized and corrupt frames points at a bad connection
+fn log_stats(stats: FrameStats) {
+    info!(
+        "Frames received: {} - oversized: {} - corrupt: {} - bad checksum: {} - resend requests: {} - discarded bytes: {}",
+        stats.frames,
+        stats.oversized,
+        stats.corrupt,
+        stats.bad_checksum,
+        stats.resend_requests,
+        stats.discarded_bytes
+    );
+}
+
+/// Log how often the reliable delivery layer had to step in since boot
+#[cfg(feature = "reliable")]
+fn log_reliable_stats(sent: SendStats, received: RecvStats) {
+    info!(
+        "Frames sent again: {} - gave up: {} - duplicate messages: {} - out of order messages: {}",
+        sent.retransmits, sent.give_ups, received.duplicates, received.out_of_order
+    );
+}
+
+/// Wait for the client to acknowledge every frame sent to it, sending them again as needed
+///
+/// Messages from the client are acknowledged but thrown away, there shouldn't be any.
+/// A client which restarts before this finishes can have its preamble thrown away too.
+#[cfg(feature = "reliable")]
+async fn flush<T: Write, R: Read, C: Clock + Clone>(
+    sender: &mut MsgSender<T, C>,
+    receiver: &mut MsgReceiver<R, C>,
+) {
+    while !sender.is_flushed() {
+        let parsed = receiver
+            .recv_msg::<IgnoredAny>(sender.retransmit_deadline())
+            .await;
+        match parsed {
+            None => {
+                sender.retransmit().await;
+            }
+            Some(Ok(Received::Message { ack, .. } | Received::Duplicate { ack })) => {
+                sender.ack(ack).await
+            }
+            Some(Ok(Received::Gap { expected })) => sender.nak(expected).await,
+            Some(Ok(Received::Ack(seq))) => sender.acked(seq),
+            Some(Ok(Received::Nak(seq))) => sender.nakked(seq).await,
+            Some(Err(_)) => sender.nak(receiver.expected()).await,
+        }
+    }
+}
+
+/// Whether the client's last message of the session proves it holds the session's keys
+#[cfg(feature = "event_log")]
+fn holds_keys(request: &LogRequest, schedule: &KeySchedule) -> bool {
+    match request {
+        LogRequest::Download { mac } => events::verify_request(&schedule.client_to_server(), mac),
+        LogRequest::Skip => false,
+    }
+}
+
+/// Answer the client's last message of the session, only a client holding the session's keys gets
+/// the event log
+#[cfg(feature = "event_log")]
+async fn serve_event_log<T: Write, C: Clock + Clone>(
+    sender: &mut MsgSender<T, C>,
+    events: &EventLog<C, EVENT_LOG_LEN>,
+    schedule: &KeySchedule,
+    request: &LogRequest,
+) -> Result<(), FragmentError> {
+    if let LogRequest::Skip = request {
+        return Ok(());
+    }
+    if !holds_keys(request, schedule) {
+        warn!("Refused the event log to a client without the session's keys");
+        sender.send_msg(&LogResponse::Refused).await?;
+        return Ok(());
+    }
+
+    let mut buf = [0u8; EVENT_LOG_LEN * MAX_EVENT_LEN];
+    let encoded = unwrap!(events::encode(events.iter(), &mut buf));
+    let message = LogResponse::Events {
+        recorded: events.recorded(),
+        events: encoded,
+        mac: events::response_mac(&schedule.server_to_client(), events.recorded(), encoded),
+    };
+    sender.send_msg(&message).await?;
+    info!("Sent the event log");
+    Ok(())
+}
+
+/// Receive the client's preamble and tell it whether we speak the same protocol, returns `false`
+/// if we don't or the answer couldn't be sent
+async fn accept_preamble<T: Write, R: Read, C: Clock + Clone>(
+    sender: &mut MsgSender<T, C>,
+    receiver: &mut MsgReceiver<R, C>,
+    s: &mut String<1024>,
+) -> bool {
+    let ours = Preamble::new(FEATURES);
+    // anything which doesn't even parse as a preamble can't be speaking this protocol
+    let checked = match receiver.recv_plain::<Preamble>().await {
+        Ok(theirs) => ours.check(&theirs),
+        Err(_) => Err(Mismatch::Magic),
+    };
+
+    match checked {
+        Ok(()) => {
+            if let Err(e) = sender.send_plain(&PreambleResponse::Accepted).await {
+                fmt_log!(ERROR, s, "Failed to accept the client's preamble - {}", e);
+                return false;
+            }
+            // both ends number their frames from zero again
+            #[cfg(feature = "reliable")]
+            {
+                sender.reset_window();
+                receiver.reset_window();
+            }
+            true
+        }
+        Err(mismatch) => {
+            fmt_log!(ERROR, s, "Rejected the client's preamble - {}", mismatch);
+            if let Err(e) = sender.send_plain(&PreambleResponse::Mismatch(ours)).await {
+                fmt_log!(ERROR, s, "Failed to send our preamble back - {}", e);
+            }
+            false
+        }
+    }
+}
+
+/// Open the link again with `config`, throwing away anything received under the old settings
+#[cfg(feature = "baud_negotiation")]
+fn switch_link<L: Link, C: Clock + Clone>(
+    link: &mut L,
+    sender: &mut MsgSender<L::Tx, C>,
+    receiver: &mut MsgReceiver<L::Rx, C>,
+    config: &LinkConfig,
+) {
+    // Safety: the old halves are overwritten straight away so they can't be used again
+    let (tx, rx) = unsafe { link.reopen(config) };
+    sender.set_tx(tx);
+    receiver.set_rx(rx);
+}
+
+/// Echo the client's link check at the new rate, returns `false` if the link doesn't work
+#[cfg(feature = "baud_negotiation")]
+async fn confirm_link<T: Write, R: Read, C: Clock + Clone>(
+    clock: &C,
+    sender: &mut MsgSender<T, C>,
+    receiver: &mut MsgReceiver<R, C>,
+) -> bool {
+    let deadline = clock.now_ms() + CHECK_TIMEOUT_MS;
+    #[cfg(not(feature = "reliable"))]
+    let Some(Ok(check)) = with_deadline(clock, deadline, receiver.recv_msg::<LinkCheck>()).await
+    else {
+        return false;
+    };
+    // the check has to be the first message at the new rate, nothing is sent again while switching,
+    // but the client's ACK of our answer can still arrive ahead of it
+    #[cfg(feature = "reliable")]
+    let check = loop {
+        match receiver.recv_msg::<LinkCheck>(Some(deadline)).await {
+            Some(Ok(Received::Message {
+                msg: Some(msg),
+                ack,
+            })) => {
+                sender.ack(ack).await;
+                break msg;
+            }
+            Some(Ok(Received::Ack(seq))) => sender.acked(seq),
+            _ => return false,
+        }
+    };
+    if sender.send_msg(&check).await.is_err() {
+        return false;
+    }
+
+    // the client falls back if it misses the echo, so only stay if it carries on at this rate
+    let deadline = clock.now_ms() + CHECK_TIMEOUT_MS;
+    with_deadline(clock, deadline, receiver.wait_for_frame())
+        .await
+        .is_some()
+}
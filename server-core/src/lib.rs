@@ -0,0 +1,25 @@
+#![no_std]
+
+//! The parts of the server which don't depend on the board it runs on
+//!
+//! Everything here builds for the host as well as the firmware's target, so it can be tested
+//! with `cargo test` on a machine without a board attached. The board provides the link to the
+//! client and a clock through the traits in `transport`, and logs through defmt when the `defmt`
+//! feature is enabled.
+
+// this has to come first so the logging macros can be used by every other module
+mod fmt;
+
+pub mod config;
+pub mod database;
+pub mod device_secret;
+pub mod events;
+pub mod handshake;
+pub mod lockout;
+pub mod receiver;
+pub mod sender;
+pub mod stack;
+pub mod throttle;
+#[cfg(feature = "resumption")]
+pub mod tickets;
+pub mod transport;
@@ -0,0 +1,111 @@
+use crate::throttle::UserKey;
+use embedded_storage::nor_flash::NorFlash;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"LOCK");
+const ERASED: u8 = 0xFF;
+
+/// Records are padded to a size any flash write granularity we care about divides
+const SLOT_LEN: usize = 32;
+
+/// Consecutive authentication failures for a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutRecord {
+    pub user: UserKey,
+    pub failures: u32,
+}
+
+impl LockoutRecord {
+    fn to_bytes(self) -> [u8; SLOT_LEN] {
+        let mut slot = [0u8; SLOT_LEN];
+        slot[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        slot[4..20].copy_from_slice(self.user.as_bytes());
+        slot[20..24].copy_from_slice(&self.failures.to_le_bytes());
+        // lets a torn write be told apart from a real record
+        slot[24..28].copy_from_slice(&(!self.failures).to_le_bytes());
+        slot
+    }
+
+    fn from_bytes(slot: &[u8; SLOT_LEN]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes(slot[i..i + 4].try_into().unwrap());
+        if word(0) != MAGIC || word(20) != !word(24) {
+            return None;
+        }
+
+        Some(Self {
+            user: UserKey::from_bytes(slot[4..20].try_into().unwrap()),
+            failures: word(20),
+        })
+    }
+}
+
+/// Persists the latest `LockoutRecord` to a region of flash so it survives a reset
+///
+/// Records are appended to the region one slot at a time and the region is only erased once
+/// every slot has been used, which keeps the number of erase cycles down.
+pub struct LockoutStore<F> {
+    flash: F,
+    offset: u32,
+    size: u32,
+    next_slot: u32,
+}
+
+impl<F: NorFlash> LockoutStore<F> {
+    /// Use the `size` bytes of `flash` starting at `offset`, which must be whole erase sectors
+    pub fn new(flash: F, offset: u32, size: u32) -> Self {
+        Self {
+            flash,
+            offset,
+            size,
+            next_slot: 0,
+        }
+    }
+
+    fn slots(&self) -> u32 {
+        self.size / SLOT_LEN as u32
+    }
+
+    /// Find the most recently saved record
+    pub fn load(&mut self) -> Result<Option<LockoutRecord>, F::Error> {
+        let mut latest = None;
+        let mut slot = [0u8; SLOT_LEN];
+        self.next_slot = self.slots();
+
+        for i in 0..self.slots() {
+            self.flash
+                .read(self.offset + i * SLOT_LEN as u32, &mut slot)?;
+            if slot.iter().all(|b| *b == ERASED) {
+                self.next_slot = i;
+                break;
+            }
+
+            // skip over any slot which was only partially written
+            if let Some(record) = LockoutRecord::from_bytes(&slot) {
+                latest = Some(record);
+            }
+        }
+
+        Ok(latest)
+    }
+
+    /// Save a record, `load` must have been called first to find the next free slot
+    pub fn save(&mut self, record: &LockoutRecord) -> Result<(), F::Error> {
+        if self.next_slot >= self.slots() {
+            self.erase()?;
+        }
+
+        self.flash.write(
+            self.offset + self.next_slot * SLOT_LEN as u32,
+            &record.to_bytes(),
+        )?;
+        self.next_slot += 1;
+
+        Ok(())
+    }
+
+    /// Erase every saved record
+    pub fn erase(&mut self) -> Result<(), F::Error> {
+        self.flash.erase(self.offset, self.offset + self.size)?;
+        self.next_slot = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+//! Receiving messages from the client, put back together from the frames and fragments they
+//! arrive in
+
+use crate::config::{RECV_BUF_LEN, RECV_MSG_LEN};
+use crate::transport::Clock;
+use embedded_io_async::{Error as _, Read};
+use protocol::fragment::{Fragment, Reassembler};
+use protocol::framing::{self, Checksum, FrameError, FrameReceiver, FrameStats};
+use protocol::key_schedule::Transcript;
+use serde::Deserialize;
+
+#[cfg(not(feature = "reliable"))]
+use crate::config::CHECKSUM;
+
+#[cfg(feature = "reliable")]
+use {
+    crate::config::RELIABLE_CHECKSUM,
+    crate::transport::with_deadline,
+    protocol::reliable::{Received, RecvStats, RecvWindow},
+};
+
+pub struct MsgReceiver<R, C> {
+    frames: FrameReceiver<RECV_BUF_LEN>,
+    reassembler: Reassembler<RECV_MSG_LEN>,
+    rx: R,
+    #[cfg_attr(not(feature = "reliable"), allow(dead_code))]
+    clock: C,
+    transcript: Transcript,
+    #[cfg(feature = "reliable")]
+    window: RecvWindow,
+}
+
+impl<R: Read, C: Clock + Clone> MsgReceiver<R, C> {
+    pub fn new(rx: R, clock: C) -> Self {
+        Self {
+            frames: FrameReceiver::new(),
+            reassembler: Reassembler::new(),
+            rx,
+            clock,
+            transcript: Transcript::default(),
+            #[cfg(feature = "reliable")]
+            window: RecvWindow::new(),
+        }
+    }
+
+    /// Carry on over a link which has been opened again, throwing away everything received but
+    /// not yet handed out as a message
+    pub fn set_rx(&mut self, rx: R) {
+        self.rx = rx;
+        self.frames.clear();
+    }
+
+    /// The transcript of every message received since the last reset
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    pub fn reset_transcript(&mut self) {
+        self.transcript = Transcript::default();
+    }
+
+    /// Counters for every frame received since boot
+    pub fn stats(&self) -> FrameStats {
+        self.frames.stats()
+    }
+
+    /// Counters for every message received since boot
+    #[cfg(feature = "reliable")]
+    pub fn reliable_stats(&self) -> RecvStats {
+        self.window.stats()
+    }
+
+    /// The sequence number of the next message from the client
+    #[cfg(feature = "reliable")]
+    pub fn expected(&self) -> u8 {
+        self.window.expected()
+    }
+
+    /// Expect the client to number its frames from zero again
+    #[cfg(feature = "reliable")]
+    pub fn reset_window(&mut self) {
+        self.window.reset();
+    }
+
+    /// Read off the link until there is a whole frame buffered
+    pub async fn wait_for_frame(&mut self) {
+        // more than one frame can be read at once so there may already be one buffered
+        while self.frames.next_frame_len().is_none() {
+            // read as much as we can off the wire
+            let spare = self.frames.spare();
+            let count = match self.rx.read(spare).await {
+                Ok(count) => count,
+                // a bad byte is just noise on the line, the framing picks up again after it
+                Err(e) => {
+                    warn!("Failed to read from the link - {:?}", e.kind());
+                    continue;
+                }
+            };
+            if count == 0 {
+                continue;
+            }
+
+            // log that we managed to read some data
+            trace!("Read {} bytes - {:02X}", count, spare[..count]);
+
+            if !self.frames.fill(count) {
+                warn!("Receive buffer filled without a frame ending - discarding up to the next frame");
+            }
+        }
+    }
+
+    /// Receive a message in as many fragments as it takes, checking their checksums if there are any
+    #[cfg(not(feature = "reliable"))]
+    pub async fn recv_msg<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T, FrameError> {
+        loop {
+            self.wait_for_frame().await;
+            let fragment: Fragment =
+                Self::decode(&mut self.frames, &mut self.transcript, CHECKSUM)?;
+            match self.reassembler.push(fragment) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => return Err(FrameError::Fragment(e)),
+            }
+        }
+        self.reassembler.decode().map_err(FrameError::Message)
+    }
+
+    /// Receive the next frame of the reliable delivery layer, `None` if `deadline_ms` passes first
+    ///
+    /// `Received::Message` only holds the message once its last fragment has arrived.
+    #[cfg(feature = "reliable")]
+    pub async fn recv_msg<'a, T: Deserialize<'a>>(
+        &'a mut self,
+        deadline_ms: Option<u64>,
+    ) -> Option<Result<Received<Option<T>>, FrameError>> {
+        match deadline_ms {
+            Some(deadline_ms) => {
+                let clock = self.clock.clone();
+                with_deadline(&clock, deadline_ms, self.wait_for_frame()).await?;
+            }
+            None => self.wait_for_frame().await,
+        }
+
+        trace!("Buffered {:02X}", self.frames.buffered());
+        let transcript = &mut self.transcript;
+        let window = &mut self.window;
+        let parsed = self.frames.decode_frame(|frame| {
+            trace!("Found frame of {} bytes", frame.len());
+
+            // duplicates and control frames stay out of the transcript, the client has those already
+            let mut updated = transcript.clone();
+            updated.update(frame);
+
+            let parsed = window.decode::<Fragment>(frame, RELIABLE_CHECKSUM);
+            if let Ok(Received::Message { .. }) = parsed {
+                *transcript = updated;
+            }
+
+            parsed
+        });
+
+        Some(match unwrap!(parsed) {
+            Ok(Received::Message { msg, ack }) => match self.reassembler.push(msg) {
+                Ok(true) => self
+                    .reassembler
+                    .decode()
+                    .map(|msg| Received::Message {
+                        msg: Some(msg),
+                        ack,
+                    })
+                    .map_err(FrameError::Message),
+                Ok(false) => Ok(Received::Message { msg: None, ack }),
+                Err(e) => Err(FrameError::Fragment(e)),
+            },
+            Ok(Received::Duplicate { ack }) => Ok(Received::Duplicate { ack }),
+            Ok(Received::Gap { expected }) => Ok(Received::Gap { expected }),
+            Ok(Received::Ack(seq)) => Ok(Received::Ack(seq)),
+            Ok(Received::Nak(seq)) => Ok(Received::Nak(seq)),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Receive a message without a checksum, for the preamble whose framing never changes
+    pub async fn recv_plain<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T, FrameError> {
+        self.recv(None).await
+    }
+
+    async fn recv<'a, T: Deserialize<'a>>(
+        &'a mut self,
+        checksum: Option<Checksum>,
+    ) -> Result<T, FrameError> {
+        self.wait_for_frame().await;
+        Self::decode(&mut self.frames, &mut self.transcript, checksum)
+    }
+
+    /// Decode the buffered frame as a `T`, taking the fields so the reassembler can still be used
+    fn decode<'a, T: Deserialize<'a>>(
+        frames: &'a mut FrameReceiver<RECV_BUF_LEN>,
+        transcript: &mut Transcript,
+        checksum: Option<Checksum>,
+    ) -> Result<T, FrameError> {
+        trace!("Buffered {:02X}", frames.buffered());
+        let parsed = frames.decode_frame(|frame| {
+            trace!("Found frame of {} bytes", frame.len());
+
+            // only frames which parse go in the transcript, the sender can't know about the others
+            let mut updated = transcript.clone();
+            updated.update(frame);
+
+            // parse the result
+            let parsed = framing::decode::<T>(frame, checksum);
+            if parsed.is_ok() {
+                *transcript = updated;
+            }
+
+            parsed
+        });
+
+        unwrap!(parsed)
+    }
+}
@@ -0,0 +1,249 @@
+//! Sending messages to the client, in fragments and frames which match the client's receiver
+
+use crate::config::{SEND_BUF_LEN, SEND_MSG_LEN};
+use crate::transport::Clock;
+use embedded_io_async::{Error as _, Write};
+use protocol::fragment::{FragmentError, Fragmenter};
+use protocol::framing::{self, Checksum};
+use protocol::key_schedule::Transcript;
+use serde::Serialize;
+use zeroize::Zeroize;
+
+#[cfg(not(feature = "reliable"))]
+use crate::config::CHECKSUM;
+
+#[cfg(feature = "reliable")]
+use {
+    crate::config::{RELIABLE_CHECKSUM, WINDOW},
+    protocol::reliable::{self, GaveUp, SendStats, SendWindow},
+};
+
+pub struct MsgSender<T, C> {
+    buf: [u8; SEND_BUF_LEN],
+    scratch: [u8; SEND_BUF_LEN],
+    fragmenter: Fragmenter<SEND_MSG_LEN>,
+    // length of the frame at the start of `buf` kept in case the client asks for it again
+    last_len: usize,
+    tx: T,
+    #[cfg_attr(not(feature = "reliable"), allow(dead_code))]
+    clock: C,
+    transcript: Transcript,
+    #[cfg(feature = "reliable")]
+    window: SendWindow<WINDOW, SEND_BUF_LEN>,
+}
+
+impl<T: Write, C: Clock> MsgSender<T, C> {
+    pub fn new(tx: T, clock: C) -> Self {
+        Self {
+            buf: [0u8; SEND_BUF_LEN],
+            scratch: [0u8; SEND_BUF_LEN],
+            fragmenter: Fragmenter::new(),
+            last_len: 0,
+            tx,
+            clock,
+            transcript: Transcript::default(),
+            #[cfg(feature = "reliable")]
+            window: SendWindow::new(reliable::Config::DEFAULT),
+        }
+    }
+
+    /// Carry on over a link which has been opened again
+    pub fn set_tx(&mut self, tx: T) {
+        self.tx = tx;
+    }
+
+    /// The transcript of every frame sent since the last reset
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    pub fn reset_transcript(&mut self) {
+        self.transcript = Transcript::default();
+    }
+
+    /// Send a message in as many fragments as it takes, returns the bytes sent
+    #[cfg(not(feature = "reliable"))]
+    pub async fn send_msg<M: Serialize>(&mut self, msg: &M) -> Result<usize, FragmentError> {
+        self.fragmenter.start(msg)?;
+        let mut sent = 0;
+        while let Some(fragment) = self.fragmenter.next_fragment() {
+            self.buf[..self.last_len].zeroize();
+            self.last_len = 0;
+            let len = framing::encode(&fragment, CHECKSUM, &mut self.scratch, &mut self.buf)
+                .map_err(FragmentError::Encode)?
+                .len();
+            sent += self.send_frame(len, CHECKSUM).await;
+        }
+        Ok(sent)
+    }
+
+    /// Send a message in as many fragments as it takes and hold on to each until the client
+    /// acknowledges it, returns the bytes sent
+    #[cfg(feature = "reliable")]
+    pub async fn send_msg<M: Serialize>(&mut self, msg: &M) -> Result<usize, FragmentError> {
+        self.fragmenter.start(msg)?;
+        let mut sent = 0;
+        while let Some(fragment) = self.fragmenter.next_fragment() {
+            let seq = unwrap!(self.window.next_seq(), "too many frames waiting for an ACK");
+            let serialised = reliable::encode_data(
+                seq,
+                &fragment,
+                RELIABLE_CHECKSUM,
+                &mut self.scratch,
+                &mut self.buf,
+            )
+            .map_err(FragmentError::Encode)?;
+            let len = serialised.len();
+            // `next_seq` made sure there is room, so the frame can only be too long to hold on to
+            let pushed = self.window.push(serialised, self.clock.now_ms());
+            if pushed.is_err() {
+                self.buf[..len].zeroize();
+                return Err(FragmentError::TooLong {
+                    limit: SEND_BUF_LEN,
+                });
+            }
+            self.transcript.update(&self.buf[..len]);
+            write(&mut self.tx, &self.buf[..len]).await;
+            self.buf[..len].zeroize();
+            sent += len;
+        }
+        Ok(sent)
+    }
+
+    /// Send a message without a checksum, for the preamble whose framing never changes
+    pub async fn send_plain<M: Serialize>(&mut self, msg: &M) -> Result<usize, FragmentError> {
+        self.send(msg, None).await
+    }
+
+    async fn send<M: Serialize>(
+        &mut self,
+        msg: &M,
+        checksum: Option<Checksum>,
+    ) -> Result<usize, FragmentError> {
+        self.buf[..self.last_len].zeroize();
+        self.last_len = 0;
+        let len = framing::encode(msg, checksum, &mut self.scratch, &mut self.buf)
+            .map_err(FragmentError::Encode)?
+            .len();
+        Ok(self.send_frame(len, checksum).await)
+    }
+
+    /// Send the frame of `len` bytes at the start of `buf`
+    async fn send_frame(&mut self, len: usize, checksum: Option<Checksum>) -> usize {
+        let serialised = &self.buf[..len];
+        self.transcript.update(serialised);
+        write(&mut self.tx, serialised).await;
+
+        // only frames with a checksum can be asked for again
+        if checksum.is_some() {
+            self.last_len = len;
+        } else {
+            self.buf[..len].zeroize();
+            self.last_len = 0;
+        }
+        len
+    }
+
+    /// Send the last frame again, it is already in the transcript
+    #[cfg(not(feature = "reliable"))]
+    pub async fn resend_last(&mut self) {
+        if self.last_len == 0 {
+            warn!("There is no frame to send again");
+            return;
+        }
+        write(&mut self.tx, &self.buf[..self.last_len]).await;
+    }
+
+    /// Ask the client to send its last frame again
+    #[cfg(not(feature = "reliable"))]
+    pub async fn request_resend(&mut self) {
+        let Some(checksum) = CHECKSUM else {
+            return;
+        };
+        let mut buf = [0u8; framing::RESEND_REQUEST_MAX_LEN];
+        let request = framing::resend_request(checksum, &mut buf);
+        write(&mut self.tx, request).await;
+    }
+
+    /// Acknowledge every message from the client up to and including `seq`
+    #[cfg(feature = "reliable")]
+    pub async fn ack(&mut self, seq: u8) {
+        let mut buf = [0u8; reliable::CONTROL_MAX_LEN];
+        let ack = reliable::encode_ack(seq, RELIABLE_CHECKSUM, &mut buf);
+        write(&mut self.tx, ack).await;
+    }
+
+    /// Ask the client for every message from `seq` onwards again
+    #[cfg(feature = "reliable")]
+    pub async fn nak(&mut self, seq: u8) {
+        let mut buf = [0u8; reliable::CONTROL_MAX_LEN];
+        let nak = reliable::encode_nak(seq, RELIABLE_CHECKSUM, &mut buf);
+        write(&mut self.tx, nak).await;
+    }
+
+    /// The client received every frame up to and including `seq`
+    #[cfg(feature = "reliable")]
+    pub fn acked(&mut self, seq: u8) {
+        self.window.ack(seq);
+    }
+
+    /// The client wants every frame from `seq` onwards again
+    #[cfg(feature = "reliable")]
+    pub async fn nakked(&mut self, seq: u8) {
+        self.window.nak(seq);
+        self.retransmit().await;
+    }
+
+    /// Send every frame whose retransmit timer has run out, returns `false` if it gave up on the
+    /// client instead
+    #[cfg(feature = "reliable")]
+    pub async fn retransmit(&mut self) -> bool {
+        loop {
+            match self.window.due(self.clock.now_ms()) {
+                Ok(Some(frame)) => write(&mut self.tx, frame).await,
+                Ok(None) => return true,
+                Err(GaveUp { seq }) => {
+                    error!("Gave up on frame {} - the client has gone", seq);
+                    self.window.reset();
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// When the next frame is due to be sent again in milliseconds since boot, `None` if nothing
+    /// is waiting for an ACK
+    #[cfg(feature = "reliable")]
+    pub fn retransmit_deadline(&self) -> Option<u64> {
+        self.window.deadline()
+    }
+
+    /// Whether the client has acknowledged every frame sent to it
+    #[cfg(feature = "reliable")]
+    pub fn is_flushed(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    /// Forget every frame waiting for an ACK and number frames from zero again
+    #[cfg(feature = "reliable")]
+    pub fn reset_window(&mut self) {
+        self.window.reset();
+    }
+
+    /// Counters for every frame sent since boot
+    #[cfg(feature = "reliable")]
+    pub fn reliable_stats(&self) -> SendStats {
+        self.window.stats()
+    }
+}
+
+/// Write the whole of `frame`, a frame which fails to go out is as good as lost on the wire
+async fn write<T: Write>(tx: &mut T, frame: &[u8]) {
+    let result = match tx.write_all(frame).await {
+        Ok(()) => tx.flush().await,
+        err => err,
+    };
+    if let Err(e) = result {
+        error!("Failed to write to the link - {:?}", e.kind());
+    }
+}
@@ -0,0 +1,38 @@
+//! Measuring the deepest the stack has been by painting it
+//!
+//! Everything the stack hasn't reached yet is painted with a known word, the peak use is then
+//! however much of it has been overwritten. The board finds its stack and reads the stack pointer,
+//! this only walks the words in between, so it works on any memory handed to it.
+
+use core::ptr;
+
+/// The word the unused stack is painted with
+pub const PAINT: u32 = 0xCCCC_CCCC;
+
+/// Paint the words from `limit` up to but not including `end`
+///
+/// # Safety
+///
+/// The words must be valid for writes and not in use, such as the stack below the stack pointer.
+pub unsafe fn paint(limit: *mut u32, end: *mut u32) {
+    let mut word = limit;
+    while word < end {
+        ptr::write_volatile(word, PAINT);
+        word = word.add(1);
+    }
+}
+
+/// Bytes from the lowest word above `limit` which no longer holds the paint up to `top`
+///
+/// The stack grows down from `top`, so that's the deepest it has been since it was painted.
+///
+/// # Safety
+///
+/// The words from `limit` up to but not including `top` must be valid for reads.
+pub unsafe fn peak(limit: *const u32, top: *const u32) -> usize {
+    let mut word = limit;
+    while word < top && ptr::read_volatile(word) == PAINT {
+        word = word.add(1);
+    }
+    top as usize - word as usize
+}
@@ -0,0 +1,169 @@
+use core::time::Duration;
+use sha2::{Digest, Sha256};
+
+/// Exponential backoff applied to a run of failed authentication attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Number of failures allowed before any delay is imposed
+    pub free_attempts: u32,
+    /// Delay imposed by the first failure past `free_attempts`, doubling with each further failure
+    pub base_delay: Duration,
+    /// Upper bound on the delay
+    pub max_delay: Duration,
+    /// Failures are forgotten once this long has passed without another one
+    pub reset_after: Duration,
+}
+
+impl Backoff {
+    /// How long to wait after the last of `failures` consecutive failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        let Some(excess) = failures.checked_sub(self.free_attempts) else {
+            return Duration::ZERO;
+        };
+        if excess == 0 {
+            return Duration::ZERO;
+        }
+
+        let multiplier = 1u32.checked_shl(excess - 1).unwrap_or(u32::MAX);
+        let delay = self.base_delay.saturating_mul(multiplier);
+        delay.min(self.max_delay)
+    }
+}
+
+/// Backoff applied to each username and to all attempts regardless of username
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThrottlePolicy {
+    pub per_user: Backoff,
+    pub global: Backoff,
+}
+
+/// Identifies a username without storing it, usernames of unbounded length can be tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserKey([u8; 16]);
+
+impl UserKey {
+    pub fn new(username: &[u8]) -> Self {
+        let digest = Sha256::digest(username);
+        let mut key = [0u8; 16];
+        key.copy_from_slice(&digest[..16]);
+        Self(key)
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    failures: u32,
+    // milliseconds since boot
+    last_failure: u64,
+}
+
+impl Record {
+    /// The number of failures still counted against this record at `now_ms`
+    fn failures(&self, backoff: &Backoff, now_ms: u64) -> u32 {
+        if since(self.last_failure, now_ms) > backoff.reset_after {
+            0
+        } else {
+            self.failures
+        }
+    }
+
+    fn retry_after(&self, backoff: &Backoff, now_ms: u64) -> Option<Duration> {
+        let delay = backoff.delay(self.failures(backoff, now_ms));
+        let waited = since(self.last_failure, now_ms);
+        (delay > waited).then(|| delay - waited)
+    }
+
+    fn fail(&mut self, backoff: &Backoff, now_ms: u64) {
+        self.failures = self.failures(backoff, now_ms).saturating_add(1);
+        self.last_failure = now_ms;
+    }
+}
+
+/// How long has passed between `then_ms` and `now_ms`, zero if the clock went backwards
+fn since(then_ms: u64, now_ms: u64) -> Duration {
+    Duration::from_millis(now_ms.saturating_sub(then_ms))
+}
+
+/// Tracks failed authentication attempts to slow down online password guessing
+///
+/// Up to `N` usernames are tracked individually, when the table is full the username which
+/// failed least recently is forgotten. A global record catches attackers cycling usernames.
+pub struct Throttle<const N: usize> {
+    policy: ThrottlePolicy,
+    global: Option<Record>,
+    users: heapless::Vec<(UserKey, Record), N>,
+}
+
+impl<const N: usize> Throttle<N> {
+    pub fn new(policy: ThrottlePolicy) -> Self {
+        Self {
+            policy,
+            global: None,
+            users: heapless::Vec::new(),
+        }
+    }
+
+    /// How long `user` has to wait before it may attempt to authenticate, `None` if it may now
+    ///
+    /// Times are in milliseconds since boot.
+    pub fn check(&self, user: UserKey, now_ms: u64) -> Option<Duration> {
+        let global = self
+            .global
+            .and_then(|record| record.retry_after(&self.policy.global, now_ms));
+        let per_user = self
+            .users
+            .iter()
+            .find(|(key, _)| *key == user)
+            .and_then(|(_, record)| record.retry_after(&self.policy.per_user, now_ms));
+
+        global.max(per_user)
+    }
+
+    /// Record a failed attempt to authenticate as `user`
+    pub fn record_failure(&mut self, user: UserKey, now_ms: u64) {
+        self.global
+            .get_or_insert(Record {
+                failures: 0,
+                last_failure: now_ms,
+            })
+            .fail(&self.policy.global, now_ms);
+
+        let record = if let Some(idx) = self.users.iter().position(|(key, _)| *key == user) {
+            &mut self.users[idx].1
+        } else {
+            let new = (
+                user,
+                Record {
+                    failures: 0,
+                    last_failure: now_ms,
+                },
+            );
+            if let Err(new) = self.users.push(new) {
+                // evict the entry which failed least recently
+                let Some(oldest) = self.users.iter_mut().min_by_key(|(_, r)| r.last_failure) else {
+                    return;
+                };
+                *oldest = new;
+            }
+            self.users
+                .iter_mut()
+                .find(|(key, _)| *key == user)
+                .map(|(_, record)| record)
+                .expect("record was just inserted")
+        };
+        record.fail(&self.policy.per_user, now_ms);
+    }
+
+    /// Record a successful authentication, clearing the failures counted against `user`
+    pub fn record_success(&mut self, user: UserKey) {
+        self.users.retain(|(key, _)| *key != user);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::device_secret;
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use core::time::Duration;
+use hkdf::Hkdf;
+use protocol::resumption::SECRET_LEN;
+use rand_core::CryptoRngCore;
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+
+const SERIAL_LEN: usize = 8;
+const TIMESTAMP_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+const PLAINTEXT_LEN: usize = TIMESTAMP_LEN + SECRET_LEN;
+
+/// Length of the random salt the ticket key is derived with at every boot
+const SALT_LEN: usize = 32;
+const KEY_INFO: &[u8] = b"AuCPace resumption ticket key";
+
+/// Length of a sealed ticket: the serial number, the encrypted contents and the tag
+pub const TICKET_LEN: usize = SERIAL_LEN + PLAINTEXT_LEN + TAG_LEN;
+
+/// Number of serial numbers the replay window covers, tickets older than this are rejected
+const REPLAY_WINDOW: u64 = u64::BITS as u64;
+
+/// Reasons a ticket can be rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TicketError {
+    /// The ticket was the wrong length
+    Malformed,
+    /// The ticket failed to decrypt, it was not issued by us or has been tampered with
+    Forged,
+    /// The ticket has already been redeemed
+    Replayed,
+    /// The ticket's lifetime has elapsed or it fell out of the replay window
+    Expired,
+}
+
+/// Issues and redeems single-use, encrypted session resumption tickets
+///
+/// The ticket key is derived from the device secret and a salt drawn at every boot, and only lives
+/// in RAM, so nobody without the device secret can seal a ticket and every ticket is invalidated
+/// when the device resets.
+pub struct TicketIssuer {
+    cipher: ChaCha20Poly1305,
+    lifetime: Duration,
+    next_serial: u64,
+    // bit `serial % REPLAY_WINDOW` is set once the ticket with that serial has been redeemed
+    redeemed: u64,
+}
+
+impl TicketIssuer {
+    pub fn new(
+        device_secret: &[u8; device_secret::SECRET_LEN],
+        rng: &mut impl CryptoRngCore,
+        lifetime: Duration,
+    ) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        rng.fill_bytes(&mut salt);
+        let mut key = Key::default();
+        Hkdf::<Sha256>::new(Some(&salt), device_secret)
+            .expand(KEY_INFO, &mut key)
+            .expect("the key is a valid HKDF-SHA256 output length");
+        let cipher = ChaCha20Poly1305::new(&key);
+        key.zeroize();
+
+        Self {
+            cipher,
+            lifetime,
+            next_serial: 0,
+            redeemed: 0,
+        }
+    }
+
+    /// The lifetime of tickets issued by this issuer
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Seal `secret` into a new ticket, returns `None` once the serial numbers run out
+    ///
+    /// `now_ms` is the time since boot, `redeem` measures the ticket's lifetime from it.
+    pub fn issue(
+        &mut self,
+        secret: &[u8; SECRET_LEN],
+        now_ms: u64,
+        out: &mut [u8; TICKET_LEN],
+    ) -> Option<()> {
+        let serial = self.next_serial;
+        self.next_serial = serial.checked_add(1)?;
+
+        // this slot previously tracked the ticket one window ago, which is now always rejected
+        self.redeemed &= !(1 << (serial % REPLAY_WINDOW));
+
+        let (serial_bytes, rest) = out.split_at_mut(SERIAL_LEN);
+        let (plaintext, tag_bytes) = rest.split_at_mut(PLAINTEXT_LEN);
+        serial_bytes.copy_from_slice(&serial.to_le_bytes());
+        plaintext[..TIMESTAMP_LEN].copy_from_slice(&now_ms.to_le_bytes());
+        plaintext[TIMESTAMP_LEN..].copy_from_slice(secret);
+
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(serial), serial_bytes, plaintext);
+        let Ok(tag) = tag else {
+            // don't leave the secret sitting in the caller's buffer in the clear
+            plaintext.zeroize();
+            return None;
+        };
+        tag_bytes.copy_from_slice(&tag);
+
+        Some(())
+    }
+
+    /// Check a ticket and mark it as used, returning the resumption secret it holds
+    ///
+    /// The ticket is consumed even if the rest of the resumption handshake fails,
+    /// so a captured ticket can't be used to probe the server repeatedly.
+    pub fn redeem(
+        &mut self,
+        ticket: &[u8],
+        now_ms: u64,
+    ) -> Result<Zeroizing<[u8; SECRET_LEN]>, TicketError> {
+        if ticket.len() != TICKET_LEN {
+            return Err(TicketError::Malformed);
+        }
+
+        let (serial_bytes, rest) = ticket.split_at(SERIAL_LEN);
+        let (ciphertext, tag) = rest.split_at(PLAINTEXT_LEN);
+        let mut plaintext = Zeroizing::new([0u8; PLAINTEXT_LEN]);
+        plaintext.copy_from_slice(ciphertext);
+
+        let serial = u64::from_le_bytes(serial_bytes.try_into().unwrap());
+        self.cipher
+            .decrypt_in_place_detached(
+                &nonce(serial),
+                serial_bytes,
+                plaintext.as_mut(),
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| TicketError::Forged)?;
+
+        // only tickets within the replay window can be checked against the bitmap
+        if serial >= self.next_serial || self.next_serial - serial > REPLAY_WINDOW {
+            return Err(TicketError::Expired);
+        }
+
+        let bit = 1 << (serial % REPLAY_WINDOW);
+        if self.redeemed & bit != 0 {
+            return Err(TicketError::Replayed);
+        }
+        self.redeemed |= bit;
+
+        let issued_at = u64::from_le_bytes(plaintext[..TIMESTAMP_LEN].try_into().unwrap());
+        if Duration::from_millis(now_ms.saturating_sub(issued_at)) > self.lifetime {
+            return Err(TicketError::Expired);
+        }
+
+        let mut secret = Zeroizing::new([0u8; SECRET_LEN]);
+        secret.copy_from_slice(&plaintext[TIMESTAMP_LEN..]);
+        Ok(secret)
+    }
+}
+
+/// Every ticket has a unique serial number so it can be used directly as the nonce
+fn nonce(serial: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[..SERIAL_LEN].copy_from_slice(&serial.to_le_bytes());
+    nonce
+}
@@ -0,0 +1,67 @@
+//! What the server needs from the board: the link to the client and a clock
+//!
+//! The link is anything with `embedded-io-async` `Read` and `Write` halves, so the same code runs
+//! over a UART, USB CDC or an in-memory pipe. The firmware implements the clock over embassy's
+//! timer, tests implement it with a clock which only moves when it is told to.
+
+use core::future::Future;
+use embassy_futures::select::{select, Either};
+use embedded_io_async::{Read, Write};
+
+#[cfg(feature = "baud_negotiation")]
+use protocol::link::LinkConfig;
+
+/// The link to the client, used as a separate `Tx` and `Rx`
+///
+/// Reads should return as soon as anything has arrived rather than waiting to fill the buffer.
+pub trait Link {
+    type Tx: Write;
+    type Rx: Read;
+
+    /// Open the link again with `config`, anything in flight under the old settings is lost
+    ///
+    /// # Safety
+    ///
+    /// The halves from the last time the link was opened must not be used again.
+    #[cfg(feature = "baud_negotiation")]
+    unsafe fn reopen(&mut self, config: &LinkConfig) -> (Self::Tx, Self::Rx);
+}
+
+/// A monotonic clock which starts at boot
+#[allow(async_fn_in_trait)]
+pub trait Clock {
+    fn now_micros(&self) -> u64;
+
+    /// Wait until `now_ms` reaches `deadline_ms`
+    async fn wait_until_ms(&self, deadline_ms: u64);
+
+    fn now_ms(&self) -> u64 {
+        self.now_micros() / 1000
+    }
+
+    async fn delay_ms(&self, ms: u64) {
+        self.wait_until_ms(self.now_ms().saturating_add(ms)).await
+    }
+}
+
+impl<C: Clock> Clock for &C {
+    fn now_micros(&self) -> u64 {
+        (*self).now_micros()
+    }
+
+    async fn wait_until_ms(&self, deadline_ms: u64) {
+        (*self).wait_until_ms(deadline_ms).await
+    }
+}
+
+/// Run `fut` until it finishes, or give up with `None` once `deadline_ms` passes
+pub async fn with_deadline<F: Future>(
+    clock: &impl Clock,
+    deadline_ms: u64,
+    fut: F,
+) -> Option<F::Output> {
+    match select(fut, clock.wait_until_ms(deadline_ms)).await {
+        Either::First(output) => Some(output),
+        Either::Second(()) => None,
+    }
+}
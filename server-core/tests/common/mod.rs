@@ -0,0 +1,209 @@
+//! Stand-ins for the board, shared by the integration tests
+#![allow(dead_code)]
+
+use core::convert::Infallible;
+use embassy_futures::yield_now;
+use embedded_io_async::{ErrorType, Read, Write};
+use embedded_storage::nor_flash::{self, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use server_core::transport::{Clock, Link};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[cfg(feature = "baud_negotiation")]
+use protocol::link::LinkConfig;
+
+pub const SECTOR: usize = 256;
+
+/// Flash held in RAM, which like the real thing can only clear bits without an erase
+pub struct RamFlash {
+    pub data: Vec<u8>,
+    pub erases: usize,
+}
+
+impl RamFlash {
+    pub fn new(sectors: usize) -> Self {
+        Self {
+            data: vec![0xFF; sectors * SECTOR],
+            erases: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FlashError(NorFlashErrorKind);
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl nor_flash::ErrorType for RamFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let start = offset as usize;
+        let Some(data) = self.data.get(start..start + bytes.len()) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        let (from, to) = (from as usize, to as usize);
+        if !from.is_multiple_of(SECTOR) || !to.is_multiple_of(SECTOR) {
+            return Err(FlashError(NorFlashErrorKind::NotAligned));
+        }
+        let Some(data) = self.data.get_mut(from..to) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        data.fill(0xFF);
+        self.erases += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let start = offset as usize;
+        if !start.is_multiple_of(Self::WRITE_SIZE) || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+        {
+            return Err(FlashError(NorFlashErrorKind::NotAligned));
+        }
+        let Some(data) = self.data.get_mut(start..start + bytes.len()) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        for (d, b) in data.iter_mut().zip(bytes) {
+            *d &= *b;
+        }
+        Ok(())
+    }
+}
+
+/// A clock which only moves when the test moves it
+#[derive(Clone, Default)]
+pub struct FakeClock(Rc<Cell<u64>>);
+
+impl FakeClock {
+    pub fn advance_ms(&self, ms: u64) {
+        self.0.set(self.0.get() + ms * 1000);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now_micros(&self) -> u64 {
+        self.0.get()
+    }
+
+    async fn wait_until_ms(&self, deadline_ms: u64) {
+        while self.now_ms() < deadline_ms {
+            yield_now().await;
+        }
+    }
+}
+
+type Buffer = Rc<RefCell<VecDeque<u8>>>;
+
+/// One direction of an in-memory serial link
+pub struct PipeTx(Buffer);
+
+/// The other end of a `PipeTx`
+#[derive(Clone)]
+pub struct PipeRx(Buffer);
+
+impl PipeRx {
+    /// Throw away everything written to the pipe which hasn't been read yet
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl ErrorType for PipeTx {
+    type Error = Infallible;
+}
+
+impl Write for PipeTx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        self.0.borrow_mut().extend(buf);
+        Ok(buf.len())
+    }
+}
+
+impl ErrorType for PipeRx {
+    type Error = Infallible;
+}
+
+impl Read for PipeRx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        loop {
+            {
+                let mut pending = self.0.borrow_mut();
+                if !pending.is_empty() {
+                    let count = buf.len().min(pending.len());
+                    for (b, p) in buf.iter_mut().zip(pending.drain(..count)) {
+                        *b = p;
+                    }
+                    return Ok(count);
+                }
+            }
+            yield_now().await;
+        }
+    }
+}
+
+pub fn pipe() -> (PipeTx, PipeRx) {
+    let buffer = Buffer::default();
+    (PipeTx(buffer.clone()), PipeRx(buffer))
+}
+
+/// Both ends of an in-memory serial link, the server's end and the client's end
+pub struct Duplex {
+    to_client: Buffer,
+    to_server: Buffer,
+    /// Every config the link was opened again with, oldest first
+    #[cfg(feature = "baud_negotiation")]
+    pub reopened: Rc<RefCell<Vec<LinkConfig>>>,
+}
+
+impl Duplex {
+    pub fn new() -> (Self, (PipeTx, PipeRx), (PipeTx, PipeRx)) {
+        let (server_tx, client_rx) = pipe();
+        let (client_tx, server_rx) = pipe();
+        let duplex = Self {
+            to_client: server_tx.0.clone(),
+            to_server: server_rx.0.clone(),
+            #[cfg(feature = "baud_negotiation")]
+            reopened: Rc::default(),
+        };
+        (duplex, (server_tx, server_rx), (client_tx, client_rx))
+    }
+}
+
+impl Link for Duplex {
+    type Tx = PipeTx;
+    type Rx = PipeRx;
+
+    /// The pipe doesn't have a baud rate, so this notes down `config` and hands out the same ends
+    /// again
+    #[cfg(feature = "baud_negotiation")]
+    unsafe fn reopen(&mut self, config: &LinkConfig) -> (PipeTx, PipeRx) {
+        self.reopened.borrow_mut().push(*config);
+        (
+            PipeTx(self.to_client.clone()),
+            PipeRx(self.to_server.clone()),
+        )
+    }
+}
@@ -0,0 +1,115 @@
+#![cfg(not(feature = "strong"))]
+
+use aucpace::Database;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::RistrettoPoint;
+use password_hash::{ParamsString, SaltString};
+use server_core::database::SingleUserDatabase;
+use server_core::lockout::LockoutRecord;
+use server_core::throttle::UserKey;
+
+const USER: &[u8] = b"alice";
+
+fn registered() -> (SingleUserDatabase<16>, RistrettoPoint, SaltString) {
+    let mut database = SingleUserDatabase::default()
+        .with_lockout_threshold(3)
+        .with_fake_secret(&[7; 32]);
+    let verifier = RISTRETTO_BASEPOINT_POINT;
+    let salt = SaltString::encode_b64(b"0123456789abcdef").unwrap();
+    let params: ParamsString = "ln=4,r=8,p=1".parse().unwrap();
+    database.store_verifier(USER, salt.clone(), None, verifier, params);
+    (database, verifier, salt)
+}
+
+#[test]
+fn registered_user_is_looked_up() {
+    let (database, verifier, salt) = registered();
+    let (found, found_salt, params) = database.lookup_verifier(USER).unwrap();
+    assert_eq!(found, verifier);
+    assert_eq!(found_salt, salt);
+    assert_eq!(params.as_str(), "ln=4,r=8,p=1");
+}
+
+#[test]
+fn usernames_too_long_are_not_stored() {
+    let mut database: SingleUserDatabase<4> = SingleUserDatabase::default();
+    let salt = SaltString::encode_b64(b"0123456789abcdef").unwrap();
+    let params: ParamsString = "ln=4,r=8,p=1".parse().unwrap();
+    database.store_verifier(USER, salt, None, RISTRETTO_BASEPOINT_POINT, params);
+    assert!(database.lookup_verifier(USER).is_none());
+}
+
+#[test]
+fn unknown_users_get_a_stable_fake_record() {
+    let (database, verifier, _) = registered();
+    let (first, first_salt, first_params) = database.lookup_verifier(b"mallory").unwrap();
+    let (second, second_salt, second_params) = database.lookup_verifier(b"mallory").unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first_salt, second_salt);
+    assert_ne!(first, verifier);
+
+    // the parameters are copied from the registered user so they don't give the game away
+    assert_eq!(first_params, second_params);
+    assert_eq!(first_params.as_str(), "ln=4,r=8,p=1");
+
+    let (other, _, _) = database.lookup_verifier(b"eve").unwrap();
+    assert_ne!(first, other);
+}
+
+#[test]
+fn unknown_users_are_missing_without_a_fake_secret() {
+    let database: SingleUserDatabase<16> = SingleUserDatabase::default();
+    assert!(database.lookup_verifier(b"mallory").is_none());
+}
+
+#[test]
+fn locked_users_get_a_verifier_no_password_matches() {
+    let (mut database, verifier, salt) = registered();
+    let user = UserKey::new(USER);
+    for failures in 1..=3 {
+        let record = database.record_failure(user).unwrap();
+        assert_eq!(record.failures, failures);
+    }
+    assert!(database.is_locked(user));
+
+    let (locked, locked_salt, _) = database.lookup_verifier(USER).unwrap();
+    assert_ne!(locked, verifier);
+    assert_eq!(locked_salt, salt);
+}
+
+#[test]
+fn failures_are_only_counted_for_the_registered_user() {
+    let (mut database, _, _) = registered();
+    assert_eq!(database.record_failure(UserKey::new(b"mallory")), None);
+}
+
+#[test]
+fn success_resets_the_failure_count() {
+    let (mut database, _, _) = registered();
+    let user = UserKey::new(USER);
+    assert_eq!(database.record_success(user), None);
+
+    database.record_failure(user);
+    assert_eq!(
+        database.record_success(user),
+        Some(LockoutRecord { user, failures: 0 })
+    );
+    assert_eq!(database.record_success(user), None);
+}
+
+#[test]
+fn restored_lockout_survives_a_reset() {
+    let (mut database, _, _) = registered();
+    let user = UserKey::new(USER);
+    database.restore_lockout(LockoutRecord { user, failures: 3 });
+    assert!(database.is_locked(user));
+}
+
+#[test]
+fn erased_users_are_gone() {
+    let (mut database, verifier, _) = registered();
+    assert!(!database.erase_user(b"mallory"));
+    assert!(database.erase_user(USER));
+    // with the fake secret the lookup still succeeds, just not with the real verifier
+    assert_ne!(database.lookup_verifier(USER).unwrap().0, verifier);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::FakeClock;
+use protocol::events::{Event, EventKind};
+use server_core::events::EventLog;
+
+#[test]
+fn events_are_stamped_with_the_time_and_session() {
+    let clock = FakeClock::default();
+    let mut log: EventLog<_, 4> = EventLog::new(clock.clone());
+    log.record(EventKind::Registered);
+    clock.advance_ms(1500);
+    log.start_session();
+    log.record(EventKind::AuthSucceeded);
+
+    let events: Vec<Event> = log.iter().copied().collect();
+    assert_eq!(
+        events,
+        [
+            Event {
+                at_ms: 0,
+                session: 0,
+                kind: EventKind::Registered
+            },
+            Event {
+                at_ms: 1500,
+                session: 1,
+                kind: EventKind::SessionStarted
+            },
+            Event {
+                at_ms: 1500,
+                session: 1,
+                kind: EventKind::AuthSucceeded
+            },
+        ]
+    );
+}
+
+#[test]
+fn the_oldest_events_are_overwritten() {
+    let mut log: EventLog<_, 4> = EventLog::new(FakeClock::default());
+    for _ in 0..6 {
+        log.start_session();
+    }
+
+    let sessions: Vec<u32> = log.iter().map(|event| event.session).collect();
+    assert_eq!(sessions, [3, 4, 5, 6]);
+    assert_eq!(log.recorded(), 6);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{RamFlash, SECTOR};
+use rand_chacha::ChaCha8Rng;
+use rand_core::SeedableRng;
+use server_core::device_secret::{self, SECRET_LEN};
+use server_core::lockout::{LockoutRecord, LockoutStore};
+use server_core::throttle::UserKey;
+
+const SIZE: u32 = SECTOR as u32;
+
+fn record(failures: u32) -> LockoutRecord {
+    LockoutRecord {
+        user: UserKey::new(b"alice"),
+        failures,
+    }
+}
+
+#[test]
+fn empty_flash_has_no_lockout_record() {
+    let mut store = LockoutStore::new(RamFlash::new(1), 0, SIZE);
+    assert_eq!(store.load().unwrap(), None);
+}
+
+#[test]
+fn the_latest_lockout_record_is_loaded() {
+    let mut flash = RamFlash::new(1);
+    let mut store = LockoutStore::new(&mut flash, 0, SIZE);
+    store.load().unwrap();
+    for failures in 1..=3 {
+        store.save(&record(failures)).unwrap();
+    }
+    assert_eq!(store.load().unwrap(), Some(record(3)));
+
+    // a fresh store over the same flash is what the firmware sees after a reset
+    let mut store = LockoutStore::new(&mut flash, 0, SIZE);
+    assert_eq!(store.load().unwrap(), Some(record(3)));
+    store.save(&record(4)).unwrap();
+    assert_eq!(store.load().unwrap(), Some(record(4)));
+}
+
+#[test]
+fn lockout_records_wrap_around_the_region() {
+    let mut store = LockoutStore::new(RamFlash::new(2), SIZE, SIZE);
+    store.load().unwrap();
+    // one more than fits so the region has to be erased
+    let slots = SIZE / 32;
+    for failures in 0..=slots {
+        store.save(&record(failures)).unwrap();
+    }
+    assert_eq!(store.load().unwrap(), Some(record(slots)));
+}
+
+#[test]
+fn torn_lockout_records_are_skipped() {
+    let mut flash = RamFlash::new(1);
+    {
+        let mut store = LockoutStore::new(&mut flash, 0, SIZE);
+        store.load().unwrap();
+        store.save(&record(1)).unwrap();
+        store.save(&record(2)).unwrap();
+    }
+    // clear some bits of the second record as an interrupted write would
+    flash.data[32 + 20] = 0;
+
+    let mut store = LockoutStore::new(&mut flash, 0, SIZE);
+    assert_eq!(store.load().unwrap(), Some(record(1)));
+}
+
+#[test]
+fn erasing_clears_the_lockout() {
+    let mut store = LockoutStore::new(RamFlash::new(1), 0, SIZE);
+    store.load().unwrap();
+    store.save(&record(10)).unwrap();
+    store.erase().unwrap();
+    assert_eq!(store.load().unwrap(), None);
+}
+
+#[test]
+fn device_secret_is_generated_once() {
+    let mut flash = RamFlash::new(1);
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    let first = device_secret::load_or_generate(&mut flash, 0, SIZE, &mut rng).unwrap();
+    let second = device_secret::load_or_generate(&mut flash, 0, SIZE, &mut rng).unwrap();
+    assert_eq!(*first, *second);
+    assert_ne!(*first, [0u8; SECRET_LEN]);
+    assert_eq!(flash.erases, 0);
+}
+
+#[test]
+fn interrupted_device_secret_writes_are_replaced() {
+    let mut flash = RamFlash::new(1);
+    flash.data[..4].copy_from_slice(b"SC\0\0");
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    let secret = device_secret::load_or_generate(&mut flash, 0, SIZE, &mut rng).unwrap();
+    assert_eq!(flash.erases, 1);
+    assert_eq!(
+        *device_secret::load_or_generate(&mut flash, 0, SIZE, &mut rng).unwrap(),
+        *secret
+    );
+}
@@ -0,0 +1,803 @@
+//! Whole sessions against an AuCPace client over an in-memory link
+//!
+//! The client here speaks every variant of AuCPace, and just enough of the extensions around it to
+//! get through a session: it stays at the configured baud rate, never resumes, skips the event log
+//! and ignores the server's timings. The workspace's integration tests cover the extensions
+//! themselves against the real client.
+
+mod common;
+
+use aucpace::{AuCPaceServer, Client, ClientMessage, ServerMessage};
+use common::{Duplex, FakeClock, PipeRx, PipeTx, RamFlash, SECTOR};
+#[cfg(feature = "strong")]
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use embassy_futures::block_on;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, Either};
+use protocol::admission::Admission;
+use protocol::confirmation::KeyConfirmation;
+use protocol::events::EventKind;
+use protocol::key_schedule::KeySchedule;
+use protocol::phase::Phase;
+use protocol::preamble::{self, Preamble, PreambleResponse, CHANNEL_ID};
+use rand_chacha::ChaCha8Rng;
+use rand_core::{OsRng, SeedableRng};
+use scrypt::{Params, Scrypt};
+#[cfg(feature = "static_ssid")]
+use server_core::config::SSID;
+use server_core::config::{FEATURES, K1, LOCKOUT_THRESHOLD, RECV_TIMEOUT_MS};
+use server_core::database::SingleUserDatabase;
+use server_core::handshake::{Handshake, SessionError};
+use server_core::lockout::LockoutStore;
+use server_core::receiver::MsgReceiver;
+use server_core::sender::MsgSender;
+use server_core::throttle::UserKey;
+
+#[cfg(feature = "baud_negotiation")]
+use {
+    embassy_futures::yield_now,
+    protocol::link::{
+        BaudRequest, BaudResponse, LinkCheck, LinkConfig, CHECK_TIMEOUT_MS, SWITCH_DELAY_MS,
+    },
+    server_core::config::{LINK_CONFIG, MAX_BAUDRATE, MIN_BAUDRATE},
+    std::cell::RefCell,
+    std::rc::Rc,
+};
+
+#[cfg(feature = "resumption")]
+use protocol::resumption::{NewTicket, SessionStart};
+
+#[cfg(feature = "reliable")]
+use protocol::reliable::Received;
+
+#[cfg(feature = "event_log")]
+use protocol::events::LogRequest;
+
+#[cfg(feature = "timings")]
+use protocol::phase::PhaseTimings;
+
+const USER: &str = "alice";
+const PASSWORD: &str = "correct horse battery staple";
+const DEVICE_SECRET: [u8; 32] = [7; 32];
+
+type Server = Handshake<Duplex, FakeClock, ChaCha8Rng, RamFlash>;
+
+/// Receive the server's next message as the test client, defaults to receiving a `ServerMessage`
+#[cfg(not(feature = "reliable"))]
+macro_rules! recv {
+    ($client:expr) => {
+        recv!($client, ServerMessage<K1>)
+    };
+    ($client:expr, $msg_ty:ty) => {
+        $client.receiver.recv_msg::<$msg_ty>().await.unwrap()
+    };
+}
+
+/// Receive the server's next message as the test client, defaults to receiving a `ServerMessage`
+///
+/// Every message is acknowledged and the server's ACKs are taken note of. Nothing is lost on the
+/// in-memory link, so nothing has to be sent again.
+#[cfg(feature = "reliable")]
+macro_rules! recv {
+    ($client:expr) => {
+        recv!($client, ServerMessage<K1>)
+    };
+    ($client:expr, $msg_ty:ty) => {
+        loop {
+            match $client.receiver.recv_msg::<$msg_ty>(None).await.unwrap() {
+                Ok(Received::Message {
+                    msg: Some(msg),
+                    ack,
+                }) => {
+                    $client.sender.ack(ack).await;
+                    break msg;
+                }
+                Ok(Received::Message { msg: None, ack }) => $client.sender.ack(ack).await,
+                Ok(Received::Ack(seq)) => $client.sender.acked(seq),
+                _ => panic!("the in-memory link lost or mangled a frame"),
+            }
+        }
+    };
+}
+
+/// The framing is the same in both directions so the client can use the server's sender and receiver
+struct TestClient {
+    sender: MsgSender<PipeTx, FakeClock>,
+    receiver: MsgReceiver<PipeRx, FakeClock>,
+    client: Client,
+    /// Another handle on the receiver's end of the link, to throw away what's left on it
+    #[cfg(feature = "reliable")]
+    rx: PipeRx,
+    #[cfg(feature = "baud_negotiation")]
+    baud: Negotiation,
+}
+
+/// How the test client negotiates the baud rate at the start of each session
+#[cfg(feature = "baud_negotiation")]
+struct Negotiation {
+    /// The rate to ask for, the link stays as it is if this is `LINK_CONFIG`'s
+    baudrate: u32,
+    /// Whether the link check at the new rate goes missing
+    lose_check: bool,
+    clock: FakeClock,
+    /// Every config the server opened the link again with, oldest first
+    reopened: Rc<RefCell<Vec<LinkConfig>>>,
+}
+
+/// Cheap parameters so the tests don't spend their time hashing passwords
+fn params() -> Params {
+    Params::new(4, 8, 1, 32).unwrap()
+}
+
+fn setup() -> (Server, TestClient) {
+    let (server, client, _) = setup_with_clock();
+    (server, client)
+}
+
+fn setup_with_clock() -> (Server, TestClient, FakeClock) {
+    let clock = FakeClock::default();
+    let (link, (server_tx, server_rx), (client_tx, client_rx)) = Duplex::new();
+    #[cfg(feature = "baud_negotiation")]
+    let reopened = link.reopened.clone();
+    let database = SingleUserDatabase::default()
+        .with_lockout_threshold(LOCKOUT_THRESHOLD)
+        .with_fake_secret(&DEVICE_SECRET);
+    let lockout = LockoutStore::new(RamFlash::new(1), 0, SECTOR as u32);
+    let server = Handshake::new(
+        link,
+        server_tx,
+        server_rx,
+        clock.clone(),
+        AuCPaceServer::new(ChaCha8Rng::seed_from_u64(1)),
+        database,
+        lockout,
+        &DEVICE_SECRET,
+        &mut ChaCha8Rng::seed_from_u64(2),
+    );
+    let client = TestClient {
+        sender: MsgSender::new(client_tx, clock.clone()),
+        #[cfg(feature = "reliable")]
+        rx: client_rx.clone(),
+        receiver: MsgReceiver::new(client_rx, clock.clone()),
+        client: Client::new(OsRng),
+        #[cfg(feature = "baud_negotiation")]
+        baud: Negotiation {
+            baudrate: LINK_CONFIG.baudrate,
+            lose_check: false,
+            clock: clock.clone(),
+            reopened,
+        },
+    };
+    (server, client, clock)
+}
+
+impl TestClient {
+    async fn preamble(&mut self, features: u32) -> PreambleResponse {
+        // the server's ACKs from a session it gave up on can still be waiting
+        #[cfg(feature = "reliable")]
+        {
+            self.rx.clear();
+            self.receiver.set_rx(self.rx.clone());
+        }
+
+        self.sender
+            .send_plain(&Preamble::new(features))
+            .await
+            .unwrap();
+        let response = self.receiver.recv_plain().await.unwrap();
+
+        // both ends number their frames from zero again
+        #[cfg(feature = "reliable")]
+        if response == PreambleResponse::Accepted {
+            self.sender.reset_window();
+            self.receiver.reset_window();
+        }
+        response
+    }
+
+    /// Open a session with the server, up to the start of the AuCPace exchange
+    async fn start(&mut self) {
+        assert_eq!(self.preamble(FEATURES).await, PreambleResponse::Accepted);
+
+        #[cfg(feature = "baud_negotiation")]
+        self.negotiate().await;
+
+        self.sender.reset_transcript();
+        self.receiver.reset_transcript();
+        #[cfg(feature = "reliable")]
+        {
+            self.sender.reset_window();
+            self.receiver.reset_window();
+        }
+
+        #[cfg(feature = "resumption")]
+        self.sender.send_msg(&SessionStart::Full).await.unwrap();
+    }
+
+    /// Ask for `baud.baudrate` and check the link at whatever rate the server switches to
+    #[cfg(feature = "baud_negotiation")]
+    async fn negotiate(&mut self) {
+        let request = BaudRequest {
+            baudrate: self.baud.baudrate,
+        };
+        self.sender.send_msg(&request).await.unwrap();
+        let BaudResponse { baudrate } = recv!(self, BaudResponse);
+        if baudrate == LINK_CONFIG.baudrate {
+            return;
+        }
+
+        let clock = self.baud.clock.clone();
+        clock.advance_ms(SWITCH_DELAY_MS);
+        if self.baud.lose_check {
+            // wait out the server's check, it switches to the new rate and then back again
+            let switched_back = self.baud.reopened.borrow().len() + 2;
+            while self.baud.reopened.borrow().len() < switched_back {
+                yield_now().await;
+                clock.advance_ms(CHECK_TIMEOUT_MS);
+            }
+            return;
+        }
+
+        let check = LinkCheck { nonce: 0x5EED };
+        self.sender.send_msg(&check).await.unwrap();
+        assert_eq!(recv!(self, LinkCheck), check);
+    }
+
+    /// See the session through to the end once the keys are derived
+    async fn finish(&mut self) {
+        #[cfg(feature = "resumption")]
+        let _ = recv!(self, NewTicket);
+        #[cfg(feature = "event_log")]
+        self.sender.send_msg(&LogRequest::Skip).await.unwrap();
+        #[cfg(feature = "timings")]
+        let _ = recv!(self, PhaseTimings);
+    }
+
+    async fn register(&mut self) {
+        assert_eq!(self.preamble(FEATURES).await, PreambleResponse::Accepted);
+        #[cfg(not(feature = "strong"))]
+        let message = self
+            .client
+            .register_alloc(USER.as_bytes(), PASSWORD, params(), Scrypt)
+            .unwrap();
+        #[cfg(feature = "strong")]
+        let message = self
+            .client
+            .register_alloc_strong(USER.as_bytes(), PASSWORD, params(), Scrypt)
+            .unwrap();
+        self.sender.send_msg(&message).await.unwrap();
+    }
+
+    /// Run a session through the key exchange, returns the subkeys if the server let us that far
+    async fn authenticate(&mut self, username: &str, password: &str) -> Option<KeySchedule> {
+        self.start().await;
+
+        #[cfg(feature = "static_ssid")]
+        let client = self.client.begin_prestablished_ssid(SSID).unwrap();
+        #[cfg(not(feature = "static_ssid"))]
+        let client = {
+            let (client, message) = self.client.begin();
+            self.sender.send_msg(&message).await.unwrap();
+            let ServerMessage::Nonce(nonce) = recv!(self) else {
+                panic!("expected the server's nonce");
+            };
+            client.agree_ssid(nonce)
+        };
+
+        #[cfg(not(feature = "strong"))]
+        let (client, message) = client.start_augmentation(username.as_bytes(), password.as_bytes());
+        #[cfg(feature = "strong")]
+        let (client, message) =
+            client.start_augmentation_strong(username.as_bytes(), password.as_bytes(), &mut OsRng);
+        self.sender.send_msg(&message).await.unwrap();
+        if recv!(self, Admission) != Admission::Proceed {
+            return None;
+        }
+
+        #[cfg(not(feature = "strong"))]
+        let client = {
+            let ServerMessage::AugmentationInfo { x_pub, salt, .. } = recv!(self) else {
+                panic!("expected the augmentation info");
+            };
+            client
+                .generate_cpace_alloc(x_pub, &salt, params(), Scrypt)
+                .unwrap()
+        };
+        #[cfg(feature = "strong")]
+        let client = {
+            let ServerMessage::StrongAugmentationInfo {
+                x_pub,
+                blinded_salt,
+                ..
+            } = recv!(self)
+            else {
+                panic!("expected the strong augmentation info");
+            };
+            client
+                .generate_cpace_alloc(x_pub, blinded_salt, params(), Scrypt)
+                .unwrap()
+        };
+
+        let (client, message) = client.generate_public_key(CHANNEL_ID, &mut OsRng);
+        self.sender.send_msg(&message).await.unwrap();
+        let ServerMessage::PublicKey(server_pubkey) = recv!(self) else {
+            panic!("expected the server's public key");
+        };
+
+        // the server doesn't answer the confirmation, so it always looks accepted
+        let schedule = if cfg!(feature = "implicit") {
+            let key = client.implicit_auth(server_pubkey).unwrap();
+            let schedule = self.schedule(&key);
+            let confirmation = KeyConfirmation::new(&schedule.client_to_server());
+            self.sender.send_msg(&confirmation).await.unwrap();
+            schedule
+        } else {
+            let (client, message) = client.receive_server_pubkey(server_pubkey).unwrap();
+            self.sender.send_msg(&message).await.unwrap();
+            let ServerMessage::Authenticator(authenticator) = recv!(self) else {
+                panic!("expected the server's authenticator");
+            };
+            let key = client.receive_server_authenticator(authenticator).unwrap();
+            self.schedule(&key)
+        };
+
+        self.finish().await;
+        Some(schedule)
+    }
+
+    /// Ask for `username`'s augmentation info, returned as the server encoded it
+    async fn augmentation_info(&mut self, username: &str) -> Vec<u8> {
+        self.start().await;
+        #[cfg(not(feature = "static_ssid"))]
+        {
+            let message: ClientMessage<K1> = ClientMessage::Nonce([0; K1]);
+            self.sender.send_msg(&message).await.unwrap();
+            let _ = recv!(self);
+        }
+
+        #[cfg(not(feature = "strong"))]
+        let message: ClientMessage<K1> = ClientMessage::Username(username.as_bytes());
+        #[cfg(feature = "strong")]
+        let message: ClientMessage<K1> = ClientMessage::StrongUsername {
+            username: username.as_bytes(),
+            blinded: RISTRETTO_BASEPOINT_POINT,
+        };
+        self.sender.send_msg(&message).await.unwrap();
+        assert_eq!(recv!(self, Admission), Admission::Proceed);
+
+        let info = recv!(self);
+        let mut buf = [0u8; 256];
+        postcard::to_slice(&info, &mut buf).unwrap().to_vec()
+    }
+
+    /// The subkeys for `key` as the client derives them, bound to both transcripts
+    fn schedule(&self, key: &[u8]) -> KeySchedule {
+        KeySchedule::new(key, self.sender.transcript(), self.receiver.transcript())
+    }
+}
+
+/// Run the server's next session alongside `client`, which never finishes if the server gives up
+fn session<F: core::future::Future>(server: &mut Server, client: F) -> Result<(), SessionError> {
+    match block_on(select(server.session(), client)) {
+        Either::First(result) => result.map(|_| ()),
+        Either::Second(_) => panic!("the client finished before the server"),
+    }
+}
+
+/// Run the server's next session against a client with the wrong password
+///
+/// In implicit mode the client can't tell and finishes alongside the server.
+fn wrong_password(server: &mut Server, client: &mut TestClient) -> Result<(), SessionError> {
+    if cfg!(feature = "implicit") {
+        let (result, _) = block_on(join(server.session(), client.authenticate(USER, "hunter2")));
+        result.map(|_| ())
+    } else {
+        session(server, client.authenticate(USER, "hunter2"))
+    }
+}
+
+/// The server turned the client away, in implicit mode it only counts the failure
+fn assert_failed(result: Result<(), SessionError>) {
+    if cfg!(feature = "implicit") {
+        assert!(result.is_ok());
+    } else {
+        assert!(matches!(result, Err(SessionError::AuthFailed)));
+    }
+}
+
+#[test]
+fn registration_stores_the_user() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    #[cfg(not(feature = "strong"))]
+    {
+        use aucpace::Database;
+        assert!(server.database().lookup_verifier(USER.as_bytes()).is_some());
+    }
+    #[cfg(feature = "strong")]
+    {
+        use aucpace::StrongDatabase;
+        assert!(server
+            .database()
+            .lookup_verifier_strong(USER.as_bytes())
+            .is_some());
+    }
+}
+
+#[test]
+fn client_and_server_agree_on_the_session() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    let (session, schedule) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    let session = session.unwrap();
+    assert!(!session.resumed);
+    assert!(session.bytes_sent > 0);
+
+    // the subkeys are bound to both transcripts, so they only match if both sides saw the same
+    assert_eq!(
+        session.schedule.client_to_server().as_slice(),
+        schedule.unwrap().client_to_server().as_slice()
+    );
+}
+
+#[test]
+fn sessions_can_be_run_back_to_back() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+    for _ in 0..3 {
+        let (session, schedule) =
+            block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+        assert!(session.is_ok());
+        assert!(schedule.is_some());
+    }
+}
+
+/// The kinds of the events the server recorded in its latest session
+fn last_session_events(server: &Server) -> Vec<EventKind> {
+    let events: Vec<_> = server.events().iter().collect();
+    let latest = events.last().unwrap().session;
+    events
+        .iter()
+        .filter(|event| event.session == latest)
+        .map(|event| event.kind)
+        .collect()
+}
+
+#[test]
+fn sessions_are_logged() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+    assert_eq!(
+        server.events().iter().next().unwrap().kind,
+        EventKind::Registered
+    );
+
+    let (result, _) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    result.unwrap();
+    #[cfg(not(feature = "implicit"))]
+    assert_eq!(
+        last_session_events(&server),
+        [
+            EventKind::SessionStarted,
+            EventKind::PhaseReached(Phase::Ssid),
+            EventKind::PhaseReached(Phase::Augmentation),
+            EventKind::PhaseReached(Phase::CPace),
+            EventKind::PhaseReached(Phase::Authenticator),
+            EventKind::AuthSucceeded,
+        ]
+    );
+    // the client's key confirmation stands in for the authenticator
+    #[cfg(feature = "implicit")]
+    assert_eq!(
+        last_session_events(&server),
+        [
+            EventKind::SessionStarted,
+            EventKind::PhaseReached(Phase::Ssid),
+            EventKind::PhaseReached(Phase::Augmentation),
+            EventKind::PhaseReached(Phase::CPace),
+            EventKind::AuthSucceeded,
+        ]
+    );
+}
+
+#[test]
+fn failures_are_logged() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    assert_failed(wrong_password(&mut server, &mut client));
+    assert_eq!(
+        last_session_events(&server).last(),
+        Some(&EventKind::AuthFailed)
+    );
+}
+
+#[cfg(not(feature = "implicit"))]
+#[test]
+fn wrong_password_fails_authentication() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    let result = session(&mut server, client.authenticate(USER, "hunter2"));
+    assert!(matches!(result, Err(SessionError::AuthFailed)));
+}
+
+#[cfg(not(feature = "implicit"))]
+#[test]
+fn unknown_users_fail_like_a_wrong_password() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    let result = session(&mut server, client.authenticate("mallory", PASSWORD));
+    assert!(matches!(result, Err(SessionError::AuthFailed)));
+}
+
+#[test]
+fn repeated_failures_are_throttled() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    // the delay starts with the first failure past the free attempts
+    let free_attempts = server_core::config::THROTTLE_POLICY.per_user.free_attempts;
+    for _ in 0..=free_attempts {
+        assert_failed(wrong_password(&mut server, &mut client));
+    }
+
+    // the client has to be let in on the throttle's answer so both sides finish
+    let (result, schedule) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    assert!(matches!(result, Err(SessionError::Throttled { millis }) if millis > 0));
+    assert!(schedule.is_none());
+}
+
+#[test]
+fn failures_lock_the_account() {
+    let (mut server, mut client, clock) = setup_with_clock();
+    block_on(join(server.register(), client.register()));
+
+    let user = UserKey::new(USER.as_bytes());
+    let max_delay = server_core::config::THROTTLE_POLICY.per_user.max_delay;
+    for _ in 0..LOCKOUT_THRESHOLD {
+        assert!(!server.database().is_locked(user));
+        assert_failed(wrong_password(&mut server, &mut client));
+        // wait out the throttle so only the lockout is in the way
+        clock.advance_ms(max_delay.as_millis() as u64);
+    }
+    assert!(server.database().is_locked(user));
+
+    // the right password no longer gets the client the server's keys
+    #[cfg(not(feature = "implicit"))]
+    {
+        let result = session(&mut server, client.authenticate(USER, PASSWORD));
+        assert!(matches!(result, Err(SessionError::AuthFailed)));
+    }
+    #[cfg(feature = "implicit")]
+    {
+        let (session, schedule) =
+            block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+        assert_ne!(
+            session.unwrap().schedule.client_to_server().as_slice(),
+            schedule.unwrap().client_to_server().as_slice()
+        );
+    }
+}
+
+/// Only sessions which fail count towards the lockout, in implicit mode too
+#[test]
+fn the_right_password_never_locks_the_account() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    let user = UserKey::new(USER.as_bytes());
+    for _ in 0..=LOCKOUT_THRESHOLD {
+        let (session, schedule) =
+            block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+        assert_eq!(
+            session.unwrap().schedule.client_to_server().as_slice(),
+            schedule.unwrap().client_to_server().as_slice()
+        );
+        assert!(!server.database().is_locked(user));
+    }
+    assert_eq!(
+        last_session_events(&server).last(),
+        Some(&EventKind::AuthSucceeded)
+    );
+}
+
+/// Neither side can tell, but the keys the client derives are of no use to it
+#[cfg(feature = "implicit")]
+#[test]
+fn wrong_passwords_derive_different_keys() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    for (username, password) in [(USER, "hunter2"), ("mallory", PASSWORD)] {
+        let (session, schedule) = block_on(join(
+            server.session(),
+            client.authenticate(username, password),
+        ));
+        assert_ne!(
+            session.unwrap().schedule.client_to_server().as_slice(),
+            schedule.unwrap().client_to_server().as_slice()
+        );
+    }
+}
+
+/// Register `USER` with a new server and return the augmentation info it sends `username`
+///
+/// The server carries on with its public key, so the session can't be followed by another.
+fn augmentation_info(username: &str) -> Vec<u8> {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+    match block_on(select(server.session(), client.augmentation_info(username))) {
+        Either::First(_) => panic!("the server finished before the client"),
+        Either::Second(info) => info,
+    }
+}
+
+#[test]
+fn unknown_users_get_augmentation_info_of_the_same_shape() {
+    let registered = augmentation_info(USER);
+    let unknown = augmentation_info("mallory");
+    assert_eq!(registered.len(), unknown.len());
+
+    let registered: ServerMessage<K1> = postcard::from_bytes(&registered).unwrap();
+    let unknown: ServerMessage<K1> = postcard::from_bytes(&unknown).unwrap();
+    match (registered, unknown) {
+        #[cfg(not(feature = "strong"))]
+        (
+            ServerMessage::AugmentationInfo {
+                group,
+                salt,
+                pbkdf_params,
+                ..
+            },
+            ServerMessage::AugmentationInfo {
+                group: unknown_group,
+                salt: unknown_salt,
+                pbkdf_params: unknown_params,
+                ..
+            },
+        ) => {
+            assert_eq!(group, unknown_group);
+            assert_eq!(salt.as_str().len(), unknown_salt.as_str().len());
+            assert_eq!(pbkdf_params.as_str(), unknown_params.as_str());
+        }
+        // the salt is blinded by the client, so only the group and the parameters could differ
+        #[cfg(feature = "strong")]
+        (
+            ServerMessage::StrongAugmentationInfo {
+                group,
+                pbkdf_params,
+                ..
+            },
+            ServerMessage::StrongAugmentationInfo {
+                group: unknown_group,
+                pbkdf_params: unknown_params,
+                ..
+            },
+        ) => {
+            assert_eq!(group, unknown_group);
+            assert_eq!(pbkdf_params.as_str(), unknown_params.as_str());
+        }
+        (registered, unknown) => {
+            panic!("expected augmentation info, got {registered:?} and {unknown:?}")
+        }
+    }
+}
+
+#[test]
+fn mismatched_preambles_are_rejected() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    let (result, response) = block_on(join(
+        server.session(),
+        client.preamble(FEATURES ^ preamble::STRONG),
+    ));
+    assert!(matches!(result, Err(SessionError::Preamble)));
+    assert_eq!(
+        response,
+        PreambleResponse::Mismatch(Preamble::new(FEATURES))
+    );
+}
+
+#[test]
+fn unexpected_messages_end_the_session() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    let result = session(&mut server, async {
+        client.start().await;
+        // the authenticator is the last message of every variant of the exchange
+        let message: ClientMessage<K1> = ClientMessage::Authenticator([0; 64]);
+        client.sender.send_msg(&message).await.unwrap();
+        core::future::pending::<()>().await
+    });
+    assert!(matches!(result, Err(SessionError::UnexpectedMessage)));
+}
+
+#[test]
+fn quiet_clients_time_out() {
+    let (mut server, mut client, clock) = setup_with_clock();
+    block_on(join(server.register(), client.register()));
+
+    let result = session(&mut server, async {
+        assert_eq!(client.preamble(FEATURES).await, PreambleResponse::Accepted);
+        clock.advance_ms(RECV_TIMEOUT_MS);
+        core::future::pending::<()>().await
+    });
+    assert!(matches!(result, Err(SessionError::Timeout)));
+    assert_eq!(
+        last_session_events(&server).last(),
+        Some(&EventKind::Timeout)
+    );
+
+    // the server is ready for the next session straight away
+    let (session, schedule) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    assert!(session.is_ok());
+    assert!(schedule.is_some());
+}
+
+#[cfg(feature = "baud_negotiation")]
+#[test]
+fn the_link_switches_to_the_negotiated_rate() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    client.baud.baudrate = 115_200;
+    let (session, schedule) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    assert_eq!(
+        session.unwrap().schedule.client_to_server().as_slice(),
+        schedule.unwrap().client_to_server().as_slice()
+    );
+    let negotiated = LINK_CONFIG.with_baudrate(115_200);
+    assert_eq!(*client.baud.reopened.borrow(), [negotiated]);
+
+    // the next session starts at the configured rate again
+    client.baud.baudrate = LINK_CONFIG.baudrate;
+    let (session, _) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    session.unwrap();
+    assert_eq!(*client.baud.reopened.borrow(), [negotiated, LINK_CONFIG]);
+}
+
+#[cfg(feature = "baud_negotiation")]
+#[test]
+fn a_lost_link_check_falls_back_to_the_configured_rate() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    client.baud.baudrate = 115_200;
+    client.baud.lose_check = true;
+    let (session, schedule) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+    assert_eq!(
+        session.unwrap().schedule.client_to_server().as_slice(),
+        schedule.unwrap().client_to_server().as_slice()
+    );
+    assert_eq!(
+        *client.baud.reopened.borrow(),
+        [LINK_CONFIG.with_baudrate(115_200), LINK_CONFIG]
+    );
+    assert!(last_session_events(&server).contains(&EventKind::Timeout));
+}
+
+#[cfg(feature = "baud_negotiation")]
+#[test]
+fn rates_out_of_range_are_clamped() {
+    let (mut server, mut client) = setup();
+    block_on(join(server.register(), client.register()));
+
+    for (asked, clamped) in [
+        (MAX_BAUDRATE * 2, MAX_BAUDRATE),
+        (MIN_BAUDRATE / 2, MIN_BAUDRATE),
+    ] {
+        client.baud.baudrate = asked;
+        let (session, _) = block_on(join(server.session(), client.authenticate(USER, PASSWORD)));
+        session.unwrap();
+        assert_eq!(
+            client.baud.reopened.borrow().last(),
+            Some(&LINK_CONFIG.with_baudrate(clamped))
+        );
+    }
+}
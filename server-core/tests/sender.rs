@@ -0,0 +1,23 @@
+mod common;
+
+use common::{pipe, FakeClock};
+use embassy_futures::block_on;
+use protocol::fragment::FragmentError;
+use protocol::key_schedule::Transcript;
+use server_core::config::SEND_MSG_LEN;
+use server_core::sender::MsgSender;
+
+#[test]
+fn messages_too_long_to_send_are_an_error() {
+    let (tx, _rx) = pipe();
+    let mut sender = MsgSender::new(tx, FakeClock::default());
+
+    let message = vec![0xA5u8; SEND_MSG_LEN];
+    let result = block_on(sender.send_msg(&message.as_slice()));
+    assert!(matches!(result, Err(FragmentError::TooLong { .. })));
+    // nothing of the message went out
+    assert_eq!(sender.transcript().hash(), Transcript::default().hash());
+
+    let sent = block_on(sender.send_msg(&[0xA5u8; 16])).unwrap();
+    assert!(sent > 16);
+}
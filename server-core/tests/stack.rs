@@ -0,0 +1,54 @@
+use server_core::stack::{paint, peak, PAINT};
+
+const WORDS: usize = 16;
+
+/// A stack of `WORDS` words, painted below the last `used` of them
+fn painted(used: usize) -> [u32; WORDS] {
+    let mut stack = [0; WORDS];
+    let range = stack.as_mut_ptr_range();
+    // SAFETY: the words belong to the array
+    unsafe { paint(range.start, range.end.sub(used)) };
+    stack
+}
+
+fn measure(stack: &[u32]) -> usize {
+    let range = stack.as_ptr_range();
+    // SAFETY: as above
+    unsafe { peak(range.start, range.end) }
+}
+
+#[test]
+fn painting_stops_at_the_end() {
+    let stack = painted(3);
+    assert!(stack[..WORDS - 3].iter().all(|&word| word == PAINT));
+    assert_eq!(stack[WORDS - 3..], [0; 3]);
+}
+
+#[test]
+fn the_peak_is_the_overwritten_part() {
+    let mut stack = painted(2);
+    assert_eq!(measure(&stack), 2 * 4);
+
+    // a deeper call, leaving some of its frame untouched
+    stack[WORDS - 6] = 0;
+    stack[WORDS - 4] = 0;
+    assert_eq!(measure(&stack), 6 * 4);
+}
+
+#[test]
+fn painting_again_forgets_the_peak() {
+    let mut stack = painted(2);
+    stack[WORDS - 6] = 0;
+    let range = stack.as_mut_ptr_range();
+    // SAFETY: as above
+    unsafe { paint(range.start, range.end.sub(2)) };
+    assert_eq!(measure(&stack), 2 * 4);
+}
+
+#[test]
+fn an_overflowed_stack_is_all_used() {
+    let mut stack = painted(2);
+    stack[0] = 0;
+    assert_eq!(measure(&stack), WORDS * 4);
+    assert_eq!(measure(&[PAINT; WORDS]), 0);
+}
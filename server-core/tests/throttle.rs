@@ -0,0 +1,183 @@
+use core::time::Duration;
+use server_core::config::THROTTLE_POLICY;
+use server_core::throttle::{Backoff, Throttle, ThrottlePolicy, UserKey};
+
+const POLICY: ThrottlePolicy = ThrottlePolicy {
+    per_user: Backoff {
+        free_attempts: 2,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(8),
+        reset_after: Duration::from_secs(60),
+    },
+    global: Backoff {
+        free_attempts: 5,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(4),
+        reset_after: Duration::from_secs(60),
+    },
+};
+
+#[test]
+fn backoff_doubles_up_to_the_maximum() {
+    let backoff = POLICY.per_user;
+    let delays: Vec<_> = (0..8).map(|failures| backoff.delay(failures)).collect();
+    assert_eq!(
+        delays,
+        [0, 0, 0, 1, 2, 4, 8, 8].map(Duration::from_secs).to_vec()
+    );
+    assert_eq!(backoff.delay(u32::MAX), backoff.max_delay);
+}
+
+#[test]
+fn free_attempts_are_not_throttled() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let user = UserKey::new(b"alice");
+    for _ in 0..POLICY.per_user.free_attempts {
+        assert_eq!(throttle.check(user, 0), None);
+        throttle.record_failure(user, 0);
+    }
+    assert_eq!(throttle.check(user, 0), None);
+
+    throttle.record_failure(user, 0);
+    assert_eq!(throttle.check(user, 0), Some(Duration::from_secs(1)));
+    assert_eq!(throttle.check(user, 400), Some(Duration::from_millis(600)));
+    assert_eq!(throttle.check(user, 1000), None);
+}
+
+#[test]
+fn a_users_delay_stops_at_the_maximum() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let user = UserKey::new(b"alice");
+    let mut delays = Vec::new();
+    for _ in 0..8 {
+        throttle.record_failure(user, 0);
+        delays.push(throttle.check(user, 0).unwrap_or_default());
+    }
+    assert_eq!(
+        delays,
+        [0, 0, 1, 2, 4, 8, 8, 8].map(Duration::from_secs).to_vec()
+    );
+}
+
+#[test]
+fn failures_are_forgotten_after_a_quiet_period() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let user = UserKey::new(b"alice");
+    for _ in 0..4 {
+        throttle.record_failure(user, 0);
+    }
+    assert!(throttle.check(user, 0).is_some());
+
+    let later = POLICY.per_user.reset_after.as_millis() as u64 + 1;
+    throttle.record_failure(user, later);
+    assert_eq!(throttle.check(user, later), None);
+}
+
+#[test]
+fn success_clears_the_users_failures() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let user = UserKey::new(b"alice");
+    for _ in 0..3 {
+        throttle.record_failure(user, 0);
+    }
+    assert!(throttle.check(user, 0).is_some());
+
+    throttle.record_success(user);
+    assert_eq!(throttle.check(user, 0), None);
+}
+
+#[test]
+fn cycling_usernames_hits_the_global_limit() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    for i in 0..=POLICY.global.free_attempts {
+        throttle.record_failure(UserKey::new(format!("user{i}").as_bytes()), 0);
+    }
+
+    // a username which never failed is still slowed down
+    let fresh = UserKey::new(b"mallory");
+    assert_eq!(throttle.check(fresh, 0), Some(POLICY.global.base_delay));
+}
+
+#[test]
+fn the_global_delay_doubles_up_to_its_maximum() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let fresh = UserKey::new(b"mallory");
+    let mut delays = Vec::new();
+    for i in 0..POLICY.global.free_attempts + 5 {
+        throttle.record_failure(UserKey::new(format!("user{i}").as_bytes()), 0);
+        delays.push(throttle.check(fresh, 0).unwrap_or_default());
+    }
+
+    let (free, throttled) = delays.split_at(POLICY.global.free_attempts as usize);
+    assert!(free.iter().all(Duration::is_zero));
+    assert_eq!(
+        throttled,
+        [500, 1000, 2000, 4000, 4000].map(Duration::from_millis)
+    );
+}
+
+#[test]
+fn success_leaves_the_global_failures() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let alice = UserKey::new(b"alice");
+    for _ in 0..=POLICY.global.free_attempts {
+        throttle.record_failure(alice, 0);
+    }
+
+    // guessing one username right doesn't excuse the guesses at the others
+    throttle.record_success(alice);
+    let fresh = UserKey::new(b"mallory");
+    assert_eq!(throttle.check(fresh, 0), Some(POLICY.global.base_delay));
+    assert_eq!(throttle.check(alice, 0), Some(POLICY.global.base_delay));
+}
+
+#[test]
+fn the_longer_delay_applies() {
+    let mut throttle: Throttle<4> = Throttle::new(POLICY);
+    let alice = UserKey::new(b"alice");
+    for _ in 0..=POLICY.global.free_attempts {
+        throttle.record_failure(alice, 0);
+    }
+
+    // alice has run into her own limit as well as the global one
+    let per_user = POLICY.per_user.delay(POLICY.global.free_attempts + 1);
+    assert!(per_user > POLICY.global.base_delay);
+    assert_eq!(throttle.check(alice, 0), Some(per_user));
+}
+
+#[test]
+fn the_shipped_policy_slows_one_user_before_everyone() {
+    let (per_user, global) = (THROTTLE_POLICY.per_user, THROTTLE_POLICY.global);
+    assert!(per_user.free_attempts < global.free_attempts);
+    for backoff in [per_user, global] {
+        assert!(!backoff.delay(backoff.free_attempts + 1).is_zero());
+        assert_eq!(backoff.delay(u32::MAX), backoff.max_delay);
+        // the failures have to be remembered for longer than the wait they impose
+        assert!(backoff.max_delay < backoff.reset_after);
+    }
+}
+
+#[test]
+fn the_least_recent_failure_is_evicted_when_full() {
+    let mut throttle: Throttle<2> = Throttle::new(ThrottlePolicy {
+        global: Backoff {
+            free_attempts: u32::MAX,
+            ..POLICY.global
+        },
+        ..POLICY
+    });
+    let (alice, bob, carol) = (
+        UserKey::new(b"alice"),
+        UserKey::new(b"bob"),
+        UserKey::new(b"carol"),
+    );
+    for now in 0..3 {
+        throttle.record_failure(alice, now);
+        throttle.record_failure(bob, now + 10);
+    }
+    assert!(throttle.check(alice, 20).is_some());
+
+    throttle.record_failure(carol, 20);
+    assert_eq!(throttle.check(alice, 20), None);
+    assert!(throttle.check(bob, 20).is_some());
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "resumption")]
+
+use core::time::Duration;
+use protocol::resumption::SECRET_LEN;
+use rand_chacha::ChaCha8Rng;
+use rand_core::SeedableRng;
+use server_core::tickets::{TicketError, TicketIssuer, TICKET_LEN};
+
+const LIFETIME: Duration = Duration::from_secs(60);
+const SECRET: [u8; SECRET_LEN] = [0x42; SECRET_LEN];
+const DEVICE_SECRET: [u8; 32] = [7; 32];
+
+fn issuer() -> TicketIssuer {
+    TicketIssuer::new(&DEVICE_SECRET, &mut ChaCha8Rng::seed_from_u64(1), LIFETIME)
+}
+
+fn issue(tickets: &mut TicketIssuer, now_ms: u64) -> [u8; TICKET_LEN] {
+    let mut ticket = [0u8; TICKET_LEN];
+    tickets.issue(&SECRET, now_ms, &mut ticket).unwrap();
+    ticket
+}
+
+#[test]
+fn tickets_hold_the_secret() {
+    let mut tickets = issuer();
+    let ticket = issue(&mut tickets, 0);
+    assert!(!ticket.windows(SECRET_LEN).any(|w| w == SECRET));
+    assert_eq!(*tickets.redeem(&ticket, 1000).unwrap(), SECRET);
+}
+
+#[test]
+fn tickets_can_only_be_redeemed_once() {
+    let mut tickets = issuer();
+    let ticket = issue(&mut tickets, 0);
+    tickets.redeem(&ticket, 0).unwrap();
+    assert_eq!(tickets.redeem(&ticket, 0), Err(TicketError::Replayed));
+}
+
+#[test]
+fn tickets_expire() {
+    let mut tickets = issuer();
+    let ticket = issue(&mut tickets, 1000);
+    let late = 1000 + LIFETIME.as_millis() as u64 + 1;
+    assert_eq!(tickets.redeem(&ticket, late), Err(TicketError::Expired));
+}
+
+#[test]
+fn tampered_tickets_are_forged() {
+    let mut tickets = issuer();
+    let ticket = issue(&mut tickets, 0);
+    for i in [0, TICKET_LEN / 2, TICKET_LEN - 1] {
+        let mut tampered = ticket;
+        tampered[i] ^= 1;
+        assert_eq!(tickets.redeem(&tampered, 0), Err(TicketError::Forged));
+    }
+    assert_eq!(tickets.redeem(&ticket[1..], 0), Err(TicketError::Malformed));
+}
+
+#[test]
+fn tickets_sealed_under_another_key_are_forged() {
+    let ticket = issue(&mut issuer(), 0);
+
+    // another device, even one which drew the same salt
+    let mut other_device = TicketIssuer::new(&[8; 32], &mut ChaCha8Rng::seed_from_u64(1), LIFETIME);
+    assert_eq!(other_device.redeem(&ticket, 0), Err(TicketError::Forged));
+
+    // the same device after a reset, so nothing issued before it can be replayed
+    let mut rebooted =
+        TicketIssuer::new(&DEVICE_SECRET, &mut ChaCha8Rng::seed_from_u64(2), LIFETIME);
+    assert_eq!(rebooted.redeem(&ticket, 0), Err(TicketError::Forged));
+}
+
+#[test]
+fn tickets_fall_out_of_the_replay_window() {
+    let mut tickets = issuer();
+    let oldest = issue(&mut tickets, 0);
+    for _ in 0..u64::BITS {
+        issue(&mut tickets, 0);
+    }
+    assert_eq!(tickets.redeem(&oldest, 0), Err(TicketError::Expired));
+}
@@ -0,0 +1,21 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Each board has a memory.x of its own, the Nucleo's keeps the firmware out of the sectors the
+/// board uses for the device secret and the lockout records
+fn main() {
+    println!("cargo:rerun-if-changed=memory-nucleo.x");
+    println!("cargo:rerun-if-changed=memory-qemu.x");
+    let memory = if env::var_os("CARGO_FEATURE_QEMU").is_some() {
+        "memory-qemu.x"
+    } else if env::var_os("CARGO_FEATURE_NUCLEO").is_some() {
+        "memory-nucleo.x"
+    } else {
+        return;
+    };
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy(memory, out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+}
@@ -0,0 +1,7 @@
+/* The Nucleo-F401RE's STM32F401RE, 512K of flash and 96K of RAM */
+MEMORY
+{
+  /* only the first 256K holds the firmware, the device secret and lockout sectors follow it */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  RAM : ORIGIN = 0x20000000, LENGTH = 96K
+}
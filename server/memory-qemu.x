@@ -0,0 +1,6 @@
+/* QEMU's mps2-an386, the firmware runs from the 4M of SSRAM which stands in for flash */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = 4M
+  RAM : ORIGIN = 0x20000000, LENGTH = 4M
+}
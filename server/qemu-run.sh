@@ -0,0 +1,12 @@
+#!/bin/sh
+# Run the firmware built with the `qemu` feature on QEMU's mps2-an386 machine
+#
+# UART0 is opened as a pty for the client, QEMU prints its path when it starts. The defmt logs
+# come out over semihosting and are decoded with defmt-print.
+set -e
+
+elf="$1"
+qemu-system-arm -machine mps2-an386 -nographic -monitor none \
+    -semihosting-config enable=on,target=native \
+    -serial pty \
+    -kernel "$elf" | defmt-print -e "$elf"
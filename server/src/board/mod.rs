@@ -0,0 +1,58 @@
+//! The boards the firmware runs on
+//!
+//! Each board brings up its clocks and hands the firmware a link to the client, a clock to time
+//! the protocol with, the flash the device secret and lockout records are kept in and a seed from
+//! whatever entropy it has. Exactly one board is picked with the `nucleo` or `qemu` feature.
+
+#[cfg(all(feature = "nucleo", feature = "qemu"))]
+compile_error!("only one of the `nucleo` and `qemu` features can be enabled");
+#[cfg(not(any(feature = "nucleo", feature = "qemu")))]
+compile_error!("one of the `nucleo` and `qemu` features has to be enabled");
+
+#[cfg(feature = "nucleo")]
+mod nucleo;
+#[cfg(feature = "qemu")]
+mod qemu;
+
+use embedded_storage::nor_flash::NorFlash;
+use server_core::transport::{Clock, Link};
+
+/// The board the firmware was built for
+#[cfg(feature = "nucleo")]
+pub type Current = nucleo::Nucleo;
+#[cfg(feature = "qemu")]
+pub type Current = qemu::Qemu;
+
+/// What the firmware needs from the board it runs on
+pub trait Board {
+    type Link: Link;
+    type Clock: Clock + Clone;
+    type Flash: NorFlash;
+
+    /// Where in `Flash` the device secret is kept, a region to itself away from the firmware
+    const DEVICE_SECRET: FlashRegion;
+    /// Where in `Flash` the lockout records are kept
+    const LOCKOUT: FlashRegion;
+
+    /// Set up the clocks and the peripherals the firmware uses
+    fn init() -> Parts<Self>;
+}
+
+/// `size` bytes of flash starting `offset` bytes in, both whole erase sectors
+pub struct FlashRegion {
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// The peripherals a board hands over to the firmware
+pub struct Parts<B: Board + ?Sized> {
+    pub link: B::Link,
+    pub tx: <B::Link as Link>::Tx,
+    pub rx: <B::Link as Link>::Rx,
+    pub clock: B::Clock,
+    pub flash: B::Flash,
+    /// Whether the operator asked for the account lockout to be cleared while the board booted
+    pub clear_lockout: bool,
+    /// Seed for the firmware's RNGs, drawn from the board's entropy at every boot
+    pub seed: [u8; 32],
+}
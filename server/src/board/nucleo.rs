@@ -0,0 +1,192 @@
+//! The Nucleo-F401RE, which talks to the client over the USART2 bridged to its USB port
+//!
+//! The user button (PC13) held while the board boots clears the account lockout.
+
+use core::ptr::read_volatile;
+use defmt::info;
+use defmt_rtt as _;
+use embassy_stm32::adc::{Adc, SampleTime};
+use embassy_stm32::flash::Flash;
+use embassy_stm32::gpio::{Input, Pull};
+use embassy_stm32::interrupt;
+use embassy_stm32::peripherals;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::usart::{self, Config, Uart, UartRx, UartTx};
+use embassy_time::{Delay, Instant, Timer};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use protocol::link::{LinkConfig, Parity, StopBits};
+use server_core::config::LINK_CONFIG;
+use server_core::transport::Clock;
+use sha2::{Digest, Sha256};
+
+use super::{Board, FlashRegion, Parts};
+
+#[cfg(feature = "baud_negotiation")]
+use embassy_stm32::interrupt::Interrupt;
+
+/// Where the F401's 96 bit unique device ID is mapped
+const UID: *const [u8; 12] = 0x1FFF_7A10 as *const [u8; 12];
+
+/// Number of temperature sensor readings hashed into the seed
+const NOISE_SAMPLES: usize = 1024;
+
+/// The Nucleo-F401RE clocked at 84MHz
+pub struct Nucleo;
+
+impl Board for Nucleo {
+    type Link = Usart2;
+    type Clock = EmbassyClock;
+    type Flash = Flash<'static>;
+
+    /// The 128K sector before the lockout records, `memory-nucleo.x` ends the firmware before it
+    const DEVICE_SECRET: FlashRegion = FlashRegion {
+        offset: 0x4_0000,
+        size: 0x2_0000,
+    };
+    /// The last 128K sector of the F401RE's flash
+    const LOCKOUT: FlashRegion = FlashRegion {
+        offset: 0x6_0000,
+        size: 0x2_0000,
+    };
+
+    fn init() -> Parts<Self> {
+        let mut rcc_config: embassy_stm32::rcc::Config = Default::default();
+        rcc_config.sys_ck = Some(Hertz::mhz(84));
+        let mut board_config: embassy_stm32::Config = Default::default();
+        board_config.rcc = rcc_config;
+        let p = embassy_stm32::init(board_config);
+        info!("Initialised peripherals.");
+
+        let seed = seed(Adc::new(p.ADC1, &mut Delay));
+        info!("Gathered entropy from the temperature sensor.");
+
+        let config = uart_config(&LINK_CONFIG);
+        let irq = interrupt::take!(USART2);
+        let (tx, rx) =
+            Uart::new(p.USART2, p.PA3, p.PA2, irq, p.DMA1_CH6, p.DMA1_CH5, config).split();
+        info!("Configured USART2.");
+
+        let button = Input::new(p.PC13, Pull::None);
+        Parts {
+            link: Usart2,
+            tx: Tx(tx),
+            rx: Rx(rx),
+            clock: EmbassyClock,
+            flash: Flash::new(p.FLASH),
+            clear_lockout: button.is_low(),
+            seed,
+        }
+    }
+}
+
+/// Hash the noise in the temperature sensor's readings together with the board's unique ID
+///
+/// The F401 has no hardware RNG. Sampled as briefly as the ADC allows the lowest bits of every
+/// reading are noise, and the timer's count at each one adds jitter, so a thousand of them hold far
+/// more entropy than the seed. The unique ID keeps two boards apart even if the readings are poor.
+fn seed(mut adc: Adc<'_, peripherals::ADC1>) -> [u8; 32] {
+    adc.set_sample_time(SampleTime::Cycles3);
+    let mut temperature = adc.enable_temperature();
+
+    let mut hasher = Sha256::new();
+    // SAFETY: the unique ID is always mapped and never changes
+    hasher.update(unsafe { read_volatile(UID) });
+    for _ in 0..NOISE_SAMPLES {
+        hasher.update(adc.read_internal(&mut temperature).to_le_bytes());
+        hasher.update(Instant::now().as_ticks().to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// embassy's time driver, which counts from boot
+#[derive(Clone, Copy)]
+pub struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now_micros(&self) -> u64 {
+        Instant::now().as_micros()
+    }
+
+    async fn wait_until_ms(&self, deadline_ms: u64) {
+        Timer::at(Instant::from_millis(deadline_ms)).await
+    }
+}
+
+/// The USART configuration for `link`, always with eight data bits
+fn uart_config(link: &LinkConfig) -> Config {
+    let mut config = Config::default();
+    config.baudrate = link.baudrate;
+    config.parity = match link.parity {
+        Parity::None => usart::Parity::ParityNone,
+        Parity::Even => usart::Parity::ParityEven,
+        Parity::Odd => usart::Parity::ParityOdd,
+    };
+    config.stop_bits = match link.stop_bits {
+        StopBits::One => usart::StopBits::STOP1,
+        StopBits::Two => usart::StopBits::STOP2,
+    };
+    config
+}
+
+/// The half of USART2 which sends to the client
+pub struct Tx(pub UartTx<'static, peripherals::USART2, peripherals::DMA1_CH6>);
+
+/// The half of USART2 which receives from the client
+///
+/// Reads return once the line goes idle rather than waiting to fill the buffer.
+pub struct Rx(pub UartRx<'static, peripherals::USART2, peripherals::DMA1_CH5>);
+
+impl ErrorType for Tx {
+    type Error = ErrorKind;
+}
+
+impl Write for Tx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        self.0.write(buf).await.map_err(error_kind)?;
+        Ok(buf.len())
+    }
+}
+
+impl ErrorType for Rx {
+    type Error = ErrorKind;
+}
+
+impl Read for Rx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        self.0.read_until_idle(buf).await.map_err(error_kind)
+    }
+}
+
+/// Noise on the line shows up as invalid data, the UART can't say more about anything else
+fn error_kind(e: usart::Error) -> ErrorKind {
+    match e {
+        usart::Error::Framing | usart::Error::Noise | usart::Error::Parity => {
+            ErrorKind::InvalidData
+        }
+        _ => ErrorKind::Other,
+    }
+}
+
+/// USART2, which goes over the USB port on the Nucleo board
+pub struct Usart2;
+
+impl server_core::transport::Link for Usart2 {
+    type Tx = Tx;
+    type Rx = Rx;
+
+    /// Open USART2 again with a different configuration
+    #[cfg(feature = "baud_negotiation")]
+    unsafe fn reopen(&mut self, link: &LinkConfig) -> (Tx, Rx) {
+        let (tx, rx) = Uart::new(
+            peripherals::USART2::steal(),
+            peripherals::PA3::steal(),
+            peripherals::PA2::steal(),
+            interrupt::USART2::steal(),
+            peripherals::DMA1_CH6::steal(),
+            peripherals::DMA1_CH5::steal(),
+            uart_config(link),
+        )
+        .split();
+        (Tx(tx), Rx(rx))
+    }
+}
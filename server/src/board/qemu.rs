@@ -0,0 +1,289 @@
+//! The MPS2 board with a Cortex-M4 (AN386) as emulated by `qemu-system-arm -machine mps2-an386`
+//!
+//! The client talks to the firmware over UART0, which QEMU connects to the host with `-serial`.
+//! Time is counted with SysTick and the flash is held in RAM, so nothing survives a restart. The
+//! logs go out over semihosting, which is also where the seed comes from.
+
+use core::cell::Cell;
+use core::ptr::{read_volatile, write_volatile};
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m_rt::exception;
+use cortex_m_semihosting::syscall;
+use defmt::info;
+use defmt_semihosting as _;
+use embassy_futures::yield_now;
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use embedded_storage::nor_flash::{self, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use server_core::config::LINK_CONFIG;
+use server_core::transport::{Clock, Link};
+
+use super::{Board, FlashRegion, Parts};
+
+#[cfg(feature = "baud_negotiation")]
+use protocol::link::LinkConfig;
+
+/// The core and the peripherals run at 25MHz
+const SYSCLK_HZ: u32 = 25_000_000;
+
+/// The CMSDK UART0 registers
+const UART0_DATA: *mut u32 = 0x4000_4000 as *mut u32;
+const UART0_STATE: *mut u32 = 0x4000_4004 as *mut u32;
+const UART0_CTRL: *mut u32 = 0x4000_4008 as *mut u32;
+const UART0_BAUDDIV: *mut u32 = 0x4000_4010 as *mut u32;
+
+const STATE_TX_FULL: u32 = 1 << 0;
+const STATE_RX_FULL: u32 = 1 << 1;
+const STATE_RX_OVERRUN: u32 = 1 << 3;
+const CTRL_TX_ENABLE: u32 = 1 << 0;
+const CTRL_RX_ENABLE: u32 = 1 << 1;
+
+const SECTOR: usize = 4096;
+
+/// The host's entropy, opened over semihosting as the emulated board has none of its own
+const URANDOM: &[u8] = b"/dev/urandom\0";
+/// Semihosting's mode for opening a file to read bytes from
+const OPEN_READ_BINARY: usize = 1;
+
+/// The emulated MPS2 board
+pub struct Qemu;
+
+impl Board for Qemu {
+    type Link = Uart0;
+    type Clock = SysTickClock;
+    type Flash = RamFlash;
+
+    const DEVICE_SECRET: FlashRegion = FlashRegion {
+        offset: 0,
+        size: SECTOR as u32,
+    };
+    const LOCKOUT: FlashRegion = FlashRegion {
+        offset: SECTOR as u32,
+        size: SECTOR as u32,
+    };
+
+    fn init() -> Parts<Self> {
+        let Some(mut core) = cortex_m::Peripherals::take() else {
+            defmt::panic!("The core peripherals were already taken");
+        };
+        core.SYST.set_clock_source(SystClkSource::Core);
+        core.SYST.set_reload(SYSCLK_HZ / 1000 - 1);
+        core.SYST.clear_current();
+        core.SYST.enable_interrupt();
+        core.SYST.enable_counter();
+        info!("Started SysTick.");
+
+        // SAFETY: nothing else touches UART0, and there are no halves around yet
+        let (tx, rx) = unsafe { Uart0::open(LINK_CONFIG.baudrate) };
+        info!("Configured UART0.");
+
+        let Some(flash) = cortex_m::singleton!(: [u8; 2 * SECTOR] = [0xFF; 2 * SECTOR]) else {
+            defmt::panic!("The flash was already set up");
+        };
+
+        Parts {
+            link: Uart0,
+            tx,
+            rx,
+            clock: SysTickClock,
+            flash: RamFlash(flash),
+            // the lockout is gone after every restart anyway
+            clear_lockout: false,
+            seed: seed(),
+        }
+    }
+}
+
+/// Read the seed from the host's /dev/urandom
+fn seed() -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    // SAFETY: the path is NUL terminated and its length leaves the NUL out, and the seed outlives
+    // the read into it
+    unsafe {
+        let fd = syscall!(OPEN, URANDOM.as_ptr(), OPEN_READ_BINARY, URANDOM.len() - 1) as isize;
+        if fd < 0 {
+            defmt::panic!("Failed to open /dev/urandom on the host");
+        }
+        // the host reports how many bytes it didn't read
+        let unread = syscall!(READ, fd, seed.as_mut_ptr(), seed.len());
+        syscall!(CLOSE, fd);
+        if unread != 0 {
+            defmt::panic!("Failed to read the seed from /dev/urandom on the host");
+        }
+    }
+    seed
+}
+
+/// UART0, polled rather than driven by interrupts
+///
+/// The CMSDK UART is always 8N1, only the baud rate of a `LinkConfig` applies.
+pub struct Uart0;
+
+impl Uart0 {
+    /// # Safety
+    ///
+    /// The halves from the last time UART0 was opened must not be used again.
+    unsafe fn open(baudrate: u32) -> (Tx, Rx) {
+        write_volatile(UART0_CTRL, 0);
+        write_volatile(UART0_BAUDDIV, SYSCLK_HZ / baudrate);
+        write_volatile(UART0_STATE, STATE_RX_OVERRUN);
+        write_volatile(UART0_CTRL, CTRL_TX_ENABLE | CTRL_RX_ENABLE);
+        (Tx(()), Rx(()))
+    }
+}
+
+impl Link for Uart0 {
+    type Tx = Tx;
+    type Rx = Rx;
+
+    /// Change UART0's baud rate
+    #[cfg(feature = "baud_negotiation")]
+    unsafe fn reopen(&mut self, link: &LinkConfig) -> (Tx, Rx) {
+        Uart0::open(link.baudrate)
+    }
+}
+
+fn state() -> u32 {
+    // SAFETY: reading the state has no side effects
+    unsafe { read_volatile(UART0_STATE) }
+}
+
+/// The half of UART0 which sends to the client
+pub struct Tx(());
+
+/// The half of UART0 which receives from the client
+///
+/// Reads return whatever has arrived once there is at least a byte.
+pub struct Rx(());
+
+impl ErrorType for Tx {
+    type Error = ErrorKind;
+}
+
+impl Write for Tx {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        for &byte in buf {
+            while state() & STATE_TX_FULL != 0 {
+                yield_now().await;
+            }
+            // SAFETY: only this half writes to the data register
+            unsafe { write_volatile(UART0_DATA, byte as u32) };
+        }
+        Ok(buf.len())
+    }
+}
+
+impl ErrorType for Rx {
+    type Error = ErrorKind;
+}
+
+impl Read for Rx {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while state() & STATE_RX_FULL == 0 {
+            yield_now().await;
+        }
+
+        let mut count = 0;
+        while count < buf.len() && state() & STATE_RX_FULL != 0 {
+            // SAFETY: only this half reads from the data register
+            buf[count] = unsafe { read_volatile(UART0_DATA) } as u8;
+            count += 1;
+        }
+
+        // a byte was lost, whatever frame it was part of won't parse
+        if state() & STATE_RX_OVERRUN != 0 {
+            // SAFETY: writing the flag back clears it and nothing else
+            unsafe { write_volatile(UART0_STATE, STATE_RX_OVERRUN) };
+            return Err(ErrorKind::InvalidData);
+        }
+        Ok(count)
+    }
+}
+
+/// Milliseconds since SysTick was started
+static MILLIS: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let millis = MILLIS.borrow(cs);
+        millis.set(millis.get() + 1);
+    });
+}
+
+/// SysTick, which only counts whole milliseconds
+#[derive(Clone, Copy)]
+pub struct SysTickClock;
+
+impl Clock for SysTickClock {
+    fn now_micros(&self) -> u64 {
+        cortex_m::interrupt::free(|cs| MILLIS.borrow(cs).get()) * 1000
+    }
+
+    /// There is no timer queue on this board, so this polls
+    async fn wait_until_ms(&self, deadline_ms: u64) {
+        while self.now_ms() < deadline_ms {
+            yield_now().await;
+        }
+    }
+}
+
+/// Flash held in RAM, which like the real thing can only clear bits
+pub struct RamFlash(&'static mut [u8; 2 * SECTOR]);
+
+#[derive(Debug)]
+pub struct FlashError(NorFlashErrorKind);
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl nor_flash::ErrorType for RamFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let start = offset as usize;
+        let Some(data) = self.0.get(start..start + bytes.len()) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        let Some(data) = self.0.get_mut(from as usize..to as usize) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        data.fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let start = offset as usize;
+        let Some(data) = self.0.get_mut(start..start + bytes.len()) else {
+            return Err(FlashError(NorFlashErrorKind::OutOfBounds));
+        };
+        for (d, b) in data.iter_mut().zip(bytes) {
+            *d &= *b;
+        }
+        Ok(())
+    }
+}
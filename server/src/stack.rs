@@ -0,0 +1,52 @@
+//! How much RAM the firmware uses, the statics and the deepest the stack has been
+//!
+//! The stack grows down from the top of RAM towards the statics, which cortex-m-rt ends at
+//! `__sheap`. Everything in between is painted by `server_core::stack`, which measures the peak.
+
+/// Left unpainted below the stack pointer, for the painting itself
+const MARGIN: usize = 64;
+
+extern "C" {
+    static __sdata: u32;
+    static __sheap: u32;
+    static _stack_start: u32;
+}
+
+fn statics_start() -> usize {
+    // SAFETY: only the address is taken, nothing is read through it
+    unsafe { &__sdata as *const u32 as usize }
+}
+
+fn stack_limit() -> usize {
+    // SAFETY: as above
+    unsafe { &__sheap as *const u32 as usize }
+}
+
+fn stack_top() -> usize {
+    // SAFETY: as above
+    unsafe { &_stack_start as *const u32 as usize }
+}
+
+/// Bytes taken by `.data`, `.bss` and `.uninit`, which includes the executor's task storage
+pub fn static_ram() -> usize {
+    stack_limit() - statics_start()
+}
+
+/// Bytes the stack can grow to before it runs into the statics
+pub fn available() -> usize {
+    stack_top() - stack_limit()
+}
+
+/// Paint the unused stack, forgetting the peak measured so far
+#[inline(never)]
+pub fn paint() {
+    let sp = cortex_m::register::msp::read() as usize;
+    // SAFETY: everything between the statics and the stack pointer is unused
+    unsafe { server_core::stack::paint(stack_limit() as *mut u32, (sp - MARGIN) as *mut u32) }
+}
+
+/// The deepest the stack has been since it was last painted, in bytes
+pub fn peak() -> usize {
+    // SAFETY: everything between the statics and the top of RAM can be read
+    unsafe { server_core::stack::peak(stack_limit() as *const u32, stack_top() as *const u32) }
+}
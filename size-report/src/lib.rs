@@ -0,0 +1,281 @@
+//! Attributes the flash taken by the server's firmware to the crates and functions it came from
+//!
+//! Only `.text` and `.rodata` are counted. Bytes in them which no symbol covers, like padding and
+//! constants the compiler merged, are reported as `[unattributed]` so the totals still add up to
+//! the size of the sections.
+
+use anyhow::Result;
+use object::{Object, ObjectSection, ObjectSymbol};
+use rustc_demangle::demangle;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+
+/// Crates reported together under the name they all start with, e.g. `embassy_stm32`
+const FAMILIES: &[&str] = &["embassy", "defmt", "cortex_m"];
+
+/// The bytes of a section which no symbol covers
+pub const UNATTRIBUTED: &str = "[unattributed]";
+
+/// Symbols without a Rust path, from C, assembly or the linker
+pub const NO_CRATE: &str = "[no crate]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Rodata,
+}
+
+impl Section {
+    fn of(name: &str) -> Option<Section> {
+        let is = |prefix: &str| name == prefix || name.starts_with(&format!("{prefix}."));
+        if is(".text") {
+            Some(Section::Text)
+        } else if is(".rodata") {
+            Some(Section::Rodata)
+        } else {
+            None
+        }
+    }
+}
+
+/// A function or constant in the firmware
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// Demangled, without the hash on the end
+    pub name: String,
+    pub section: Section,
+    pub size: u64,
+}
+
+/// Every sized symbol in the `.text` and `.rodata` of `elf`, plus what they leave uncovered
+pub fn symbols(elf: &[u8]) -> Result<Vec<Symbol>> {
+    let file = object::File::parse(elf)?;
+
+    let mut symbols = Vec::new();
+    // aliases of a symbol cover the same bytes, only the first one is counted
+    let mut seen = HashSet::new();
+    for symbol in file.symbols() {
+        let Some(index) = symbol.section_index() else {
+            continue;
+        };
+        let Some(section) = Section::of(file.section_by_index(index)?.name()?) else {
+            continue;
+        };
+        // the lowest bit of a function's address only marks it as Thumb code
+        if symbol.size() == 0 || !seen.insert((symbol.address() & !1, symbol.size())) {
+            continue;
+        }
+        symbols.push(Symbol {
+            name: format!("{:#}", demangle(symbol.name()?)),
+            section,
+            size: symbol.size(),
+        });
+    }
+
+    for section in [Section::Text, Section::Rodata] {
+        let mut size = 0;
+        for s in file.sections() {
+            if Section::of(s.name()?) == Some(section) {
+                size += s.size();
+            }
+        }
+        let covered: u64 = symbols
+            .iter()
+            .filter(|symbol| symbol.section == section)
+            .map(|symbol| symbol.size)
+            .sum();
+        if size > covered {
+            symbols.push(Symbol {
+                name: UNATTRIBUTED.into(),
+                section,
+                size: size - covered,
+            });
+        }
+    }
+    Ok(symbols)
+}
+
+/// The crate a demangled symbol comes from, or the family of crates it belongs to
+///
+/// Trait methods like `<aucpace::Server as Trait>::f` belong to the crate of the type they're
+/// implemented on.
+pub fn crate_of(name: &str) -> &str {
+    if name == UNATTRIBUTED {
+        return UNATTRIBUTED;
+    }
+    let mut path = name.trim_start_matches(['<', '&', '*', '(', '[']);
+    for qualifier in ["mut ", "const ", "dyn "] {
+        path = path.strip_prefix(qualifier).unwrap_or(path);
+    }
+    let krate = match path.find("::") {
+        Some(end) if path[..end].chars().all(|c| c.is_alphanumeric() || c == '_') => &path[..end],
+        _ => return NO_CRATE,
+    };
+    FAMILIES
+        .iter()
+        .find(|family| {
+            krate
+                .strip_prefix(**family)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+        })
+        .copied()
+        .unwrap_or(krate)
+}
+
+/// Bytes a crate or a function takes in each section
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Row {
+    pub name: String,
+    pub text: u64,
+    pub rodata: u64,
+}
+
+impl Row {
+    pub fn total(&self) -> u64 {
+        self.text + self.rodata
+    }
+}
+
+fn rows<'a>(symbols: &'a [Symbol], key: impl Fn(&'a Symbol) -> &'a str) -> Vec<Row> {
+    let mut rows = BTreeMap::<&str, Row>::new();
+    for symbol in symbols {
+        let name = key(symbol);
+        let row = rows.entry(name).or_insert_with(|| Row {
+            name: name.into(),
+            ..Row::default()
+        });
+        match symbol.section {
+            Section::Text => row.text += symbol.size,
+            Section::Rodata => row.rodata += symbol.size,
+        }
+    }
+    let mut rows: Vec<Row> = rows.into_values().collect();
+    rows.sort_by(|a, b| b.total().cmp(&a.total()).then_with(|| a.name.cmp(&b.name)));
+    rows
+}
+
+/// The size of each crate, largest first
+pub fn by_crate(symbols: &[Symbol]) -> Vec<Row> {
+    rows(symbols, |symbol| crate_of(&symbol.name))
+}
+
+/// The size of each function and constant, largest first
+///
+/// Monomorphisations with the same name, like closures, are added together.
+pub fn by_function(symbols: &[Symbol]) -> Vec<Row> {
+    rows(symbols, |symbol| &symbol.name)
+}
+
+/// Every row added together
+pub fn total(rows: &[Row]) -> Row {
+    Row {
+        name: "total".into(),
+        text: rows.iter().map(|row| row.text).sum(),
+        rodata: rows.iter().map(|row| row.rodata).sum(),
+    }
+}
+
+/// How much a crate or a function grew or shrank between two builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub name: String,
+    pub old: u64,
+    pub new: u64,
+}
+
+impl Change {
+    pub fn delta(&self) -> i64 {
+        self.new as i64 - self.old as i64
+    }
+}
+
+/// Everything whose size changed from `old` to `new`, the biggest changes first
+pub fn diff(old: &[Row], new: &[Row]) -> Vec<Change> {
+    let mut changes = BTreeMap::<&str, Change>::new();
+    for (rows, is_new) in [(old, false), (new, true)] {
+        for row in rows {
+            let change = changes.entry(&row.name).or_insert_with(|| Change {
+                name: row.name.clone(),
+                old: 0,
+                new: 0,
+            });
+            if is_new {
+                change.new = row.total();
+            } else {
+                change.old = row.total();
+            }
+        }
+    }
+    let mut changes: Vec<Change> = changes
+        .into_values()
+        .filter(|change| change.delta() != 0)
+        .collect();
+    changes.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    changes
+}
+
+/// Something which can be written out as a line of a table
+pub trait Table {
+    const HEADERS: &'static [&'static str];
+
+    /// The name first, then the numbers
+    fn cells(&self) -> Vec<String>;
+}
+
+impl Table for Row {
+    const HEADERS: &'static [&'static str] = &["name", "text", "rodata", "total"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.text.to_string(),
+            self.rodata.to_string(),
+            self.total().to_string(),
+        ]
+    }
+}
+
+impl Table for Change {
+    const HEADERS: &'static [&'static str] = &["name", "old", "new", "delta"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.old.to_string(),
+            self.new.to_string(),
+            format!("{:+}", self.delta()),
+        ]
+    }
+}
+
+pub fn write_csv<T: Table>(out: impl Write, rows: &[T]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(T::HEADERS)?;
+    for row in rows {
+        writer.write_record(row.cells())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `rows` as a Markdown table, with the names as code so generics survive rendering
+pub fn write_markdown<T: Table>(mut out: impl Write, rows: &[T]) -> io::Result<()> {
+    writeln!(out, "| {} |", T::HEADERS.join(" | "))?;
+    let align: Vec<&str> = T::HEADERS
+        .iter()
+        .enumerate()
+        .map(|(i, _)| if i == 0 { ":--" } else { "--:" })
+        .collect();
+    writeln!(out, "| {} |", align.join(" | "))?;
+    for row in rows {
+        let mut cells = row.cells();
+        cells[0] = format!("`{}`", cells[0].replace('|', "\\|").replace('`', "'"));
+        writeln!(out, "| {} |", cells.join(" | "))?;
+    }
+    Ok(())
+}
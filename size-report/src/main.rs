@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use size_report::{Row, Table};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The server's ELF, e.g. target/thumbv7em-none-eabihf/server/server
+    elf: PathBuf,
+
+    /// An earlier build of the server to compare against
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Break the report down by function rather than by crate
+    #[arg(long)]
+    functions: bool,
+
+    /// Only show this many of the largest rows, or of the largest changes
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// How to write the report
+    #[arg(long, value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Markdown,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let report = |path: &Path| -> Result<Vec<Row>> {
+        let elf = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let symbols = size_report::symbols(&elf)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(if args.functions {
+            size_report::by_function(&symbols)
+        } else {
+            size_report::by_crate(&symbols)
+        })
+    };
+
+    let new = report(&args.elf)?;
+    match &args.baseline {
+        Some(baseline) => {
+            let old = report(baseline)?;
+            let mut changes = size_report::diff(&old, &new);
+            truncate(&mut changes, args.top);
+            changes.push(size_report::Change {
+                name: "total".into(),
+                old: size_report::total(&old).total(),
+                new: size_report::total(&new).total(),
+            });
+            write(args.format, &changes)
+        }
+        None => {
+            let total = size_report::total(&new);
+            let mut rows = new;
+            truncate(&mut rows, args.top);
+            rows.push(total);
+            write(args.format, &rows)
+        }
+    }
+}
+
+fn truncate<T>(rows: &mut Vec<T>, top: Option<usize>) {
+    if let Some(top) = top {
+        rows.truncate(top);
+    }
+}
+
+fn write<T: Table>(format: Format, rows: &[T]) -> Result<()> {
+    let stdout = io::stdout().lock();
+    match format {
+        Format::Csv => size_report::write_csv(stdout, rows)?,
+        Format::Markdown => size_report::write_markdown(stdout, rows)?,
+    }
+    io::stdout().flush()?;
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use object::write::{Object, Symbol as ElfSymbol, SymbolSection};
+use object::{
+    Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+};
+use size_report::{Change, Row, NO_CRATE, UNATTRIBUTED};
+
+/// A relocatable ELF with a function from each of two crates, a constant and some padding
+fn elf() -> Vec<u8> {
+    let mut elf = Object::new(BinaryFormat::Elf, Architecture::Arm, Endianness::Little);
+    let text = elf.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+    let rodata = elf.add_section(Vec::new(), b".rodata".to_vec(), SectionKind::ReadOnlyData);
+
+    let mut add = |name: &str, section, size: u64, kind| {
+        let offset = elf.append_section_data(section, &vec![0; size as usize], 4);
+        elf.add_symbol(ElfSymbol {
+            name: name.as_bytes().to_vec(),
+            value: offset,
+            size,
+            kind,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(section),
+            flags: SymbolFlags::None,
+        });
+    };
+    add(
+        "_ZN7aucpace6server5begin17h0123456789abcdefE",
+        text,
+        100,
+        SymbolKind::Text,
+    );
+    add(
+        "_ZN4sha26sha2568compress17h0123456789abcdefE",
+        text,
+        60,
+        SymbolKind::Text,
+    );
+    add(
+        "_ZN4sha26sha2561K17h0123456789abcdefE",
+        rodata,
+        256,
+        SymbolKind::Data,
+    );
+    elf.append_section_data(text, &[0; 8], 4);
+    elf.write().unwrap()
+}
+
+fn row(name: &str, text: u64, rodata: u64) -> Row {
+    Row {
+        name: name.into(),
+        text,
+        rodata,
+    }
+}
+
+#[test]
+fn symbols_are_attributed_to_their_crates() {
+    let symbols = size_report::symbols(&elf()).unwrap();
+    assert_eq!(
+        size_report::by_crate(&symbols),
+        [
+            row("sha2", 60, 256),
+            row("aucpace", 100, 0),
+            row(UNATTRIBUTED, 8, 0)
+        ]
+    );
+}
+
+#[test]
+fn functions_keep_their_paths() {
+    let symbols = size_report::symbols(&elf()).unwrap();
+    let functions = size_report::by_function(&symbols);
+    assert_eq!(functions[0], row("sha2::sha256::K", 0, 256));
+    assert_eq!(functions[1], row("aucpace::server::begin", 100, 0));
+    assert_eq!(size_report::total(&functions).total(), 424);
+}
+
+#[test]
+fn trait_impls_belong_to_the_type() {
+    let crate_of = size_report::crate_of;
+    assert_eq!(
+        crate_of("<aucpace::server::AuCPaceServer<R> as core::fmt::Debug>::fmt"),
+        "aucpace"
+    );
+    assert_eq!(
+        crate_of("<&mut postcard::ser::Serializer<F> as serde::Serializer>::serialize_u32"),
+        "postcard"
+    );
+    assert_eq!(
+        crate_of("curve25519_dalek::scalar::Scalar::reduce"),
+        "curve25519_dalek"
+    );
+    assert_eq!(crate_of("memcpy"), NO_CRATE);
+}
+
+#[test]
+fn crate_families_are_reported_together() {
+    let crate_of = size_report::crate_of;
+    assert_eq!(crate_of("embassy_stm32::usart::Uart::new"), "embassy");
+    assert_eq!(crate_of("embassy_executor::raw::Executor::poll"), "embassy");
+    assert_eq!(crate_of("defmt_rtt::write"), "defmt");
+    assert_eq!(crate_of("cortex_m_rt::Reset"), "cortex_m");
+}
+
+#[test]
+fn diffs_show_the_biggest_changes_first() {
+    let old = [row("aucpace", 100, 0), row("sha2", 60, 256)];
+    let new = [
+        row("aucpace", 110, 0),
+        row("postcard", 40, 4),
+        row("sha2", 60, 256),
+    ];
+    let changes = size_report::diff(&old, &new);
+    assert_eq!(
+        changes,
+        [
+            Change {
+                name: "postcard".into(),
+                old: 0,
+                new: 44
+            },
+            Change {
+                name: "aucpace".into(),
+                old: 100,
+                new: 110
+            },
+        ]
+    );
+}
+
+#[test]
+fn markdown_names_are_code() {
+    let mut out = Vec::new();
+    size_report::write_markdown(&mut out, &[row("core::fmt::<impl Fn(u8) -> u8>", 2, 0)]).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.ends_with("| `core::fmt::<impl Fn(u8) -> u8>` | 2 | 0 | 2 |\n"));
+}